@@ -0,0 +1,308 @@
+use crate::compute::{Household, TaxData, compute_taxes};
+use crate::request::Request;
+use anyhow::{Result, anyhow};
+use log::{debug, trace, warn};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Calculator answering requests in the format of the ESTV tax calculator.
+pub trait CalculatorBackend {
+    async fn calculate(&self, request: &Request) -> Result<Value>;
+}
+
+/// Failure of a calculation that may succeed when retried: a server error or
+/// a timeout.
+#[derive(Debug)]
+pub struct TransientError(pub String);
+
+impl fmt::Display for TransientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransientError {}
+
+fn transient_if_timeout(e: reqwest::Error) -> anyhow::Error {
+    if e.is_timeout() {
+        TransientError(format!("Request timed out: {e}")).into()
+    } else {
+        e.into()
+    }
+}
+
+/// The ESTV tax calculator, queried over HTTP.
+pub struct EstvBackend {
+    client: Client,
+    base_url: String,
+}
+
+impl EstvBackend {
+    pub const DEFAULT_BASE_URL: &str = "https://swisstaxcalculator.estv.admin.ch";
+    const PATH: &str =
+        "/delegate/ost-integration/v1/lg-proxy/operation/c3b67379_ESTV/API_calculateSimpleTaxes";
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    pub fn new(base_url: &str) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(Self::TIMEOUT).build()?,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        })
+    }
+}
+
+impl CalculatorBackend for EstvBackend {
+    async fn calculate(&self, request: &Request) -> Result<Value> {
+        let url = format!("{}{}", self.base_url, Self::PATH);
+        let res = self
+            .client
+            .post(url)
+            .json(request)
+            .send()
+            .await
+            .map_err(transient_if_timeout)?;
+        trace!("Status: {:?}", res.status());
+        if res.status().is_server_error() {
+            return Err(TransientError(format!("Server error: {}", res.status())).into());
+        }
+        let res = res.error_for_status()?;
+
+        let bytes = res.bytes().await.map_err(transient_if_timeout)?;
+        trace!("Received {} bytes", bytes.len());
+
+        trace!("Parsing as JSON");
+        let json = serde_json::from_slice(bytes.deref())?;
+
+        Ok(json)
+    }
+}
+
+/// Backend caching the responses of another backend on disk, keyed by the
+/// digest of the requests. Caching is disabled without a directory.
+pub struct CachedBackend<B> {
+    inner: B,
+    dir: Option<PathBuf>,
+}
+
+impl<B> CachedBackend<B> {
+    pub fn new(inner: B, dir: Option<PathBuf>) -> Self {
+        Self { inner, dir }
+    }
+}
+
+impl<B: CalculatorBackend> CalculatorBackend for CachedBackend<B> {
+    async fn calculate(&self, request: &Request) -> Result<Value> {
+        let Some(dir) = &self.dir else {
+            return self.inner.calculate(request).await;
+        };
+
+        let path = dir.join(format!("{}.json", request.digest()));
+        match File::open(&path) {
+            Ok(file) => match serde_json::from_reader(BufReader::new(file)) {
+                Ok(response) => {
+                    trace!("Cache hit: {path:?}");
+                    return Ok(response);
+                }
+                Err(e) => warn!("Ignoring invalid cache entry {path:?}: {e:?}"),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => warn!("Failed to read cache entry {path:?}: {e:?}"),
+        }
+
+        let response = self.inner.calculate(request).await?;
+        fs::create_dir_all(dir)?;
+        // Write to a unique temporary file first, so that concurrent requests
+        // never read or leave behind a truncated entry.
+        static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let tmp_path = path.with_extension(format!(
+            "json.{}-{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.flush()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(response)
+    }
+}
+
+/// Backend spacing out the requests to another backend by a minimum interval,
+/// and retrying those failing with a [`TransientError`].
+pub struct ThrottledBackend<B> {
+    inner: B,
+    interval: Duration,
+    retries: u32,
+    backoff: Duration,
+    /// Earliest time of the next request.
+    next: Mutex<Instant>,
+}
+
+impl<B> ThrottledBackend<B> {
+    pub fn new(inner: B, interval: Duration, retries: u32, backoff: Duration) -> Self {
+        Self {
+            inner,
+            interval,
+            retries,
+            backoff,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl<B: CalculatorBackend> CalculatorBackend for ThrottledBackend<B> {
+    async fn calculate(&self, request: &Request) -> Result<Value> {
+        let wait = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let slot = (*next).max(now);
+            *next = slot + self.interval;
+            slot - now
+        };
+        tokio::time::sleep(wait).await;
+        calculate_with_retry(&self.inner, request, self.retries, self.backoff).await
+    }
+}
+
+/// Calculates the request, retrying it up to the given number of times while
+/// it fails with a [`TransientError`]. The delay before the first retry is
+/// doubled at each subsequent retry.
+pub async fn calculate_with_retry(
+    backend: &impl CalculatorBackend,
+    request: &Request,
+    retries: u32,
+    backoff: Duration,
+) -> Result<Value> {
+    let mut delay = backoff;
+    let mut attempt = 0;
+    loop {
+        trace!("Evaluating {request:?}");
+        match backend.calculate(request).await {
+            Ok(response) => return Ok(response),
+            Err(e) if attempt < retries && e.is::<TransientError>() => {
+                debug!("Request failed, retrying in {delay:?}: {e:?}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// In-process stand-in for the ESTV calculator, answering with our own
+/// evaluation. Fields that we don't compute are null.
+pub struct LocalBackend {
+    data_dir: PathBuf,
+    data: Mutex<HashMap<u32, TaxData>>,
+}
+
+impl LocalBackend {
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            data: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl CalculatorBackend for LocalBackend {
+    async fn calculate(&self, request: &Request) -> Result<Value> {
+        let mut data = self.data.lock().unwrap();
+        let data = match data.entry(request.tax_year) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(TaxData::load(&self.data_dir, request.tax_year)?),
+        };
+
+        let household = Household::from_request(request, data)?;
+        let location = data
+            .location(request.tax_location_id)
+            .ok_or_else(|| anyhow!("Unknown tax location: {}", request.tax_location_id))?;
+        let taxes = compute_taxes(data, &household)?;
+
+        Ok(json!({
+            "response": {
+                "Location": location,
+                "FortuneSimpleTaxCanton": taxes.fortune_simple_tax_canton,
+                "FortuneSimpleTaxCity": taxes.fortune_simple_tax_city,
+                "FortuneTaxCanton": taxes.fortune_tax_canton,
+                "FortuneTaxChurch": null,
+                "FortuneTaxCity": taxes.fortune_tax_city,
+                "IncomeSimpleTaxCanton": taxes.income_simple_tax_canton,
+                "IncomeSimpleTaxCity": taxes.income_simple_tax_city,
+                "IncomeSimpleTaxFed": taxes.income_simple_tax_fed,
+                "IncomeTaxCanton": taxes.income_tax_canton,
+                "IncomeTaxChurch": null,
+                "IncomeTaxCity": taxes.income_tax_city,
+                "IncomeTaxFed": taxes.income_tax_fed,
+                "PersonalTax": null,
+                "TaxCredit": null,
+                "TotalNetTax": taxes.total_tax,
+                "TotalTax": taxes.total_tax,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::CivilStatus;
+    use crate::testdata::{YEAR, make_data_dir};
+
+    /// Backend counting the requests it answers.
+    struct CountingBackend(LocalBackend, AtomicUsize);
+
+    impl CalculatorBackend for CountingBackend {
+        async fn calculate(&self, request: &Request) -> Result<Value> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.calculate(request).await
+        }
+    }
+
+    #[test]
+    fn cached_backend() {
+        let dir = make_data_dir();
+        let cache_dir = dir.path().join("cache");
+        let backend = CachedBackend::new(
+            CountingBackend(LocalBackend::new(dir.path()), AtomicUsize::new(0)),
+            Some(cache_dir.clone()),
+        );
+        let single = Request::builder(YEAR, 100).income(50_000).build().unwrap();
+        let married = Request::builder(YEAR, 100)
+            .civil_status(CivilStatus::Married)
+            .income(50_000)
+            .build()
+            .unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let first = rt.block_on(backend.calculate(&single)).unwrap();
+        let second = rt.block_on(backend.calculate(&single)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(backend.inner.1.load(Ordering::Relaxed), 1);
+        assert!(cache_dir.join(format!("{}.json", single.digest())).exists());
+
+        rt.block_on(backend.calculate(&married)).unwrap();
+        assert_eq!(backend.inner.1.load(Ordering::Relaxed), 2);
+
+        // A corrupted entry is recomputed and rewritten.
+        let path = cache_dir.join(format!("{}.json", married.digest()));
+        fs::write(&path, "{\"response\":").unwrap();
+        let third = rt.block_on(backend.calculate(&married)).unwrap();
+        assert_eq!(backend.inner.1.load(Ordering::Relaxed), 3);
+        let cached: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(cached, third);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,531 @@
+use crate::calculator::EstvBackend;
+use crate::compute::Household;
+use crate::couple::HouseholdPair;
+use crate::db::Compression;
+use crate::examples::{FetchOptions, Relationship, Tolerance, WriteMode};
+use crate::generate::{Generator, Household as GeneratedHousehold, Strategy};
+use anyhow::{Error, Result, anyhow};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use taxes_common::color::{ColorPreset, ColorRange, ColorScale};
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Directory containing the input tax data.
+    #[arg(long, global = true, default_value = "data")]
+    pub data_dir: PathBuf,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Check that the input data files can be parsed.
+    Validate {
+        #[command(flatten)]
+        years: YearArgs,
+    },
+    /// Fetch test examples from the ESTV calculator.
+    FetchExamples {
+        #[command(flatten)]
+        years: YearArgs,
+        #[command(flatten)]
+        cantons: CantonArgs,
+        #[command(flatten)]
+        backend: BackendArgs,
+        #[command(flatten)]
+        generator: GeneratorArgs,
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// Locate the income thresholds around mismatching test examples, by
+    /// querying the calculator.
+    Probe {
+        #[command(flatten)]
+        years: YearArgs,
+        #[command(flatten)]
+        cantons: CantonArgs,
+        /// Maximum number of mismatching examples to probe.
+        #[arg(long, default_value_t = 10)]
+        max_probes: usize,
+        /// Minimum delay between requests to the ESTV calculator, in
+        /// milliseconds. Requests failing with a server error or a timeout
+        /// are retried with exponential backoff.
+        #[arg(long, default_value_t = 250)]
+        interval_ms: u64,
+        #[command(flatten)]
+        backend: BackendArgs,
+    },
+    /// Compare the test examples against our own evaluation.
+    Check {
+        #[command(flatten)]
+        years: YearArgs,
+        #[command(flatten)]
+        cantons: CantonArgs,
+        /// Write the accuracy report as JSON to this file.
+        #[arg(long)]
+        json: Option<PathBuf>,
+        /// Write the accuracy report as HTML to this file.
+        #[arg(long)]
+        html: Option<PathBuf>,
+        /// Previous JSON accuracy report, failing if the accuracy regressed
+        /// compared to it.
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+        /// Tolerance of a field, as "FIELD=ABS[,REL]", e.g.
+        /// "income_tax_canton=1,0.001" accepts differences up to 1 CHF or up to
+        /// 0.1%. The "*" field applies to all the other fields. Can be
+        /// repeated.
+        #[arg(long)]
+        tolerance: Vec<FieldTolerance>,
+        /// Overwrite existing reports.
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Build the database of tax tables used by the wasm viewer.
+    BuildDb {
+        #[command(flatten)]
+        years: YearArgs,
+        #[command(flatten)]
+        cantons: CantonArgs,
+        /// Output directory, defaults to the data directory.
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+        /// Overwrite an existing database.
+        #[arg(long)]
+        overwrite: bool,
+        /// Delta-encode bracket starts and store rates exact to 0.001 as
+        /// integers.
+        #[arg(long)]
+        compact: bool,
+        /// Compression of the database.
+        #[arg(long, value_enum, default_value_t = Compression::None)]
+        compression: Compression,
+        /// Date when the input data was fetched, in seconds since the Unix
+        /// epoch. It's recorded in the database header. Defaults to
+        /// SOURCE_DATE_EPOCH if set, or else to the latest modification time
+        /// of the input rates and scales.
+        #[arg(long)]
+        source_date: Option<u64>,
+    },
+    /// Print the contents of a database built with build-db.
+    DbInfo {
+        /// Database file, defaults to tables.db in the data directory.
+        #[arg(long)]
+        db: Option<PathBuf>,
+        #[command(flatten)]
+        cantons: CantonArgs,
+    },
+    /// Plot tax rates and marriage penalties.
+    Plot {
+        #[command(flatten)]
+        years: PlotYearArgs,
+        #[command(flatten)]
+        cantons: CantonArgs,
+        #[command(flatten)]
+        colors: ColorArgs,
+        /// Levels of the contour lines on the heatmaps of absolute
+        /// differences, in CHF (comma-separated). Each level is drawn at both
+        /// signs, besides the break-even line.
+        #[arg(long, value_delimiter = ',')]
+        contours: Vec<f64>,
+        /// Levels of the contour lines on the heatmaps of relative
+        /// differences, in percent (comma-separated).
+        #[arg(long, value_delimiter = ',')]
+        percent_contours: Vec<f64>,
+        /// Also plot the marriage penalties of the federal, cantonal and
+        /// communal income taxes and their total, each in its own panel.
+        /// Communal taxes use the median multiplier of the canton.
+        #[arg(long)]
+        layers: bool,
+        /// Also plot the marriage penalties against the gross salaries of
+        /// both partners, after social contributions and the professional,
+        /// two-earner and married couple deductions.
+        #[arg(long)]
+        gross: bool,
+        /// Also plot the tax differences between pairs of households
+        /// (comma-separated), such as `cohabiting-2-0:married-2`: a married
+        /// couple with N children is written `married-N`, and unmarried
+        /// partners with N and M children `cohabiting-N-M`. Partners with
+        /// children get the single parent tariff. Pairs with children need
+        /// --gross, to apply the child deductions.
+        #[arg(long, value_delimiter = ',')]
+        pairs: Vec<HouseholdPair>,
+        /// Output directory for the plots.
+        #[arg(long, default_value = "plots")]
+        output_dir: PathBuf,
+        /// Overwrite existing plots.
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Print tables of tax examples for each scale.
+    Report {
+        #[command(flatten)]
+        years: ReportYearArgs,
+        #[command(flatten)]
+        cantons: CantonArgs,
+    },
+    /// Compute the taxes of a household, or of a CSV file of households.
+    Compute {
+        /// CSV file of households, with the columns "year", "canton",
+        /// "municipality", "relationship", "taxable_income_canton",
+        /// "taxable_income_fed" and "taxable_fortune".
+        #[arg(long)]
+        input: Option<PathBuf>,
+        #[command(flatten)]
+        household: HouseholdArgs,
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+        /// Output file, defaults to the standard output.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+pub struct YearArgs {
+    /// Years to process, as a comma-separated list of years or ranges (e.g.
+    /// "2010-2015,2020").
+    #[arg(long, default_value = "2010-2025")]
+    pub years: Years,
+}
+
+#[derive(Args)]
+pub struct PlotYearArgs {
+    /// Years to process, as a comma-separated list of years or ranges (e.g.
+    /// "2010-2015,2020").
+    #[arg(long, default_value = "2010,2015,2020,2025")]
+    pub years: Years,
+}
+
+#[derive(Args)]
+pub struct ReportYearArgs {
+    /// Years to process, as a comma-separated list of years or ranges (e.g.
+    /// "2010-2015,2020").
+    #[arg(long, default_value = "2010,2025")]
+    pub years: Years,
+}
+
+#[derive(Args)]
+pub struct CantonArgs {
+    /// Restrict processing to these cantons (comma-separated), e.g. "ZH,VD".
+    #[arg(long, value_delimiter = ',')]
+    pub cantons: Vec<String>,
+}
+
+impl CantonArgs {
+    pub fn contains(&self, canton: &str) -> bool {
+        self.cantons.is_empty() || self.cantons.iter().any(|c| c == canton)
+    }
+}
+
+#[derive(Args)]
+pub struct ColorArgs {
+    /// Palette of the marriage penalty heatmaps.
+    #[arg(long, value_enum, default_value_t = ColorPreset::Classic)]
+    pub palette: ColorPreset,
+    /// Break-points of the absolute differences in CHF, as increasing
+    /// positive numbers (comma-separated). The first one bounds the neutral
+    /// colour. Defaults to break-points suited for cantonal taxes.
+    #[arg(long, value_delimiter = ',')]
+    pub breaks: Vec<f64>,
+    /// Break-points of the relative differences in percent (comma-separated).
+    #[arg(long, value_delimiter = ',')]
+    pub percent_breaks: Vec<f64>,
+    /// Range of the colour scales.
+    #[arg(long, value_enum, default_value_t = ColorRange::Fixed)]
+    pub color_range: ColorRange,
+}
+
+impl ColorArgs {
+    pub fn to_color_scale(&self, percent: bool) -> Result<ColorScale> {
+        let breaks = if percent {
+            &self.percent_breaks
+        } else {
+            &self.breaks
+        };
+        if breaks.is_empty() {
+            Ok(ColorScale::new(self.palette, percent, self.color_range))
+        } else {
+            ColorScale::with_breaks(self.palette, breaks, self.color_range).map_err(Error::msg)
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct BackendArgs {
+    /// Calculator to query.
+    #[arg(long, value_enum, default_value_t = Backend::Estv)]
+    pub backend: Backend,
+    /// Base URL of the ESTV calculator.
+    #[arg(long, default_value = EstvBackend::DEFAULT_BASE_URL)]
+    pub base_url: String,
+}
+
+#[derive(Args)]
+pub struct GeneratorArgs {
+    /// Seed of the random generator, chosen randomly if not given.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Strategies to generate incomes (comma-separated).
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "uniform")]
+    pub strategies: Vec<Strategy>,
+    /// Number of municipalities sampled in each canton.
+    #[arg(long, default_value_t = 2)]
+    pub locations_per_canton: usize,
+    /// Generate examples for all municipalities.
+    #[arg(long, conflicts_with = "locations_per_canton")]
+    pub all_municipalities: bool,
+    /// Number of samples per municipality for the randomized strategies.
+    #[arg(long, default_value_t = 1)]
+    pub samples: usize,
+    /// Household shapes to generate (comma-separated).
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "single,married"
+    )]
+    pub households: Vec<GeneratedHousehold>,
+    /// Sample the confessions of the taxpayers, to cover the church tax.
+    #[arg(long)]
+    pub church: bool,
+}
+
+impl GeneratorArgs {
+    pub fn to_generator(&self) -> Generator {
+        Generator {
+            seed: self.seed.unwrap_or_else(rand::random),
+            strategies: self.strategies.clone(),
+            locations_per_canton: if self.all_municipalities {
+                None
+            } else {
+                Some(self.locations_per_canton)
+            },
+            samples: self.samples,
+            households: self.households.clone(),
+            church: self.church,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct FetchArgs {
+    /// Overwrite existing test files.
+    #[arg(long, conflicts_with_all = ["append", "resume"])]
+    pub overwrite: bool,
+    /// Add new examples to existing test files, keeping the old ones.
+    #[arg(long, conflicts_with = "resume")]
+    pub append: bool,
+    /// Fetch the examples missing from existing test files, with the
+    /// generators recorded in them.
+    #[arg(long)]
+    pub resume: bool,
+    /// Maximum number of concurrent requests.
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+    /// Number of retries of a request failing with a server error or a
+    /// timeout, with exponential backoff.
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+    /// Directory of the cache of ESTV responses, defaults to "cache" in the
+    /// data directory.
+    #[arg(long)]
+    pub cache_dir: Option<PathBuf>,
+    /// Don't cache the responses.
+    #[arg(long, conflicts_with = "cache_dir")]
+    pub no_cache: bool,
+}
+
+impl FetchArgs {
+    pub fn to_options(&self) -> FetchOptions {
+        let mode = if self.overwrite {
+            WriteMode::Overwrite
+        } else if self.append {
+            WriteMode::Append
+        } else if self.resume {
+            WriteMode::Resume
+        } else {
+            WriteMode::CreateNew
+        };
+        FetchOptions {
+            mode,
+            concurrency: self.concurrency,
+            retries: self.retries,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the cache directory of the ESTV responses, if caching is
+    /// enabled.
+    pub fn cache_dir(&self, data_dir: &Path) -> Option<PathBuf> {
+        if self.no_cache {
+            return None;
+        }
+        let dir = match &self.cache_dir {
+            Some(dir) => dir.clone(),
+            None => data_dir.join("cache"),
+        };
+        Some(dir.join("estv"))
+    }
+}
+
+#[derive(Args)]
+pub struct HouseholdArgs {
+    /// Tax year.
+    #[arg(long, required_unless_present = "input", conflicts_with = "input")]
+    pub year: Option<u32>,
+    /// Canton abbreviation, e.g. "ZH".
+    #[arg(long, conflicts_with = "input")]
+    pub canton: Option<String>,
+    /// Municipality name or BFS id.
+    #[arg(long, conflicts_with = "input")]
+    pub municipality: Option<String>,
+    /// Civil status.
+    #[arg(
+        long,
+        value_enum,
+        required_unless_present = "input",
+        conflicts_with = "input"
+    )]
+    pub relationship: Option<Relationship>,
+    /// Taxable income for the cantonal and communal taxes.
+    #[arg(long, required_unless_present = "input", conflicts_with = "input")]
+    pub income_canton: Option<f64>,
+    /// Taxable income for the federal tax, defaults to the cantonal one.
+    #[arg(long, conflicts_with = "input")]
+    pub income_fed: Option<f64>,
+    /// Taxable wealth.
+    #[arg(long, conflicts_with = "input")]
+    pub fortune: Option<f64>,
+}
+
+impl HouseholdArgs {
+    pub fn to_household(&self) -> Result<Household> {
+        Ok(Household {
+            year: self.year.ok_or_else(|| anyhow!("Missing year"))?,
+            canton: self.canton.clone(),
+            municipality: self.municipality.clone(),
+            relationship: self
+                .relationship
+                .ok_or_else(|| anyhow!("Missing relationship"))?,
+            taxable_income_canton: self
+                .income_canton
+                .ok_or_else(|| anyhow!("Missing cantonal income"))?,
+            taxable_income_fed: self.income_fed,
+            taxable_fortune: self.fortune,
+        })
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Backend {
+    /// The ESTV tax calculator.
+    Estv,
+    /// Our own evaluation, useful to test the pipeline offline.
+    Local,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldTolerance {
+    pub field: String,
+    pub tolerance: Tolerance,
+}
+
+impl FromStr for FieldTolerance {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid tolerance, expected FIELD=ABS[,REL]: {s}"))?;
+        let (abs, rel) = value.split_once(',').unwrap_or((value, "0"));
+        Ok(FieldTolerance {
+            field: field.trim().to_owned(),
+            tolerance: Tolerance {
+                abs: abs.trim().parse()?,
+                rel: rel.trim().parse()?,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Years(Vec<u32>);
+
+impl Years {
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl FromStr for Years {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut years = Vec::new();
+        for item in s.split(',') {
+            let item = item.trim();
+            match item.split_once('-') {
+                Some((start, end)) => {
+                    let start: u32 = start.trim().parse()?;
+                    let end: u32 = end.trim().parse()?;
+                    if start > end {
+                        return Err(anyhow!("Invalid year range: {item}"));
+                    }
+                    years.extend(start..=end);
+                }
+                None => years.push(item.parse()?),
+            }
+        }
+        years.sort_unstable();
+        years.dedup();
+        Ok(Years(years))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_years() {
+        assert_eq!(Years::from_str("2020").unwrap().0, vec![2020]);
+        assert_eq!(
+            Years::from_str("2010-2012").unwrap().0,
+            vec![2010, 2011, 2012]
+        );
+        assert_eq!(
+            Years::from_str("2025, 2010-2011,2011").unwrap().0,
+            vec![2010, 2011, 2025]
+        );
+        assert!(Years::from_str("2012-2010").is_err());
+        assert!(Years::from_str("twenty").is_err());
+    }
+
+    #[test]
+    fn parse_tolerance() {
+        let t = FieldTolerance::from_str("income_tax_canton=1,0.001").unwrap();
+        assert_eq!(t.field, "income_tax_canton");
+        assert_eq!(
+            t.tolerance,
+            Tolerance {
+                abs: 1.0,
+                rel: 0.001
+            }
+        );
+        let t = FieldTolerance::from_str("*=2").unwrap();
+        assert_eq!(t.tolerance, Tolerance { abs: 2.0, rel: 0.0 });
+        assert!(FieldTolerance::from_str("income_tax_canton").is_err());
+    }
+}
@@ -0,0 +1,414 @@
+use crate::cli::OutputFormat;
+use crate::examples::Relationship;
+use crate::load::{CantonalScale, cantonal_rates, cantonal_scales, load_rates, load_scales};
+use crate::request::Request;
+use crate::schema::{Location, Rate, Rates, TaxType};
+use anyhow::{Result, anyhow};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::Write;
+use std::path::Path;
+
+/// Household for which to compute taxes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Household {
+    pub year: u32,
+    /// Canton abbreviation, e.g. "ZH". Optional if a municipality is given.
+    pub canton: Option<String>,
+    /// Municipality name or BFS id. Communal taxes are only computed if given.
+    pub municipality: Option<String>,
+    pub relationship: Relationship,
+    pub taxable_income_canton: f64,
+    /// Defaults to the cantonal taxable income.
+    pub taxable_income_fed: Option<f64>,
+    pub taxable_fortune: Option<f64>,
+}
+
+impl Household {
+    /// Converts a request in the format of the ESTV calculator.
+    pub fn from_request(request: &Request, data: &TaxData) -> Result<Self> {
+        if !request.is_modeled() {
+            return Err(anyhow!(
+                "Households with children or unmarried partners aren't modeled: {request:?}"
+            ));
+        }
+        let location = data
+            .location(request.tax_location_id)
+            .ok_or_else(|| anyhow!("Unknown tax location: {}", request.tax_location_id))?;
+        Ok(Household {
+            year: request.tax_year,
+            canton: Some(location.canton.clone()),
+            municipality: Some(location.bfs_id.to_string()),
+            relationship: request.relationship(),
+            taxable_income_canton: request.taxable_income_canton.into(),
+            taxable_income_fed: Some(request.taxable_income_fed.into()),
+            taxable_fortune: Some(request.taxable_fortune.into()),
+        })
+    }
+}
+
+/// Breakdown of the taxes of a household, following the fields of the ESTV
+/// calculator. Fields that cannot be computed are left empty.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxBreakdown {
+    pub year: u32,
+    pub canton: String,
+    pub municipality: Option<String>,
+    pub relationship: Relationship,
+    pub taxable_income_canton: f64,
+    pub taxable_income_fed: f64,
+    pub taxable_fortune: Option<f64>,
+    pub income_simple_tax_canton: f64,
+    pub income_simple_tax_city: Option<f64>,
+    pub income_simple_tax_fed: f64,
+    pub income_tax_canton: f64,
+    pub income_tax_city: Option<f64>,
+    pub income_tax_fed: f64,
+    pub fortune_simple_tax_canton: Option<f64>,
+    pub fortune_simple_tax_city: Option<f64>,
+    pub fortune_tax_canton: Option<f64>,
+    pub fortune_tax_city: Option<f64>,
+    pub total_tax: f64,
+}
+
+/// Tax data for a given year, needed to compute the taxes of households.
+pub struct TaxData {
+    rates: Rates,
+    cantonal_rates: HashMap<String, f64>,
+    income_scales: HashMap<String, CantonalScale>,
+    fortune_scales: HashMap<String, CantonalScale>,
+}
+
+impl TaxData {
+    pub fn load(data_dir: &Path, year: u32) -> Result<Self> {
+        debug!("Loading tax data for {year}");
+        let rates = load_rates(data_dir, year)?;
+        let scales = load_scales(data_dir, year)?;
+        Ok(TaxData {
+            cantonal_rates: cantonal_rates(&rates, year)?,
+            rates,
+            income_scales: cantonal_scales(&scales, TaxType::EinkommensSteuer)?,
+            fortune_scales: cantonal_scales(&scales, TaxType::VermoegensSteuer)?,
+        })
+    }
+
+    pub fn income_scale(&self, canton: &str) -> Option<&CantonalScale> {
+        self.income_scales.get(canton)
+    }
+
+    pub fn location(&self, tax_location_id: u32) -> Option<&Location> {
+        self.rates
+            .response
+            .iter()
+            .map(|rate| &rate.location)
+            .find(|location| location.tax_location_id == tax_location_id)
+    }
+
+    fn find_municipality(&self, canton: Option<&str>, municipality: &str) -> Result<&Rate> {
+        let bfs_id = municipality.parse::<u32>().ok();
+        self.rates
+            .response
+            .iter()
+            .filter(|rate| canton.is_none_or(|canton| rate.location.canton == canton))
+            .find(|rate| match bfs_id {
+                Some(bfs_id) => rate.location.bfs_id == bfs_id,
+                None => Self::matches_name(&rate.location, municipality),
+            })
+            .ok_or_else(|| anyhow!("Unknown municipality: {municipality}"))
+    }
+
+    fn matches_name(location: &Location, name: &str) -> bool {
+        location.bfs_name.eq_ignore_ascii_case(name) || location.city.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Computes the taxes of a household, given the tax data for its year.
+pub fn compute_taxes(data: &TaxData, household: &Household) -> Result<TaxBreakdown> {
+    let municipality = match &household.municipality {
+        Some(municipality) => {
+            Some(data.find_municipality(household.canton.as_deref(), municipality)?)
+        }
+        None => None,
+    };
+    let canton = match (&household.canton, municipality) {
+        (_, Some(rate)) => rate.location.canton.clone(),
+        (Some(canton), None) => canton.clone(),
+        (None, None) => return Err(anyhow!("Either a canton or a municipality is required")),
+    };
+    if canton == "VS" {
+        warn!("Taxes in VS aren't computed accurately");
+    }
+
+    let relationship = household.relationship;
+    let taxable_income_fed = household
+        .taxable_income_fed
+        .unwrap_or(household.taxable_income_canton);
+
+    let income_scale = data
+        .income_scales
+        .get(&canton)
+        .ok_or_else(|| anyhow!("No income tax scale for canton: {canton}"))?;
+    let cantonal_rate = data
+        .cantonal_rates
+        .get(&canton)
+        .ok_or_else(|| anyhow!("No income tax rate for canton: {canton}"))?;
+    let federal_scale = data
+        .income_scales
+        .get("CH")
+        .ok_or_else(|| anyhow!("No federal income tax scale"))?;
+
+    let income_simple_tax_canton = income_scale
+        .eval(relationship, household.taxable_income_canton)
+        .round();
+    let income_tax_canton = (income_simple_tax_canton * cantonal_rate / 100.0).round();
+    let income_simple_tax_fed = federal_scale.eval(relationship, taxable_income_fed).round();
+    let income_tax_fed = income_simple_tax_fed;
+
+    // The communal tax applies the multiplier of the municipality to the
+    // cantonal simple tax, except in VS where municipalities have their own
+    // scale.
+    let communal = municipality.filter(|_| canton != "VS");
+    let income_simple_tax_city = communal.map(|_| income_simple_tax_canton);
+    let income_tax_city =
+        communal.map(|rate| (income_simple_tax_canton * rate.income_rate_city / 100.0).round());
+
+    let fortune_simple_tax_canton = match household.taxable_fortune {
+        Some(fortune) => match data.fortune_scales.get(&canton) {
+            Some(scale) => Some(scale.eval(relationship, fortune).round()),
+            None => {
+                warn!("No fortune tax scale for canton: {canton}");
+                None
+            }
+        },
+        None => None,
+    };
+    // The cantonal multiplier is the same for all municipalities of a canton.
+    let cantonal_entry = municipality.or_else(|| {
+        data.rates
+            .response
+            .iter()
+            .find(|rate| rate.location.canton == canton)
+    });
+    let fortune_tax_canton = match (fortune_simple_tax_canton, cantonal_entry) {
+        (Some(simple_tax), Some(rate)) => {
+            Some((simple_tax * rate.fortune_rate_canton / 100.0).round())
+        }
+        _ => None,
+    };
+    let fortune_simple_tax_city = municipality.and(fortune_simple_tax_canton);
+    let fortune_tax_city = match (fortune_simple_tax_city, municipality) {
+        (Some(simple_tax), Some(rate)) => {
+            Some((simple_tax * rate.fortune_rate_city / 100.0).round())
+        }
+        _ => None,
+    };
+
+    let total_tax = income_tax_canton
+        + income_tax_fed
+        + income_tax_city.unwrap_or_default()
+        + fortune_tax_canton.unwrap_or_default()
+        + fortune_tax_city.unwrap_or_default();
+
+    Ok(TaxBreakdown {
+        year: household.year,
+        canton,
+        municipality: municipality.map(|rate| rate.location.bfs_name.clone()),
+        relationship,
+        taxable_income_canton: household.taxable_income_canton,
+        taxable_income_fed,
+        taxable_fortune: household.taxable_fortune,
+        income_simple_tax_canton,
+        income_simple_tax_city,
+        income_simple_tax_fed,
+        income_tax_canton,
+        income_tax_city,
+        income_tax_fed,
+        fortune_simple_tax_canton,
+        fortune_simple_tax_city,
+        fortune_tax_canton,
+        fortune_tax_city,
+        total_tax,
+    })
+}
+
+/// Computes the taxes of the given households, loading the tax data of each
+/// year only once.
+pub fn compute_all_taxes(
+    data_dir: &Path,
+    households: impl IntoIterator<Item = Household>,
+) -> Result<Vec<TaxBreakdown>> {
+    let mut data: HashMap<u32, TaxData> = HashMap::new();
+    households
+        .into_iter()
+        .enumerate()
+        .map(|(i, household)| {
+            let data = match data.entry(household.year) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(TaxData::load(data_dir, household.year)?),
+            };
+            compute_taxes(data, &household).map_err(|e| anyhow!("Household #{}: {e}", i + 1))
+        })
+        .try_collect()
+}
+
+pub fn read_households(path: &Path) -> Result<Vec<Household>> {
+    Ok(csv::Reader::from_path(path)?.deserialize().try_collect()?)
+}
+
+pub fn write_breakdowns(
+    breakdowns: &[TaxBreakdown],
+    format: OutputFormat,
+    writer: impl Write,
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => serde_json::to_writer_pretty(writer, breakdowns)?,
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for breakdown in breakdowns {
+                writer.serialize(breakdown)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testdata::{YEAR, make_data_dir};
+    use std::fs;
+
+    fn household(
+        canton: Option<&str>,
+        municipality: Option<&str>,
+        relationship: Relationship,
+        income: f64,
+    ) -> Household {
+        Household {
+            year: YEAR,
+            canton: canton.map(Into::into),
+            municipality: municipality.map(Into::into),
+            relationship,
+            taxable_income_canton: income,
+            taxable_income_fed: None,
+            taxable_fortune: Some(500_000.0),
+        }
+    }
+
+    #[test]
+    fn compute_breakdown() {
+        let dir = make_data_dir();
+        let data = TaxData::load(dir.path(), YEAR).unwrap();
+
+        // The canton is derived from the municipality.
+        let taxes = compute_taxes(
+            &data,
+            &household(None, Some("Winterthur"), Relationship::Single, 30_000.0),
+        )
+        .unwrap();
+        assert_eq!(taxes.canton, "ZH");
+        assert_eq!(taxes.municipality.as_deref(), Some("Winterthur"));
+        assert_eq!(taxes.taxable_income_fed, 30_000.0);
+        assert_eq!(taxes.income_simple_tax_canton, 820.0);
+        assert_eq!(taxes.income_simple_tax_city, Some(820.0));
+        assert_eq!(taxes.income_tax_canton, 820.0);
+        assert_eq!(taxes.income_tax_city, Some(1025.0));
+        assert_eq!(taxes.income_tax_fed, 150.0);
+        assert_eq!(taxes.fortune_tax_canton, Some(500.0));
+        assert_eq!(taxes.fortune_tax_city, Some(250.0));
+        assert_eq!(taxes.total_tax, 2745.0);
+
+        // Without a municipality, only the cantonal and federal taxes are
+        // computed.
+        let taxes = compute_taxes(
+            &data,
+            &household(Some("ZH"), None, Relationship::Married, 80_000.0),
+        )
+        .unwrap();
+        assert_eq!(taxes.income_tax_canton, 5620.0);
+        assert_eq!(taxes.income_simple_tax_city, None);
+        assert_eq!(taxes.income_tax_city, None);
+        assert_eq!(taxes.income_tax_fed, 900.0);
+        assert_eq!(taxes.fortune_tax_city, None);
+        assert_eq!(taxes.total_tax, 7020.0);
+
+        // Splitting and the corrected cantonal multiplier of VD.
+        let taxes = compute_taxes(
+            &data,
+            &household(None, Some("5586"), Relationship::Married, 80_000.0),
+        )
+        .unwrap();
+        assert_eq!(taxes.income_simple_tax_canton, 5450.0);
+        assert_eq!(taxes.income_tax_canton, 8152.0);
+        assert_eq!(taxes.income_tax_city, Some(4278.0));
+        assert_eq!(taxes.fortune_tax_canton, None);
+
+        assert!(compute_taxes(&data, &household(None, None, Relationship::Single, 0.0)).is_err());
+        assert!(
+            compute_taxes(
+                &data,
+                &household(Some("VD"), Some("Winterthur"), Relationship::Single, 0.0)
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn households_csv() {
+        let dir = make_data_dir();
+        let input = dir.path().join("households.csv");
+        fs::write(
+            &input,
+            "year,canton,municipality,relationship,taxable_income_canton,taxable_income_fed,taxable_fortune\n\
+             2025,ZH,Winterthur,single,30000,,500000\n\
+             2025,ZH,,married,80000,80000,\n",
+        )
+        .unwrap();
+        let households = read_households(&input).unwrap();
+        assert_eq!(households.len(), 2);
+        assert_eq!(households[0].municipality.as_deref(), Some("Winterthur"));
+        assert_eq!(households[0].taxable_income_fed, None);
+        assert_eq!(households[1].municipality, None);
+        assert_eq!(households[1].relationship, Relationship::Married);
+        assert_eq!(households[1].taxable_fortune, None);
+
+        let breakdowns = compute_all_taxes(dir.path(), households).unwrap();
+        assert_eq!(breakdowns[0].total_tax, 2745.0);
+        assert_eq!(breakdowns[1].total_tax, 6520.0);
+
+        let mut csv = Vec::new();
+        write_breakdowns(&breakdowns, OutputFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("year,canton,municipality,relationship,"));
+        assert!(
+            lines[1].starts_with("2025,ZH,Winterthur,single,30000.0,30000.0,500000.0,820.0,820.0,")
+        );
+        assert!(lines[2].ends_with(",6520.0"));
+
+        let mut json = Vec::new();
+        write_breakdowns(&breakdowns, OutputFormat::Json, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[0]["income_tax_city"], 1025.0);
+        assert_eq!(json[1]["municipality"], serde_json::Value::Null);
+        assert_eq!(json[1]["relationship"], "married");
+
+        // Errors mention the household that failed.
+        let error = compute_all_taxes(
+            dir.path(),
+            [household(
+                Some("ZH"),
+                Some("Nowhere"),
+                Relationship::Single,
+                0.0,
+            )],
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("Household #1:"));
+    }
+}
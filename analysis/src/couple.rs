@@ -0,0 +1,184 @@
+use anyhow::{Result, anyhow, bail};
+use log::info;
+use std::fmt;
+use std::str::FromStr;
+
+/// How a couple is taxed, with the children it supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Couple {
+    /// Married couple, taxed jointly with its children.
+    Married { children: u32 },
+    /// Unmarried partners, each taxed separately with the children they
+    /// support. A partner with children gets the single parent tariff.
+    Cohabiting { children_1: u32, children_2: u32 },
+}
+
+impl Couple {
+    /// Returns the number of children supported by the couple.
+    pub fn children(self) -> u32 {
+        match self {
+            Couple::Married { children } => children,
+            Couple::Cohabiting {
+                children_1,
+                children_2,
+            } => children_1 + children_2,
+        }
+    }
+}
+
+impl fmt::Display for Couple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Couple::Married { children: 0 } => write!(f, "married"),
+            Couple::Married { children } => write!(f, "married-{children}"),
+            Couple::Cohabiting {
+                children_1: 0,
+                children_2: 0,
+            } => write!(f, "cohabiting"),
+            Couple::Cohabiting {
+                children_1,
+                children_2,
+            } => write!(f, "cohabiting-{children_1}-{children_2}"),
+        }
+    }
+}
+
+impl FromStr for Couple {
+    type Err = anyhow::Error;
+
+    /// Parses `married[-N]` or `cohabiting[-N-M]`, where the numbers are the
+    /// children of the couple, or of each partner.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        match parts[..] {
+            ["married"] => Ok(Couple::Married { children: 0 }),
+            ["married", children] => Ok(Couple::Married {
+                children: children.parse()?,
+            }),
+            ["cohabiting"] => Ok(Couple::Cohabiting {
+                children_1: 0,
+                children_2: 0,
+            }),
+            ["cohabiting", children_1, children_2] => Ok(Couple::Cohabiting {
+                children_1: children_1.parse()?,
+                children_2: children_2.parse()?,
+            }),
+            _ => Err(anyhow!(
+                "Invalid couple, expected married[-N] or cohabiting[-N-M]: {s}"
+            )),
+        }
+    }
+}
+
+/// Two households compared on the heatmaps. Positive differences mean that
+/// the second one pays less taxes than the first one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HouseholdPair {
+    pub first: Couple,
+    pub second: Couple,
+}
+
+impl Default for HouseholdPair {
+    /// Two singles against a married couple, without children.
+    fn default() -> Self {
+        HouseholdPair {
+            first: Couple::Cohabiting {
+                children_1: 0,
+                children_2: 0,
+            },
+            second: Couple::Married { children: 0 },
+        }
+    }
+}
+
+impl HouseholdPair {
+    pub fn has_children(self) -> bool {
+        self.first.children() > 0 || self.second.children() > 0
+    }
+}
+
+/// Returns the pairs to plot besides the default one, which is always
+/// plotted, without duplicates. Pairs with children need gross salaries, as
+/// taxable incomes already include the child deductions.
+pub fn extra_pairs(pairs: &[HouseholdPair], gross: bool) -> Result<Vec<HouseholdPair>> {
+    let mut extra = Vec::new();
+    for &pair in pairs {
+        if pair.has_children() && !gross {
+            bail!("Comparing households with children needs --gross: {pair}");
+        }
+        if pair == HouseholdPair::default() {
+            info!("The {pair} pair is already plotted");
+        } else if !extra.contains(&pair) {
+            extra.push(pair);
+        }
+    }
+    Ok(extra)
+}
+
+impl fmt::Display for HouseholdPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-vs-{}", self.first, self.second)
+    }
+}
+
+impl FromStr for HouseholdPair {
+    type Err = anyhow::Error;
+
+    /// Parses two couples separated by a colon, such as
+    /// `cohabiting-2-0:married-2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, second) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid household pair, expected FIRST:SECOND: {s}"))?;
+        Ok(HouseholdPair {
+            first: first.parse()?,
+            second: second.parse()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_pair() {
+        let pair: HouseholdPair = "cohabiting-2-0:married-2".parse().unwrap();
+        assert_eq!(
+            pair,
+            HouseholdPair {
+                first: Couple::Cohabiting {
+                    children_1: 2,
+                    children_2: 0,
+                },
+                second: Couple::Married { children: 2 },
+            }
+        );
+        assert_eq!(pair.to_string(), "cohabiting-2-0-vs-married-2");
+
+        let pair: HouseholdPair = "cohabiting:married".parse().unwrap();
+        assert_eq!(pair, HouseholdPair::default());
+        assert_eq!(pair.to_string(), "cohabiting-vs-married");
+
+        assert!(!pair.has_children());
+        assert!("married-2".parse::<HouseholdPair>().is_err());
+        assert!("cohabiting-1:married".parse::<HouseholdPair>().is_err());
+        assert!("divorced:married".parse::<HouseholdPair>().is_err());
+    }
+
+    #[test]
+    fn select_pairs() {
+        let parents: HouseholdPair = "cohabiting-2-0:married-2".parse().unwrap();
+        let singles: HouseholdPair = "cohabiting:cohabiting".parse().unwrap();
+        assert_eq!(
+            extra_pairs(&[HouseholdPair::default(), parents, singles, parents], true).unwrap(),
+            [parents, singles]
+        );
+        assert_eq!(
+            extra_pairs(&[HouseholdPair::default(), singles], false).unwrap(),
+            [singles]
+        );
+        assert!(extra_pairs(&[parents], false).is_err());
+        assert!(extra_pairs(&["married-1:married".parse().unwrap()], false).is_err());
+    }
+}
@@ -0,0 +1,893 @@
+use crate::cli::CantonArgs;
+use crate::load::{CantonalScale, cantonal_rates, cantonal_scales, load_rates, load_scales};
+use crate::schema::{Rate, TaxType};
+use crate::table::{CompactTable, Table};
+use anyhow::{Result, anyhow};
+use blazinterner::{Arena, Interned};
+use clap::ValueEnum;
+use log::info;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use taxes_common::compact::Number;
+
+/// Magic bytes at the start of `tables.db`.
+pub const DB_MAGIC: [u8; 8] = *b"CHTAXDB\0";
+/// Version of the `tables.db` format. It must be bumped whenever the
+/// serialized layout changes, and the wasm loader taught to migrate or reject
+/// older versions.
+pub const DB_VERSION: u32 = 3;
+
+/// Time within which loading a database should stay, well below a frame at
+/// 60 Hz.
+pub const DECODE_BUDGET: Duration = Duration::from_millis(4);
+
+/// Incomes at which tables are compared when verifying a database, in
+/// addition to their bracket starts.
+const VERIFY_INCOMES: [f64; 12] = [
+    0.0,
+    1.0,
+    10_000.0,
+    25_000.0,
+    50_000.0,
+    75_000.0,
+    100_000.0,
+    150_000.0,
+    250_000.0,
+    500_000.0,
+    1_000_000.0,
+    10_000_000.0,
+];
+
+/// The `tables.db` file consists of the [`DB_MAGIC`] bytes, the [`DB_VERSION`]
+/// as 4 little-endian bytes, this header and the database, both encoded with
+/// postcard.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DbHeader {
+    /// Date when the input data was fetched, in seconds since the Unix epoch.
+    pub source_date: u64,
+    pub years: Vec<u32>,
+    pub cantons: Vec<String>,
+    pub encoding: DbEncoding,
+    /// CRC-32 of the encoded database, after compression.
+    pub checksum: u32,
+}
+
+/// Encoding of the database in `tables.db`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbEncoding {
+    /// Whether tables and rates use the [compact](CompactDatabase) encoding.
+    pub compact: bool,
+    pub compression: Compression,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Raw deflate stream, which has a small pure-Rust decoder.
+    Deflate,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Database {
+    #[serde(skip)]
+    source_date: u64,
+    arena_scale: Arena<InternedCantonalScale>,
+    arena_table: Arena<Table>,
+    arena_municipality: Arena<Municipality>,
+    db: BTreeMap<u32, Year>,
+}
+
+/// Returns the date when the input data was fetched, for a database built
+/// without an explicit date: `SOURCE_DATE_EPOCH` if set, as for reproducible
+/// builds, or else the latest modification time of the rates and scales of the
+/// given years.
+pub fn default_source_date(data_dir: &Path, years: impl Iterator<Item = u32>) -> Result<u64> {
+    if let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") {
+        return epoch
+            .parse()
+            .map_err(|e| anyhow!("Invalid SOURCE_DATE_EPOCH {epoch:?}: {e}"));
+    }
+    let source_date = latest_modification(data_dir, years)?;
+    info!("Dating the database with the input data: {source_date}");
+    Ok(source_date)
+}
+
+/// Returns the latest modification time of the rates and scales of the given
+/// years, in seconds since the Unix epoch.
+fn latest_modification(data_dir: &Path, years: impl Iterator<Item = u32>) -> Result<u64> {
+    let mut latest = None;
+    for year in years {
+        for name in [format!("rates-{year}.json"), format!("scales-{year}.json")] {
+            let path = data_dir.join(name);
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|e| anyhow!("Failed to read the modification time of {path:?}: {e}"))?;
+            latest = latest.max(Some(modified));
+        }
+    }
+    let latest = latest.ok_or_else(|| anyhow!("No input data to date the database"))?;
+    Ok(latest.duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+impl Database {
+    /// Builds the database of the given years and cantons, whose input data
+    /// was fetched at the given date.
+    pub fn new(
+        data_dir: &Path,
+        years: impl Iterator<Item = u32>,
+        cantons: &CantonArgs,
+        source_date: u64,
+    ) -> Result<Self> {
+        let mut db = Database {
+            source_date,
+            arena_scale: Arena::default(),
+            arena_table: Arena::default(),
+            arena_municipality: Arena::default(),
+            db: BTreeMap::new(),
+        };
+        for year in years {
+            let entries = Year::new(data_dir, year, cantons, &db)?;
+            db.db.insert(year, entries);
+        }
+        Ok(db)
+    }
+
+    /// Writes the database to `tables.db` in the given directory, and returns
+    /// the path of the written file.
+    pub fn serialize(
+        &self,
+        output_dir: &Path,
+        overwrite: bool,
+        encoding: DbEncoding,
+    ) -> Result<PathBuf> {
+        fs::create_dir_all(output_dir)?;
+        let path = output_dir.join("tables.db");
+        let mut file = if overwrite {
+            File::create(&path)?
+        } else {
+            File::create_new(&path)?
+        };
+        let body = self.encode(encoding)?;
+        let header = self.header(encoding, &body);
+        info!("Writing tables.db: {header:?}");
+
+        let mut bytes = DB_MAGIC.to_vec();
+        bytes.extend_from_slice(&DB_VERSION.to_le_bytes());
+        postcard::to_io(&header, &mut bytes)?;
+        bytes.extend_from_slice(&body);
+        file.write_all(&bytes)?;
+        Ok(path)
+    }
+
+    fn encode(&self, encoding: DbEncoding) -> Result<Vec<u8>> {
+        let body = if encoding.compact {
+            postcard::to_stdvec(&CompactDatabase::from(self))?
+        } else {
+            postcard::to_stdvec(self)?
+        };
+        Ok(compress(&body, encoding.compression))
+    }
+
+    fn decode(encoding: DbEncoding, body: &[u8]) -> Result<Self> {
+        let body = match encoding.compression {
+            Compression::None => body.into(),
+            Compression::Deflate => miniz_oxide::inflate::decompress_to_vec(body)
+                .map_err(|e| anyhow!("Failed to decompress the database: {e:?}"))?,
+        };
+        if encoding.compact {
+            postcard::from_bytes::<CompactDatabase>(&body)?.try_into()
+        } else {
+            Ok(postcard::from_bytes(&body)?)
+        }
+    }
+
+    /// Logs the encoded size of each section of the database, in the plain and
+    /// compact encodings, and the total size after compression.
+    pub fn report_sizes(&self) -> Result<()> {
+        let compact = CompactDatabase::from(self);
+        let sections = [
+            (
+                "scales",
+                postcard::to_stdvec(&self.arena_scale)?.len(),
+                postcard::to_stdvec(&compact.arena_scale)?.len(),
+            ),
+            (
+                "tables",
+                postcard::to_stdvec(&self.arena_table)?.len(),
+                postcard::to_stdvec(&compact.arena_table)?.len(),
+            ),
+            (
+                "municipalities",
+                postcard::to_stdvec(&self.arena_municipality)?.len(),
+                postcard::to_stdvec(&compact.arena_municipality)?.len(),
+            ),
+            (
+                "years",
+                postcard::to_stdvec(&self.db)?.len(),
+                postcard::to_stdvec(&compact.db)?.len(),
+            ),
+        ];
+        let plain_total: usize = sections.iter().map(|(_, plain, _)| plain).sum();
+        let compact_total: usize = sections.iter().map(|(_, _, compact)| compact).sum();
+        for (name, plain, compact) in
+            sections
+                .into_iter()
+                .chain([("total", plain_total, compact_total)])
+        {
+            info!(
+                "{name}: {plain} bytes plain, {compact} bytes compact ({:.1}% saved)",
+                savings(plain, compact)
+            );
+        }
+        for compact in [false, true] {
+            let encoding = DbEncoding {
+                compact,
+                compression: Compression::Deflate,
+            };
+            let compressed = self.encode(encoding)?.len();
+            info!(
+                "{} encoding with deflate: {compressed} bytes ({:.1}% saved)",
+                if compact { "compact" } else { "plain" },
+                savings(plain_total, compressed)
+            );
+        }
+        Ok(())
+    }
+
+    fn header(&self, encoding: DbEncoding, body: &[u8]) -> DbHeader {
+        let cantons: BTreeSet<&String> = self
+            .db
+            .values()
+            .flat_map(|year| year.cantons.keys())
+            .collect();
+        DbHeader {
+            source_date: self.source_date,
+            years: self.db.keys().copied().collect(),
+            cantons: cantons.into_iter().cloned().collect(),
+            encoding,
+            checksum: crc32fast::hash(body),
+        }
+    }
+
+    /// Loads a database written by [`serialize()`](Self::serialize), after
+    /// checking its header.
+    pub fn load(path: &Path) -> Result<(DbHeader, Self)> {
+        let bytes = fs::read(path).map_err(|e| anyhow!("Failed to read {path:?}: {e}"))?;
+        let data = bytes
+            .strip_prefix(&DB_MAGIC)
+            .ok_or_else(|| anyhow!("{path:?} isn't a tables.db file"))?;
+        let (version, data) = data
+            .split_first_chunk::<4>()
+            .ok_or_else(|| anyhow!("{path:?} is truncated"))?;
+        let version = u32::from_le_bytes(*version);
+        if version != DB_VERSION {
+            return Err(anyhow!(
+                "{path:?} has format version {version}, expected {DB_VERSION}"
+            ));
+        }
+
+        let (header, body) = postcard::take_from_bytes::<DbHeader>(data)?;
+        let checksum = crc32fast::hash(body);
+        if checksum != header.checksum {
+            return Err(anyhow!(
+                "Checksum mismatch in {path:?}: expected {:08x}, got {checksum:08x}",
+                header.checksum
+            ));
+        }
+        let mut db = Self::decode(header.encoding, body)?;
+        db.source_date = header.source_date;
+        Ok((header, db))
+    }
+
+    /// Checks that the other database contains the same entries, with tables
+    /// that evaluate identically.
+    pub fn verify(&self, other: &Database) -> Result<()> {
+        if self.db != other.db {
+            return Err(anyhow!("Mismatching years, cantons or municipalities"));
+        }
+        verify_arena(&self.arena_scale, &other.arena_scale, "scales")?;
+        verify_arena(&self.arena_table, &other.arena_table, "tables")?;
+        verify_arena(
+            &self.arena_municipality,
+            &other.arena_municipality,
+            "municipalities",
+        )?;
+
+        for id in 0..self.arena_scale.len() as u32 {
+            let scale = self.arena_scale.lookup_ref(Interned::from_id(id));
+            let splitting = *scale.splitting;
+            let tables = [scale.single, scale.married].map(|table| {
+                (
+                    self.arena_table.lookup_ref(table),
+                    other.arena_table.lookup_ref(table),
+                )
+            });
+            let [(single, other_single), (married, other_married)] = tables;
+            let incomes = VERIFY_INCOMES
+                .into_iter()
+                .chain(single.bracket_starts())
+                .chain(married.bracket_starts());
+            for x in incomes {
+                let values = [single.eval(x), married.eval_split(x, splitting)];
+                let other_values = [other_single.eval(x), other_married.eval_split(x, splitting)];
+                if values.map(f64::to_bits) != other_values.map(f64::to_bits) {
+                    return Err(anyhow!(
+                        "Scale #{id} evaluates differently at {x}: {values:?} != {other_values:?}"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the contents of the database, and how scales, tables and
+    /// municipalities are shared between cantons and years.
+    pub fn print_summary(&self, header: &DbHeader, cantons: &CantonArgs) {
+        println!(
+            "Format version {DB_VERSION}, source date {} (Unix time), checksum {:08x}",
+            header.source_date, header.checksum
+        );
+        println!(
+            "Encoding: {}, compression {:?}",
+            if header.encoding.compact {
+                "compact"
+            } else {
+                "plain"
+            },
+            header.encoding.compression
+        );
+        println!("Years: {:?}", header.years);
+        println!("Cantons: {}", header.cantons.join(", "));
+
+        let mut scale_refs = vec![0; self.arena_scale.len()];
+        let mut table_refs = vec![0; self.arena_table.len()];
+        let mut municipality_refs = vec![0; self.arena_municipality.len()];
+        let mut count_scale = |scale: Interned<InternedCantonalScale>| {
+            scale_refs[scale.id() as usize] += 1;
+            let scale = self.arena_scale.lookup_ref(scale);
+            table_refs[scale.single.id() as usize] += 1;
+            table_refs[scale.married.id() as usize] += 1;
+        };
+        for (year, entries) in &self.db {
+            println!("{year}: federal scale #{}", entries.federal.id());
+            count_scale(entries.federal);
+            for (canton, base) in &entries.cantons {
+                count_scale(base.scale);
+                if let Some(scale) = base.fortune_scale {
+                    count_scale(scale);
+                }
+                for municipality in &base.municipalities {
+                    municipality_refs[municipality.id() as usize] += 1;
+                }
+                if cantons.contains(canton) {
+                    let scale = self.arena_scale.lookup_ref(base.scale);
+                    println!(
+                        "  {canton}: rate {}, scale #{} (splitting {}, single table #{}, married table #{}), fortune rate {}, fortune scale {}, {} municipalities",
+                        base.rate,
+                        base.scale.id(),
+                        scale.splitting,
+                        scale.single.id(),
+                        scale.married.id(),
+                        base.fortune_rate,
+                        base.fortune_scale
+                            .map_or("none".into(), |scale| format!("#{}", scale.id())),
+                        base.municipalities.len(),
+                    );
+                }
+            }
+        }
+
+        for (name, refs) in [
+            ("scales", scale_refs),
+            ("tables", table_refs),
+            ("municipalities", municipality_refs),
+        ] {
+            let total: usize = refs.iter().sum();
+            let shared = refs.iter().filter(|&&count| count > 1).count();
+            println!(
+                "{} distinct {name} for {total} references ({shared} shared, max {} references)",
+                refs.len(),
+                refs.iter().max().unwrap_or(&0),
+            );
+        }
+    }
+
+    fn intern_scale(&self, scale: CantonalScale) -> Interned<InternedCantonalScale> {
+        self.arena_scale.intern(InternedCantonalScale {
+            splitting: scale.splitting,
+            single: self.arena_table.intern(scale.single),
+            married: self.arena_table.intern(scale.married),
+        })
+    }
+}
+
+fn compress(body: &[u8], compression: Compression) -> Vec<u8> {
+    match compression {
+        Compression::None => body.to_vec(),
+        Compression::Deflate => miniz_oxide::deflate::compress_to_vec(body, 10),
+    }
+}
+
+/// Percentage of bytes saved by an encoding.
+fn savings(before: usize, after: usize) -> f64 {
+    100.0 * (1.0 - after as f64 / before as f64)
+}
+
+fn arena_values<T: Eq + Hash>(arena: &Arena<T>) -> impl Iterator<Item = &T> {
+    (0..arena.len() as u32).map(|id| arena.lookup_ref(Interned::from_id(id)))
+}
+
+fn arena_from<T: Eq + Hash, U>(values: Vec<U>, f: impl Fn(U) -> Result<T>) -> Result<Arena<T>> {
+    let mut arena = Arena::default();
+    for value in values {
+        arena.push_mut(f(value)?);
+    }
+    Ok(arena)
+}
+
+fn verify_arena<T: Eq + Hash>(arena: &Arena<T>, other: &Arena<T>, name: &str) -> Result<()> {
+    let equal = arena.len() == other.len() && arena_values(arena).eq(arena_values(other));
+    if equal {
+        Ok(())
+    } else {
+        Err(anyhow!("Mismatching {name}"))
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+pub struct Year {
+    /// Federal income tax scale.
+    federal: Interned<InternedCantonalScale>,
+    cantons: BTreeMap<String, CantonalBase>,
+}
+
+impl Year {
+    fn new(data_dir: &Path, year: u32, cantons: &CantonArgs, db: &Database) -> Result<Self> {
+        let rates = load_rates(data_dir, year)?;
+        let cantonal_rates = cantonal_rates(&rates, year)?;
+        let scales = load_scales(data_dir, year)?;
+        let mut income_scales = cantonal_scales(&scales, TaxType::EinkommensSteuer)?;
+        let mut fortune_scales = cantonal_scales(&scales, TaxType::VermoegensSteuer)?;
+
+        let federal = income_scales
+            .remove("CH")
+            .ok_or_else(|| anyhow!("No federal income tax scale in {year}"))?;
+
+        let mut municipalities: BTreeMap<&str, Vec<&Rate>> = BTreeMap::new();
+        for rate in &rates.response {
+            municipalities
+                .entry(&rate.location.canton)
+                .or_default()
+                .push(rate);
+        }
+
+        let mut map = BTreeMap::new();
+        for (canton, scale) in income_scales {
+            // Municipalities of VS have their own scales, which aren't
+            // modeled.
+            if canton == "VS" || !cantons.contains(&canton) {
+                continue;
+            }
+            let rate = *cantonal_rates
+                .get(&canton)
+                .ok_or_else(|| anyhow!("No income tax rate for canton {canton} in {year}"))?;
+            let municipalities = municipalities.remove(canton.as_str()).unwrap_or_default();
+            // The cantonal multiplier is the same for all municipalities of a
+            // canton.
+            let fortune_rate = municipalities
+                .first()
+                .map_or(0.0, |rate| rate.fortune_rate_canton);
+            if let Some(rate) = municipalities
+                .iter()
+                .find(|rate| rate.fortune_rate_canton != fortune_rate)
+            {
+                return Err(anyhow!(
+                    "Inconsistent cantonal fortune rate in {canton}: {fortune_rate} != {}",
+                    rate.fortune_rate_canton
+                ));
+            }
+            let base = CantonalBase {
+                rate,
+                scale: db.intern_scale(scale),
+                fortune_rate,
+                fortune_scale: fortune_scales
+                    .remove(&canton)
+                    .map(|scale| db.intern_scale(scale)),
+                municipalities: municipalities
+                    .into_iter()
+                    .map(|rate| db.arena_municipality.intern(Municipality::new(rate)))
+                    .collect(),
+            };
+            map.insert(canton, base);
+        }
+        Ok(Year {
+            federal: db.intern_scale(federal),
+            cantons: map,
+        })
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+struct CantonalBase {
+    /// Cantonal multiplier of the income tax, in percent.
+    rate: f64,
+    /// Income tax scale.
+    scale: Interned<InternedCantonalScale>,
+    /// Cantonal multiplier of the fortune tax, in percent.
+    fortune_rate: f64,
+    fortune_scale: Option<Interned<InternedCantonalScale>>,
+    municipalities: Vec<Interned<Municipality>>,
+}
+
+#[derive(PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InternedCantonalScale {
+    pub splitting: OrderedFloat<f64>,
+    pub single: Interned<Table>,
+    pub married: Interned<Table>,
+}
+
+/// Communal multipliers of a municipality, in percent of the simple tax.
+#[derive(PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Municipality {
+    pub bfs_id: u32,
+    pub tax_location_id: u32,
+    pub name: String,
+    pub zip_code: String,
+    pub income_rate: OrderedFloat<f64>,
+    pub fortune_rate: OrderedFloat<f64>,
+    pub church: ChurchRates,
+}
+
+impl Municipality {
+    fn new(rate: &Rate) -> Self {
+        Self {
+            bfs_id: rate.location.bfs_id,
+            tax_location_id: rate.location.tax_location_id,
+            name: rate.location.bfs_name.clone(),
+            zip_code: rate.location.zip_code.clone(),
+            income_rate: OrderedFloat(rate.income_rate_city),
+            fortune_rate: OrderedFloat(rate.fortune_rate_city),
+            church: ChurchRates {
+                income_protestant: OrderedFloat(rate.income_rate_protestant),
+                income_roman_catholic: OrderedFloat(rate.income_rate_roman),
+                income_christ_catholic: OrderedFloat(rate.income_rate_christ),
+                fortune_protestant: OrderedFloat(rate.fortune_rate_protestant),
+                fortune_roman_catholic: OrderedFloat(rate.fortune_rate_roman),
+                fortune_christ_catholic: OrderedFloat(rate.fortune_rate_christ),
+            },
+        }
+    }
+}
+
+impl Municipality {
+    fn rates(&self) -> [f64; 8] {
+        let church = &self.church;
+        [
+            self.income_rate,
+            self.fortune_rate,
+            church.income_protestant,
+            church.income_roman_catholic,
+            church.income_christ_catholic,
+            church.fortune_protestant,
+            church.fortune_roman_catholic,
+            church.fortune_christ_catholic,
+        ]
+        .map(|rate| rate.0)
+    }
+
+    fn from_rates(
+        bfs_id: u32,
+        tax_location_id: u32,
+        name: String,
+        zip_code: String,
+        rates: [f64; 8],
+    ) -> Self {
+        let [
+            income_rate,
+            fortune_rate,
+            income_protestant,
+            income_roman_catholic,
+            income_christ_catholic,
+            fortune_protestant,
+            fortune_roman_catholic,
+            fortune_christ_catholic,
+        ] = rates.map(OrderedFloat);
+        Self {
+            bfs_id,
+            tax_location_id,
+            name,
+            zip_code,
+            income_rate,
+            fortune_rate,
+            church: ChurchRates {
+                income_protestant,
+                income_roman_catholic,
+                income_christ_catholic,
+                fortune_protestant,
+                fortune_roman_catholic,
+                fortune_christ_catholic,
+            },
+        }
+    }
+}
+
+/// Church tax multipliers of each confession, in percent of the simple tax.
+#[derive(PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChurchRates {
+    pub income_protestant: OrderedFloat<f64>,
+    pub income_roman_catholic: OrderedFloat<f64>,
+    pub income_christ_catholic: OrderedFloat<f64>,
+    pub fortune_protestant: OrderedFloat<f64>,
+    pub fortune_roman_catholic: OrderedFloat<f64>,
+    pub fortune_christ_catholic: OrderedFloat<f64>,
+}
+
+/// Compact encoding of a [`Database`]: tables use [`CompactTable`], and
+/// rates are [`Number`]s, so that most of them take a few bytes.
+#[derive(Serialize, Deserialize)]
+struct CompactDatabase {
+    arena_scale: Vec<CompactScale>,
+    arena_table: Vec<CompactTable>,
+    arena_municipality: Vec<CompactMunicipality>,
+    db: BTreeMap<u32, CompactYear>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactScale {
+    splitting: Number,
+    single: Interned<Table>,
+    married: Interned<Table>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactYear {
+    federal: Interned<InternedCantonalScale>,
+    cantons: BTreeMap<String, CompactCantonalBase>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactCantonalBase {
+    rate: Number,
+    scale: Interned<InternedCantonalScale>,
+    fortune_rate: Number,
+    fortune_scale: Option<Interned<InternedCantonalScale>>,
+    municipalities: Vec<Interned<Municipality>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompactMunicipality {
+    bfs_id: u32,
+    tax_location_id: u32,
+    name: String,
+    zip_code: String,
+    /// Income and fortune rates, followed by the church rates in the order of
+    /// the [`ChurchRates`] fields.
+    rates: [Number; 8],
+}
+
+impl From<&Database> for CompactDatabase {
+    fn from(db: &Database) -> Self {
+        CompactDatabase {
+            arena_scale: arena_values(&db.arena_scale)
+                .map(|scale| CompactScale {
+                    splitting: scale.splitting.0.into(),
+                    single: scale.single,
+                    married: scale.married,
+                })
+                .collect(),
+            arena_table: arena_values(&db.arena_table)
+                .map(CompactTable::from)
+                .collect(),
+            arena_municipality: arena_values(&db.arena_municipality)
+                .map(|municipality| CompactMunicipality {
+                    bfs_id: municipality.bfs_id,
+                    tax_location_id: municipality.tax_location_id,
+                    name: municipality.name.clone(),
+                    zip_code: municipality.zip_code.clone(),
+                    rates: municipality.rates().map(Number::from),
+                })
+                .collect(),
+            db: db
+                .db
+                .iter()
+                .map(|(&year, entries)| {
+                    let cantons = entries
+                        .cantons
+                        .iter()
+                        .map(|(canton, base)| {
+                            let base = CompactCantonalBase {
+                                rate: base.rate.into(),
+                                scale: base.scale,
+                                fortune_rate: base.fortune_rate.into(),
+                                fortune_scale: base.fortune_scale,
+                                municipalities: base.municipalities.clone(),
+                            };
+                            (canton.clone(), base)
+                        })
+                        .collect();
+                    let entries = CompactYear {
+                        federal: entries.federal,
+                        cantons,
+                    };
+                    (year, entries)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<CompactDatabase> for Database {
+    type Error = anyhow::Error;
+
+    fn try_from(compact: CompactDatabase) -> Result<Self> {
+        Ok(Database {
+            source_date: 0,
+            arena_scale: arena_from(compact.arena_scale, |scale| {
+                Ok(InternedCantonalScale {
+                    splitting: OrderedFloat(scale.splitting.into()),
+                    single: scale.single,
+                    married: scale.married,
+                })
+            })?,
+            arena_table: arena_from(compact.arena_table, CompactTable::try_into)?,
+            arena_municipality: arena_from(compact.arena_municipality, |municipality| {
+                Ok(Municipality::from_rates(
+                    municipality.bfs_id,
+                    municipality.tax_location_id,
+                    municipality.name,
+                    municipality.zip_code,
+                    municipality.rates.map(f64::from),
+                ))
+            })?,
+            db: compact
+                .db
+                .into_iter()
+                .map(|(year, entries)| {
+                    let cantons = entries
+                        .cantons
+                        .into_iter()
+                        .map(|(canton, base)| {
+                            let base = CantonalBase {
+                                rate: base.rate.into(),
+                                scale: base.scale,
+                                fortune_rate: base.fortune_rate.into(),
+                                fortune_scale: base.fortune_scale,
+                                municipalities: base.municipalities,
+                            };
+                            (canton, base)
+                        })
+                        .collect();
+                    let entries = Year {
+                        federal: entries.federal,
+                        cantons,
+                    };
+                    (year, entries)
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testdata::{YEAR, make_data_dir};
+
+    const SOURCE_DATE: u64 = 1_750_000_000;
+
+    #[test]
+    fn round_trip() {
+        let dir = make_data_dir();
+        let all = CantonArgs { cantons: vec![] };
+        let db = Database::new(dir.path(), [YEAR].into_iter(), &all, SOURCE_DATE).unwrap();
+        let path = db
+            .serialize(dir.path(), false, DbEncoding::default())
+            .unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes[..8], DB_MAGIC);
+        assert_eq!(bytes[8..12], DB_VERSION.to_le_bytes());
+
+        let (header, loaded) = Database::load(&path).unwrap();
+        assert_eq!(header.years, [YEAR]);
+        assert_eq!(header.cantons, ["VD", "ZH"]);
+        assert_eq!(header.source_date, SOURCE_DATE);
+        db.verify(&loaded).unwrap();
+
+        let zh = &loaded.db[&YEAR].cantons["ZH"];
+        let names: Vec<&str> = zh
+            .municipalities
+            .iter()
+            .map(|&municipality| {
+                loaded
+                    .arena_municipality
+                    .lookup_ref(municipality)
+                    .name
+                    .as_str()
+            })
+            .collect();
+        assert_eq!(names, ["Zürich", "Winterthur"]);
+        assert!(zh.fortune_scale.is_some());
+        assert!(loaded.db[&YEAR].cantons["VD"].fortune_scale.is_none());
+
+        // Corrupt the last byte of the database.
+        let mut corrupted = bytes;
+        *corrupted.last_mut().unwrap() ^= 1;
+        fs::write(&path, corrupted).unwrap();
+        let error = Database::load(&path).err().unwrap();
+        assert!(error.to_string().contains("Checksum mismatch"));
+    }
+
+    #[test]
+    fn encodings() {
+        let dir = make_data_dir();
+        let all = CantonArgs { cantons: vec![] };
+        let db = Database::new(dir.path(), [YEAR].into_iter(), &all, SOURCE_DATE).unwrap();
+
+        let mut sizes = Vec::new();
+        for compact in [false, true] {
+            for compression in [Compression::None, Compression::Deflate] {
+                let encoding = DbEncoding {
+                    compact,
+                    compression,
+                };
+                let path = db.serialize(dir.path(), true, encoding).unwrap();
+                let (header, loaded) = Database::load(&path).unwrap();
+                assert_eq!(header.encoding, encoding);
+                db.verify(&loaded).unwrap();
+                sizes.push(fs::metadata(&path).unwrap().len());
+            }
+        }
+        // Plain, plain + deflate, compact, compact + deflate.
+        assert!(sizes[2] < sizes[0]);
+        assert!(sizes[3] < sizes[1]);
+        assert!(sizes[3] < sizes[2]);
+    }
+
+    #[test]
+    fn source_date() {
+        let dir = make_data_dir();
+        for (name, date) in [("rates", SOURCE_DATE), ("scales", SOURCE_DATE + 60)] {
+            let path = dir.path().join(format!("{name}-{YEAR}.json"));
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(date))
+                .unwrap();
+        }
+        assert_eq!(
+            latest_modification(dir.path(), [YEAR].into_iter()).unwrap(),
+            SOURCE_DATE + 60
+        );
+        assert!(latest_modification(dir.path(), [YEAR, YEAR + 1].into_iter()).is_err());
+    }
+
+    #[test]
+    fn inconsistent_fortune_rate() {
+        let dir = make_data_dir();
+        let path = dir.path().join(format!("rates-{YEAR}.json"));
+        let mut rates: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        rates["response"][1]["FortuneRateCanton"] = 90.0.into();
+        fs::write(&path, rates.to_string()).unwrap();
+
+        let all = CantonArgs { cantons: vec![] };
+        let error = Database::new(dir.path(), [YEAR].into_iter(), &all, SOURCE_DATE)
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .contains("Inconsistent cantonal fortune rate in ZH: 100 != 90")
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use crate::schema::{DeductionEntry, Deductions, Format, Target, TaxType};
+use log::{debug, trace, warn};
+
+/// Social contributions of employees (AHV/IV/EO and ALV), in percent of the
+/// gross salary. Pension fund contributions vary between employers, and are
+/// ignored.
+const EMPLOYEE_CONTRIBUTIONS: f64 = 6.4;
+
+/// German names of the ESTV deductions of each kind. Other deductions, such as
+/// further education or insurance premiums, are ignored even if their names
+/// are similar.
+const PROFESSIONAL: &[&str] = &["Berufsauslagen", "Übrige Berufsauslagen"];
+const TWO_EARNER: &[&str] = &["Zweiverdienerabzug"];
+const MARRIED: &[&str] = &["Verheiratetenabzug"];
+const CHILD: &[&str] = &["Kinderabzug"];
+
+/// Deduction from the income: a percentage of it or a fixed amount, within
+/// optional bounds.
+#[derive(Clone, Debug, PartialEq)]
+struct DeductionRule {
+    percent: Option<f64>,
+    amount: f64,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+}
+
+impl DeductionRule {
+    fn new(entry: &DeductionEntry) -> Self {
+        let has = |format| entry.format.contains(&format);
+        DeductionRule {
+            percent: has(Format::Percent).then_some(entry.percent),
+            amount: entry.amount,
+            minimum: has(Format::Minimum).then_some(entry.minimum),
+            maximum: has(Format::Maximum).then_some(entry.maximum),
+        }
+    }
+
+    /// Returns the deduction from the given income, which it never exceeds.
+    fn eval(&self, income: f64) -> f64 {
+        let mut deduction = match self.percent {
+            Some(percent) => income * percent / 100.0,
+            None => self.amount,
+        };
+        if let Some(minimum) = self.minimum {
+            deduction = deduction.max(minimum);
+        }
+        if let Some(maximum) = self.maximum {
+            deduction = deduction.min(maximum);
+        }
+        deduction.clamp(0.0, income.max(0.0))
+    }
+}
+
+/// Deductions that differ between singles, single parents and married
+/// couples, to derive taxable incomes from gross salaries.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeductionModel {
+    /// Professional expenses of each employed person.
+    professional: Option<DeductionRule>,
+    /// Deduction from the lower income of a couple where both partners work.
+    two_earner: Option<DeductionRule>,
+    /// Deduction of married couples, from their combined income.
+    married: Option<DeductionRule>,
+    /// Deduction for each child, claimed by the parent who supports it.
+    child: Option<DeductionRule>,
+}
+
+impl DeductionModel {
+    /// Collects the income tax deductions of a canton, or the federal ones for
+    /// [`Target::Bund`]. Deductions are recognized by their exact German name,
+    /// and other ones are ignored. If a kind is listed several times, e.g. a
+    /// child deduction per age, only the first one is kept.
+    pub fn new(deductions: &Deductions, canton: &str, target: Target) -> Self {
+        let mut model = DeductionModel::default();
+        let entries = deductions
+            .response
+            .iter()
+            .filter(|deduction| {
+                deduction.tax_type == TaxType::EinkommensSteuer
+                    && deduction.target == target
+                    && match target {
+                        Target::Bund => deduction.location.canton_id == 1,
+                        _ => deduction.location.canton == canton,
+                    }
+            })
+            .flat_map(|deduction| &deduction.table);
+        for entry in entries {
+            trace!("Deduction: {entry:?}");
+            let name = entry.name.de.trim();
+            let rule = if PROFESSIONAL.contains(&name) {
+                &mut model.professional
+            } else if TWO_EARNER.contains(&name) {
+                &mut model.two_earner
+            } else if MARRIED.contains(&name) {
+                &mut model.married
+            } else if CHILD.contains(&name) {
+                &mut model.child
+            } else {
+                debug!("Ignoring deduction in {canton}: {name}");
+                continue;
+            };
+            if rule.is_some() {
+                warn!("Ignoring another deduction of the same kind as {name} in {canton}");
+                continue;
+            }
+            *rule = Some(DeductionRule::new(entry));
+        }
+        model
+    }
+
+    fn net_income(&self, gross: f64) -> f64 {
+        let net = gross * (1.0 - EMPLOYEE_CONTRIBUTIONS / 100.0);
+        let professional = self
+            .professional
+            .as_ref()
+            .map_or(0.0, |rule| rule.eval(net));
+        net - professional
+    }
+
+    fn child_deduction(&self, income: f64, children: u32) -> f64 {
+        self.child
+            .as_ref()
+            .map_or(0.0, |rule| children as f64 * rule.eval(income))
+    }
+
+    /// Returns the taxable income of a single person with the given gross
+    /// salary, who supports the given number of children.
+    pub fn taxable_single(&self, gross: f64, children: u32) -> f64 {
+        let income = self.net_income(gross);
+        (income - self.child_deduction(income, children)).max(0.0)
+    }
+
+    /// Returns the taxable income of a married couple with the given gross
+    /// salaries and number of children.
+    pub fn taxable_married(&self, gross_1: f64, gross_2: f64, children: u32) -> f64 {
+        let (income_1, income_2) = (self.net_income(gross_1), self.net_income(gross_2));
+        let income = income_1 + income_2;
+        let two_earner = match &self.two_earner {
+            Some(rule) if income_1 > 0.0 && income_2 > 0.0 => rule.eval(income_1.min(income_2)),
+            _ => 0.0,
+        };
+        let married = self.married.as_ref().map_or(0.0, |rule| rule.eval(income));
+        let child = self.child_deduction(income, children);
+        (income - two_earner - married - child).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::load::load_deductions;
+    use crate::testdata::{YEAR, make_data_dir};
+
+    #[test]
+    fn deduction_model() {
+        let rule = |percent, amount, minimum, maximum| {
+            Some(DeductionRule {
+                percent,
+                amount,
+                minimum,
+                maximum,
+            })
+        };
+        let model = DeductionModel {
+            professional: rule(Some(3.0), 0.0, Some(2000.0), Some(4000.0)),
+            two_earner: rule(Some(50.0), 0.0, Some(8000.0), Some(14000.0)),
+            married: rule(None, 2500.0, None, None),
+            child: rule(None, 9000.0, None, None),
+        };
+
+        // 100'000 gross is 93'600 net, minus 2'808 of professional expenses.
+        assert_eq!(model.taxable_single(100_000.0, 0), 90_792.0);
+        assert_eq!(model.taxable_single(0.0, 0), 0.0);
+        // The two-earner deduction only applies if both partners work, and is
+        // at most the lower income.
+        assert_eq!(model.taxable_married(100_000.0, 0.0, 0), 88_292.0);
+        assert_eq!(
+            model.taxable_married(100_000.0, 100_000.0, 0),
+            2.0 * 90_792.0 - 14_000.0 - 2_500.0
+        );
+        assert_eq!(model.taxable_married(100_000.0, 1_000.0, 0), 88_292.0);
+
+        // Children are deducted from the income of the parent who supports
+        // them.
+        assert_eq!(model.taxable_single(100_000.0, 2), 72_792.0);
+        assert_eq!(model.taxable_married(100_000.0, 0.0, 1), 79_292.0);
+        assert_eq!(model.taxable_single(10_000.0, 3), 0.0);
+
+        assert_eq!(
+            DeductionModel::default().taxable_married(1_000.0, 0.0, 2),
+            936.0
+        );
+    }
+
+    #[test]
+    fn deductions_by_canton() {
+        let dir = make_data_dir();
+        let deductions = load_deductions(dir.path(), YEAR).unwrap();
+        let professional = Some(DeductionRule {
+            percent: Some(3.0),
+            amount: 0.0,
+            minimum: Some(2_000.0),
+            maximum: Some(4_000.0),
+        });
+        let fixed = |amount| {
+            Some(DeductionRule {
+                percent: None,
+                amount,
+                minimum: None,
+                maximum: None,
+            })
+        };
+
+        assert_eq!(
+            DeductionModel::new(&deductions, "CH", Target::Bund),
+            DeductionModel {
+                professional: professional.clone(),
+                two_earner: Some(DeductionRule {
+                    percent: Some(50.0),
+                    amount: 0.0,
+                    minimum: Some(8_600.0),
+                    maximum: Some(14_100.0),
+                }),
+                married: fixed(2_800.0),
+                child: fixed(6_700.0),
+            }
+        );
+        // The deduction of married couples from the fortune is ignored.
+        assert_eq!(
+            DeductionModel::new(&deductions, "ZH", Target::Kanton),
+            DeductionModel {
+                professional,
+                two_earner: fixed(5_900.0),
+                married: None,
+                child: fixed(9_300.0),
+            }
+        );
+        assert_eq!(
+            DeductionModel::new(&deductions, "VD", Target::Kanton),
+            DeductionModel::default()
+        );
+        assert_eq!(
+            DeductionModel::new(&deductions, "BE", Target::Kanton),
+            DeductionModel::default()
+        );
+
+        // Only the first of several child deductions is kept.
+        let mut duplicated = deductions.clone();
+        let mut entry = duplicated.response[1].table[3].clone();
+        assert_eq!(entry.name.de, "Kinderabzug");
+        for amount in [2_700.0, 5_400.0] {
+            entry.amount = amount;
+            duplicated.response[1].table.push(entry.clone());
+        }
+        assert_eq!(
+            DeductionModel::new(&duplicated, "ZH", Target::Kanton).child,
+            fixed(9_300.0)
+        );
+    }
+}
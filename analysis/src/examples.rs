@@ -1,206 +1,537 @@
-use crate::load::{get_cantonal_rates, get_cantonal_scales};
-use crate::schema::{Location, Rates};
-use anyhow::Result;
-use log::{debug, info, trace, warn};
-use rand::RngExt;
-use rand::seq::SliceRandom;
-use reqwest::Client;
+use crate::calculator::{CalculatorBackend, calculate_with_retry};
+use crate::cli::CantonArgs;
+use crate::compute::{Household, TaxData, compute_taxes};
+use crate::generate::Generator;
+use crate::report::AccuracyReport;
+use crate::request::Request;
+use crate::schema::Location;
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use futures_util::{StreamExt, stream};
+use log::{debug, info, warn};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::{BTreeSet, HashMap};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter};
-use std::ops::{AddAssign, Deref};
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::AddAssign;
+use std::path::Path;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
-pub fn check_all_tests(years: impl IntoIterator<Item = u32>) -> Result<()> {
-    let mut cantons = BTreeSet::new();
-    let mut by_canton: HashMap<(String, Relationship), Matches> = HashMap::new();
-    let mut total = Matches::default();
-    let mut count_tests = 0;
+pub fn check_all_tests(
+    data_dir: &Path,
+    years: impl IntoIterator<Item = u32>,
+    canton_filter: &CantonArgs,
+    tolerances: &Tolerances,
+) -> Result<AccuracyReport> {
+    let mut report = AccuracyReport::new(tolerances.clone());
     for year in years {
-        let (num_tests, results) = check_tests(year)?;
-        count_tests += num_tests;
-
-        let mut by_year = Matches::default();
-        for (key, test_result) in results {
-            cantons.insert(key.0.clone());
-            let matches = test_result.map_or_default(|r| r.matches());
-            *by_canton.entry(key).or_default() += matches;
-            by_year += matches;
+        for test in check_tests(data_dir, year, tolerances)? {
+            if canton_filter.contains(&test.canton) {
+                report.add(year, &test);
+            }
+        }
+        if let Some(stats) = report.by_year.get(&year) {
+            info!("Matches in {year}: {}", stats.matches);
         }
-
-        total += by_year;
-        info!("Matches in {year}: {by_year:?}");
     }
 
-    for relationship in [Relationship::Single, Relationship::Married] {
-        for canton in &cantons {
-            let matches = by_canton[&(canton.clone(), relationship)];
-            info!("Matches in ({canton}, {relationship:?}): {matches:?}");
-        }
+    for (canton, stats) in &report.by_canton {
+        info!("Matches in {canton}: {}", stats.matches);
     }
+    for (relationship, stats) in &report.by_relationship {
+        info!("Matches in {relationship:?}: {}", stats.matches);
+    }
+    info!(
+        "Total matches ({} examples): {}",
+        report.total.examples, report.total.matches
+    );
 
-    info!("Total matches: {total:?} / {count_tests}");
+    Ok(report)
+}
 
-    Ok(())
+/// Outcome of checking one example, which is `None` if we have no scale for
+/// the canton or don't model the household.
+pub struct CheckedTest {
+    pub canton: String,
+    pub relationship: Relationship,
+    pub request: Request,
+    pub result: Option<TestResult>,
 }
 
 pub struct TestResult {
-    expected: Evaluation,
-    actual: Evaluation,
+    pub expected: Evaluation<Option<f64>>,
+    pub actual: Evaluation<Option<f64>>,
 }
 
 impl TestResult {
-    fn check(&self, year: u32, canton: &str, relationship: Relationship) {
-        if self.expected.income_simple_tax_canton != self.actual.income_simple_tax_canton {
+    fn check(&self, year: u32, canton: &str, relationship: Relationship, tolerances: &Tolerances) {
+        for (field, expected, actual) in self.mismatches(tolerances) {
             warn!(
-                "[{canton}, {year}, {relationship:?}] Mismatch for income_simple_tax_canton: expected {} got {}",
-                self.expected.income_simple_tax_canton, self.actual.income_simple_tax_canton
+                "[{canton}, {year}, {relationship:?}] Mismatch for {field}: expected {expected} got {actual}"
             );
         }
-        if self.expected.income_simple_tax_city != self.actual.income_simple_tax_city {
-            warn!(
-                "[{canton}, {year}, {relationship:?}] Mismatch for income_simple_tax_city: expected {} got {}",
-                self.expected.income_simple_tax_city, self.actual.income_simple_tax_city
-            );
-        }
-        if self.expected.income_tax_canton != self.actual.income_tax_canton {
-            warn!(
-                "[{canton}, {year}, {relationship:?}] Mismatch for income_tax_canton: expected {} got {}",
-                self.expected.income_tax_canton, self.actual.income_tax_canton
-            );
+    }
+
+    /// Returns the name, expected and actual value of each field whose
+    /// expected value is known, where the actual value is `None` if the field
+    /// is unsupported.
+    pub fn fields(&self) -> impl Iterator<Item = (&'static str, f64, Option<f64>)> {
+        Evaluation::<f64>::FIELDS
+            .into_iter()
+            .zip(self.expected.values())
+            .zip(self.actual.values())
+            .filter_map(|((field, expected), actual)| Some((field, expected?, actual)))
+    }
+
+    /// Returns the supported fields that don't match, with their expected and
+    /// actual values.
+    pub fn mismatches(
+        &self,
+        tolerances: &Tolerances,
+    ) -> impl Iterator<Item = (&'static str, f64, f64)> {
+        self.fields().filter_map(|(field, expected, actual)| {
+            let actual = actual?;
+            (!tolerances.get(field).accepts(expected, actual)).then_some((field, expected, actual))
+        })
+    }
+
+    /// Returns whether all the supported fields match.
+    pub fn is_match(&self, tolerances: &Tolerances) -> bool {
+        self.mismatches(tolerances).next().is_none()
+    }
+
+    pub fn matches(&self, tolerances: &Tolerances) -> Matches {
+        Matches(
+            self.fields()
+                .map(|(field, expected, actual)| {
+                    (
+                        field.to_owned(),
+                        FieldMatches::new(expected, actual, tolerances.get(field)),
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Accepted difference between an expected and an actual value: either up to
+/// `abs` CHF, or up to `rel` times the expected value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tolerance {
+    pub abs: f64,
+    pub rel: f64,
+}
+
+impl Tolerance {
+    pub fn accepts(&self, expected: f64, actual: f64) -> bool {
+        let diff = (actual - expected).abs();
+        diff <= self.abs || diff <= self.rel * expected.abs()
+    }
+}
+
+/// Tolerance of each field, defaulting to an exact comparison.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tolerances {
+    default: Tolerance,
+    fields: BTreeMap<String, Tolerance>,
+}
+
+impl Tolerances {
+    /// Returns the tolerances of an exact comparison of all the fields.
+    pub const fn exact() -> Self {
+        Self {
+            default: Tolerance { abs: 0.0, rel: 0.0 },
+            fields: BTreeMap::new(),
         }
     }
 
-    fn matches(&self) -> Matches {
-        Matches {
-            income_simple_tax_canton: if self.expected.income_simple_tax_canton
-                == self.actual.income_simple_tax_canton
-            {
-                1
+    /// Builds the tolerances for the given fields, where the "*" field sets
+    /// the tolerance of all the other fields.
+    pub fn new(tolerances: impl IntoIterator<Item = (String, Tolerance)>) -> Result<Self> {
+        let mut result = Self::exact();
+        for (field, tolerance) in tolerances {
+            if field == "*" {
+                result.default = tolerance;
+            } else if Evaluation::<f64>::FIELDS.contains(&field.as_str()) {
+                result.fields.insert(field, tolerance);
             } else {
-                0
-            },
-            income_simple_tax_city: if self.expected.income_simple_tax_city
-                == self.actual.income_simple_tax_city
-            {
-                1
-            } else {
-                0
-            },
-            income_tax_canton: if self.expected.income_tax_canton == self.actual.income_tax_canton {
-                1
-            } else {
-                0
-            },
+                return Err(anyhow!(
+                    "Unknown field {field:?}, expected one of {:?}",
+                    Evaluation::<f64>::FIELDS
+                ));
+            }
         }
+        Ok(result)
+    }
+
+    pub fn get(&self, field: &str) -> Tolerance {
+        self.fields.get(field).copied().unwrap_or(self.default)
     }
 }
 
-pub struct Evaluation {
-    income_simple_tax_canton: f64,
-    income_simple_tax_city: f64,
-    income_tax_canton: f64,
+/// Values of the fields of an example. The expected values are `None` for the
+/// fields that the calculator doesn't return, and the actual ones for the
+/// fields that we don't compute yet.
+#[derive(Debug, PartialEq)]
+pub struct Evaluation<T> {
+    pub fortune_simple_tax_canton: T,
+    pub fortune_simple_tax_city: T,
+    pub fortune_tax_canton: T,
+    pub fortune_tax_church: T,
+    pub fortune_tax_city: T,
+    pub income_simple_tax_canton: T,
+    pub income_simple_tax_city: T,
+    pub income_simple_tax_fed: T,
+    pub income_tax_canton: T,
+    pub income_tax_church: T,
+    pub income_tax_city: T,
+    pub income_tax_fed: T,
+    pub personal_tax: T,
+    pub tax_credit: T,
+    pub total_net_tax: T,
+    pub total_tax: T,
+}
+
+impl Evaluation<Option<f64>> {
+    /// Evaluates the given request with our own implementation.
+    pub fn evaluate(request: &Request, data: &TaxData) -> Result<Self> {
+        let taxes = compute_taxes(data, &Household::from_request(request, data)?)?;
+        Ok(Evaluation {
+            fortune_simple_tax_canton: taxes.fortune_simple_tax_canton,
+            fortune_simple_tax_city: taxes.fortune_simple_tax_city,
+            fortune_tax_canton: taxes.fortune_tax_canton,
+            fortune_tax_church: None,
+            fortune_tax_city: taxes.fortune_tax_city,
+            income_simple_tax_canton: Some(taxes.income_simple_tax_canton),
+            income_simple_tax_city: taxes.income_simple_tax_city,
+            income_simple_tax_fed: Some(taxes.income_simple_tax_fed),
+            income_tax_canton: Some(taxes.income_tax_canton),
+            income_tax_church: None,
+            income_tax_city: taxes.income_tax_city,
+            income_tax_fed: Some(taxes.income_tax_fed),
+            personal_tax: None,
+            tax_credit: None,
+            // The totals include the church and personal taxes.
+            total_net_tax: None,
+            total_tax: None,
+        })
+    }
+}
+
+impl<T: Copy> Evaluation<T> {
+    pub const FIELDS: [&str; 16] = [
+        "fortune_simple_tax_canton",
+        "fortune_simple_tax_city",
+        "fortune_tax_canton",
+        "fortune_tax_church",
+        "fortune_tax_city",
+        "income_simple_tax_canton",
+        "income_simple_tax_city",
+        "income_simple_tax_fed",
+        "income_tax_canton",
+        "income_tax_church",
+        "income_tax_city",
+        "income_tax_fed",
+        "personal_tax",
+        "tax_credit",
+        "total_net_tax",
+        "total_tax",
+    ];
+
+    fn values(&self) -> [T; 16] {
+        [
+            self.fortune_simple_tax_canton,
+            self.fortune_simple_tax_city,
+            self.fortune_tax_canton,
+            self.fortune_tax_church,
+            self.fortune_tax_city,
+            self.income_simple_tax_canton,
+            self.income_simple_tax_city,
+            self.income_simple_tax_fed,
+            self.income_tax_canton,
+            self.income_tax_church,
+            self.income_tax_city,
+            self.income_tax_fed,
+            self.personal_tax,
+            self.tax_credit,
+            self.total_net_tax,
+            self.total_tax,
+        ]
+    }
+}
+
+impl From<&Example> for Evaluation<Option<f64>> {
+    fn from(example: &Example) -> Self {
+        Evaluation {
+            fortune_simple_tax_canton: example.fortune_simple_tax_canton,
+            fortune_simple_tax_city: example.fortune_simple_tax_city,
+            fortune_tax_canton: example.fortune_tax_canton,
+            fortune_tax_church: example.fortune_tax_church,
+            fortune_tax_city: example.fortune_tax_city,
+            income_simple_tax_canton: example.income_simple_tax_canton,
+            income_simple_tax_city: example.income_simple_tax_city,
+            income_simple_tax_fed: example.income_simple_tax_fed,
+            income_tax_canton: example.income_tax_canton,
+            income_tax_church: example.income_tax_church,
+            income_tax_city: example.income_tax_city,
+            income_tax_fed: example.income_tax_fed,
+            personal_tax: example.personal_tax,
+            tax_credit: example.tax_credit,
+            total_net_tax: example.total_net_tax,
+            total_tax: example.total_tax,
+        }
+    }
 }
 
-#[derive(Default, Clone, Copy, Debug)]
-pub struct Matches {
-    income_simple_tax_canton: usize,
-    income_simple_tax_city: usize,
-    income_tax_canton: usize,
+/// Match counts and error statistics of each field.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Matches(BTreeMap<String, FieldMatches>);
+
+impl Matches {
+    pub fn get(&self, field: &str) -> Option<&FieldMatches> {
+        self.0.get(field)
+    }
+
+    /// Returns the fraction of values that we compute, over all fields.
+    pub fn coverage(&self) -> Option<f64> {
+        let (count, unsupported) = self
+            .0
+            .values()
+            .fold((0, 0), |(count, unsupported), matches| {
+                (count + matches.count, unsupported + matches.unsupported)
+            });
+        let total = count + unsupported;
+        (total != 0).then(|| count as f64 / total as f64)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &FieldMatches)> {
+        self.0
+            .iter()
+            .map(|(field, matches)| (field.as_str(), matches))
+    }
 }
 
 impl AddAssign for Matches {
     fn add_assign(&mut self, other: Self) {
-        self.income_simple_tax_canton += other.income_simple_tax_canton;
-        self.income_simple_tax_city += other.income_simple_tax_city;
-        self.income_tax_canton += other.income_tax_canton;
+        for (field, matches) in other.0 {
+            *self.0.entry(field).or_default() += matches;
+        }
+    }
+}
+
+impl fmt::Display for Matches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (field, matches) in self.iter() {
+            if matches.count == 0 {
+                continue;
+            }
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+            write!(
+                f,
+                "{field}: {}/{} (mean error {:.2}, max error {:.2})",
+                matches.matches, matches.count, matches.mean_abs_error, matches.max_abs_error
+            )?;
+        }
+        if let Some(coverage) = self.coverage() {
+            write!(f, "; coverage {:.1}%", coverage * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Upper bounds of the buckets of the absolute error histograms, in CHF. An
+/// additional last bucket counts the larger errors.
+pub const HISTOGRAM_BOUNDS: [f64; 5] = [0.0, 1.0, 10.0, 100.0, 1000.0];
+
+/// Match count and error statistics of a field. Relative errors are computed
+/// against the expected value, or against 1 CHF if it's smaller.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct FieldMatches {
+    /// Number of compared values.
+    pub count: usize,
+    /// Number of values within the tolerance.
+    pub matches: usize,
+    /// Number of values that we don't compute.
+    pub unsupported: usize,
+    pub mean_abs_error: f64,
+    pub max_abs_error: f64,
+    pub mean_rel_error: f64,
+    pub max_rel_error: f64,
+    /// Number of absolute errors in each bucket of [`HISTOGRAM_BOUNDS`].
+    pub histogram: [usize; HISTOGRAM_BOUNDS.len() + 1],
+}
+
+impl FieldMatches {
+    fn new(expected: f64, actual: Option<f64>, tolerance: Tolerance) -> Self {
+        let Some(actual) = actual else {
+            return Self {
+                unsupported: 1,
+                ..Default::default()
+            };
+        };
+        let abs_error = (actual - expected).abs();
+        let rel_error = abs_error / expected.abs().max(1.0);
+        let mut histogram = [0; HISTOGRAM_BOUNDS.len() + 1];
+        histogram[HISTOGRAM_BOUNDS.partition_point(|&bound| bound < abs_error)] = 1;
+        Self {
+            count: 1,
+            matches: tolerance.accepts(expected, actual).into(),
+            unsupported: 0,
+            mean_abs_error: abs_error,
+            max_abs_error: abs_error,
+            mean_rel_error: rel_error,
+            max_rel_error: rel_error,
+            histogram,
+        }
+    }
+
+    pub fn match_rate(&self) -> Option<f64> {
+        (self.count != 0).then(|| self.matches as f64 / self.count as f64)
+    }
+}
+
+impl AddAssign for FieldMatches {
+    fn add_assign(&mut self, other: Self) {
+        self.unsupported += other.unsupported;
+        let count = self.count + other.count;
+        if count == 0 {
+            return;
+        }
+        let weight = other.count as f64 / count as f64;
+        self.mean_abs_error += (other.mean_abs_error - self.mean_abs_error) * weight;
+        self.mean_rel_error += (other.mean_rel_error - self.mean_rel_error) * weight;
+        self.max_abs_error = self.max_abs_error.max(other.max_abs_error);
+        self.max_rel_error = self.max_rel_error.max(other.max_rel_error);
+        for (bucket, other) in self.histogram.iter_mut().zip(other.histogram) {
+            *bucket += other;
+        }
+        self.count = count;
+        self.matches += other.matches;
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum Relationship {
     Single,
     Married,
 }
 
-#[expect(clippy::type_complexity)]
+pub fn load_tests(data_dir: &Path, year: u32) -> Result<Vec<Test>> {
+    let (generators, tests) = read_test_suite(&data_dir.join(format!("tests-{year}.json")))?;
+    debug!(
+        "Loaded {} tests, generated with {generators:?}",
+        tests.len()
+    );
+    Ok(tests)
+}
+
 pub fn check_tests(
+    data_dir: &Path,
     year: u32,
-) -> Result<(usize, HashMap<(String, Relationship), Option<TestResult>>)> {
+    tolerances: &Tolerances,
+) -> Result<Vec<CheckedTest>> {
     info!("Checking examples for {year}");
-    let tests: TestSuite = serde_json::from_reader(BufReader::new(File::open(format!(
-        "data/tests-{year}.json"
-    ))?))?;
-    let num_tests = tests.0.len();
-    debug!("Loaded {num_tests} tests");
+    let tests = load_tests(data_dir, year)?;
 
-    debug!("Loading cantonal scales");
-    let cantonal_scales = get_cantonal_scales(year)?;
+    let data = TaxData::load(data_dir, year)?;
 
-    debug!("Loading cantonal rates");
-    let cantonal_rates = get_cantonal_rates(year)?;
-
-    let mut results = HashMap::new();
-    for test in tests.0 {
+    let mut results = Vec::with_capacity(tests.len());
+    for test in tests {
         let (request, response) = (test.request, test.response.response);
-        let canton = response.location.canton;
-        let relationship = match request.relationship {
-            1 => Relationship::Single,
-            2 => Relationship::Married,
-            x => panic!("Unknown relationship type: {x}"),
-        };
+        let canton = response.location.canton.clone();
+        let relationship = request.relationship();
 
         debug!("Checking {canton}");
-        if let (Some(canton_scale), Some(canton_rate)) =
-            (cantonal_scales.get(&canton), cantonal_rates.get(&canton))
-        {
-            let table = match relationship {
-                Relationship::Single => &canton_scale.single,
-                Relationship::Married => &canton_scale.married,
-            };
-
-            let income_simple_tax_canton = match relationship {
-                Relationship::Single => table.eval(request.taxable_income_canton.into()),
-                Relationship::Married => table.eval_split(
-                    request.taxable_income_canton.into(),
-                    *canton_scale.splitting,
-                ),
-            };
-            let income_tax_canton = income_simple_tax_canton * canton_rate / 100.0;
-            // TODO: not in VS
-            let income_simple_tax_city = income_simple_tax_canton;
-
-            let expected = Evaluation {
-                income_simple_tax_canton: response.income_simple_tax_canton,
-                income_simple_tax_city: response.income_simple_tax_city,
-                income_tax_canton: response.income_tax_canton,
-            };
-            let actual = Evaluation {
-                income_simple_tax_canton: income_simple_tax_canton.round(),
-                income_simple_tax_city: income_simple_tax_city.round(),
-                income_tax_canton: income_tax_canton.round(),
-            };
+        let result = if !request.is_modeled() {
+            debug!("Skipping household that isn't modeled: {request:?}");
+            None
+        } else if data.income_scale(&canton).is_some() {
+            let expected = Evaluation::from(&response);
+            let actual = Evaluation::evaluate(&request, &data)?;
             let test_result = TestResult { expected, actual };
-            test_result.check(year, &canton, relationship);
-
-            results.insert((canton, relationship), Some(test_result));
+            test_result.check(year, &canton, relationship, tolerances);
+            Some(test_result)
         } else {
-            results.insert((canton, relationship), None);
-        }
+            None
+        };
+        results.push(CheckedTest {
+            canton,
+            relationship,
+            request,
+            result,
+        });
     }
 
-    Ok((num_tests, results))
+    Ok(results)
 }
 
-pub fn fetch_examples(years: impl Iterator<Item = u32>) -> Result<()> {
+/// How to handle existing test files when fetching examples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Fail if the file already exists.
+    CreateNew,
+    /// Replace the existing file.
+    Overwrite,
+    /// Add new examples to the existing file.
+    Append,
+    /// Fetch the examples of the generators recorded in the existing file
+    /// that are missing from it.
+    Resume,
+}
+
+/// Number of fetched tests after which the test file is written again, so that
+/// an interrupted fetch can be resumed.
+const CHECKPOINT: usize = 100;
+
+pub struct FetchOptions {
+    pub mode: WriteMode,
+    /// Maximum number of concurrent requests.
+    pub concurrency: usize,
+    /// Number of retries of a request that failed with a server error or a
+    /// timeout.
+    pub retries: u32,
+    /// Delay before the first retry, doubled at each subsequent retry.
+    pub backoff: Duration,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            mode: WriteMode::CreateNew,
+            concurrency: 4,
+            retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+pub fn fetch_examples(
+    backend: &impl CalculatorBackend,
+    generator: &Generator,
+    data_dir: &Path,
+    years: impl Iterator<Item = u32>,
+    cantons: &CantonArgs,
+    options: &FetchOptions,
+) -> Result<()> {
     let rt = Runtime::new()?;
 
     rt.block_on(async {
-        let client = Client::new();
         for year in years {
-            if let Err(e) = fetch_examples_impl(&client, year).await {
+            if let Err(e) =
+                fetch_examples_impl(backend, generator, data_dir, year, cantons, options).await
+            {
                 warn!("Failed to fetch examples for {year}: {e:?}");
             }
         }
@@ -208,208 +539,514 @@ pub fn fetch_examples(years: impl Iterator<Item = u32>) -> Result<()> {
     })
 }
 
-async fn fetch_examples_impl(client: &Client, year: u32) -> Result<()> {
+async fn fetch_examples_impl(
+    backend: &impl CalculatorBackend,
+    generator: &Generator,
+    data_dir: &Path,
+    year: u32,
+    cantons: &CantonArgs,
+    options: &FetchOptions,
+) -> Result<()> {
     info!("Making test cases for {year}");
-    fs::create_dir_all("data")?;
+    fs::create_dir_all(data_dir)?;
 
-    let path = format!("data/tests-{year}.json");
-    let file = File::create_new(&path)?;
-    debug!("Created new file: {path:?}");
+    let path = data_dir.join(format!("tests-{year}.json"));
+    // Existing tests are kept as raw JSON, so that they're written back as is.
+    let (mut generators, mut tests) = match options.mode {
+        WriteMode::CreateNew if path.exists() => {
+            return Err(anyhow!("File already exists: {path:?}"));
+        }
+        WriteMode::Append | WriteMode::Resume if path.exists() => read_test_suite::<Value>(&path)?,
+        _ => (Vec::new(), Vec::new()),
+    };
+    debug!(
+        "Loaded {} existing tests, generated with {generators:?}",
+        tests.len()
+    );
 
-    let examples = make_examples(year)?;
+    let new_generators = match options.mode {
+        WriteMode::Resume if !tests.is_empty() => {
+            if generators.is_empty() {
+                return Err(anyhow!(
+                    "Cannot resume {path:?}, which doesn't record its generator"
+                ));
+            }
+            generators.clone()
+        }
+        _ => {
+            generators.push(generator.clone());
+            vec![generator.clone()]
+        }
+    };
+
+    let mut known: HashSet<String> = tests
+        .iter()
+        .map(|test| serde_json::from_value::<Request>(test["request"].clone()).map(|r| r.digest()))
+        .try_collect()?;
+    let mut requests = Vec::new();
+    for generator in &new_generators {
+        for request in generator.make_examples(data_dir, year, cantons)? {
+            if known.insert(request.digest()) {
+                requests.push(request);
+            }
+        }
+    }
+    info!("Fetching {} examples for {year}", requests.len());
 
-    let mut tests = Vec::new();
-    for request in examples {
-        trace!("Evaluating {request:?}");
-        match fetch_calculation(client, &request).await {
+    let mut responses = stream::iter(requests)
+        .map(|request| async {
+            let response =
+                calculate_with_retry(backend, &request, options.retries, options.backoff).await;
+            (request, response)
+        })
+        .buffered(options.concurrency.max(1));
+    let mut fetched = 0;
+    while let Some((request, response)) = responses.next().await {
+        match response {
             Ok(response) => {
-                tests.push(Value::Object(
-                    [
-                        ("request".into(), request.to_json()),
-                        ("response".into(), response),
-                    ]
-                    .into_iter()
-                    .collect(),
-                ));
+                tests.push(json!({
+                    "request": request.to_json(),
+                    "response": response,
+                }));
+                fetched += 1;
+                if fetched % CHECKPOINT == 0 {
+                    write_test_suite(&path, &generators, &tests)?;
+                }
             }
             Err(e) => {
                 warn!("Failed to fetch calculation for {request:?}: {e:?}");
             }
         }
     }
+    write_test_suite(&path, &generators, &tests)
+}
 
-    debug!("Serializing tests for {year}");
-    let json = Value::Array(tests);
-    serde_json::to_writer(BufWriter::new(file), &json)?;
-
+fn write_test_suite(path: &Path, generators: &[Generator], tests: &[Value]) -> Result<()> {
+    debug!("Serializing {} tests to {path:?}", tests.len());
+    // Write to a temporary file first, to never leave a truncated file behind.
+    let tmp_path = path.with_extension("json.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(
+        &mut writer,
+        &json!({
+            "generators": generators,
+            "tests": tests,
+        }),
+    )?;
+    writer.flush()?;
+    fs::rename(&tmp_path, path)?;
     Ok(())
 }
 
-fn make_examples(year: u32) -> Result<Vec<Request>> {
-    debug!("Making examples for {year}");
-    let rates: Rates = serde_json::from_reader(BufReader::new(File::open(format!(
-        "data/rates-{year}.json"
-    ))?))?;
+fn read_test_suite<T: DeserializeOwned>(path: &Path) -> Result<(Vec<Generator>, Vec<T>)> {
+    let suite: TestSuite<T> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    Ok(suite.into_parts())
+}
 
-    let mut locations: HashMap<String, Vec<&Location>> = HashMap::new();
-    for rate in &rates.response {
-        locations
-            .entry(rate.location.canton.clone())
-            .or_default()
-            .push(&rate.location);
-    }
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TestSuite<T> {
+    /// Tests generated with the recorded generator configurations.
+    Generated {
+        generators: Vec<Generator>,
+        tests: Vec<T>,
+    },
+    /// Tests generated with a single recorded generator configuration, before
+    /// appending was supported.
+    SingleGenerator { generator: Generator, tests: Vec<T> },
+    /// Older test files, only containing the tests.
+    Legacy(Vec<T>),
+}
 
-    let mut requests = Vec::new();
-    let mut rng = rand::rng();
-    for (canton, mut locations) in locations.into_iter() {
-        locations.partial_shuffle(&mut rng, 2);
-
-        trace!("- Canton: {canton}");
-        for i in 0..2 {
-            let location = locations[i % locations.len()];
-            trace!("  [{i}] {location:?}");
-
-            let taxable_fortune = rng.random_range(500_000..2_000_000);
-            let taxable_income_canton = rng.random_range(50_000..200_000);
-            let taxable_income_fed = rng.random_range(50_000..200_000);
-
-            if i == 0 {
-                requests.push(Request::make_single(
-                    taxable_fortune,
-                    taxable_income_canton,
-                    taxable_income_fed,
-                    location.tax_location_id,
-                    year,
-                ));
-            } else {
-                requests.push(Request::make_married(
-                    taxable_fortune,
-                    taxable_income_canton,
-                    taxable_income_fed,
-                    location.tax_location_id,
-                    year,
-                ));
-            }
+impl<T> TestSuite<T> {
+    fn into_parts(self) -> (Vec<Generator>, Vec<T>) {
+        match self {
+            TestSuite::Generated { generators, tests } => (generators, tests),
+            TestSuite::SingleGenerator { generator, tests } => (vec![generator], tests),
+            TestSuite::Legacy(tests) => (Vec::new(), tests),
         }
     }
-    Ok(requests)
 }
 
-async fn fetch_calculation(client: &Client, request: &Request) -> Result<serde_json::Value> {
-    const URL: &str = "https://swisstaxcalculator.estv.admin.ch/delegate/ost-integration/v1/lg-proxy/operation/c3b67379_ESTV/API_calculateSimpleTaxes";
-
-    let res = client.post(URL).json(request).send().await?;
-    trace!("Status: {:?}", res.status());
-
-    let bytes = res.bytes().await?;
-    trace!("Received {} bytes", bytes.len());
-
-    trace!("Parsing as JSON");
-    let json = serde_json::from_slice(bytes.deref())?;
-
-    Ok(json)
-}
-
-#[derive(Debug, Deserialize)]
-struct TestSuite(Vec<Test>);
-
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
-struct Test {
-    request: Request,
-    response: Response,
+pub struct Test {
+    pub request: Request,
+    pub response: Response,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
-struct Response {
-    response: Example,
+pub struct Response {
+    pub response: Example,
 }
 
-#[expect(dead_code)]
+/// Response of a calculator, whose fields are `None` where it doesn't compute
+/// them, as with the [`LocalBackend`](crate::calculator::LocalBackend).
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "PascalCase")]
-struct Example {
-    location: Location,
-    fortune_simple_tax_canton: f64,
-    fortune_simple_tax_city: f64,
-    fortune_tax_canton: f64,
-    fortune_tax_church: f64,
-    fortune_tax_city: f64,
-    income_simple_tax_canton: f64,
-    income_simple_tax_city: f64,
-    income_simple_tax_fed: f64,
-    income_tax_canton: f64,
-    income_tax_church: f64,
-    income_tax_city: f64,
-    income_tax_fed: f64,
-    personal_tax: f64,
-    tax_credit: f64,
-    total_net_tax: f64,
-    total_tax: f64,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "PascalCase")]
-struct Request {
-    children: Vec<Child>,
-    confession1: u32,
-    confession2: u32,
-    relationship: u32,
-    taxable_fortune: u32,
-    taxable_income_canton: u32,
-    taxable_income_fed: u32,
-    #[serde(rename = "TaxLocationID")]
-    tax_location_id: u32,
-    tax_year: u32,
-}
-
-impl Request {
-    fn to_json(&self) -> Value {
-        serde_json::to_value(self).unwrap()
-    }
-
-    fn make_single(
-        taxable_fortune: u32,
-        taxable_income_canton: u32,
-        taxable_income_fed: u32,
-        tax_location_id: u32,
-        tax_year: u32,
-    ) -> Self {
-        Self {
-            children: vec![],
-            confession1: 5,
-            confession2: 0,
-            relationship: 1,
-            taxable_fortune,
-            taxable_income_canton,
-            taxable_income_fed,
-            tax_location_id,
-            tax_year,
+pub struct Example {
+    pub location: Location,
+    fortune_simple_tax_canton: Option<f64>,
+    fortune_simple_tax_city: Option<f64>,
+    fortune_tax_canton: Option<f64>,
+    fortune_tax_church: Option<f64>,
+    fortune_tax_city: Option<f64>,
+    income_simple_tax_canton: Option<f64>,
+    income_simple_tax_city: Option<f64>,
+    income_simple_tax_fed: Option<f64>,
+    income_tax_canton: Option<f64>,
+    income_tax_church: Option<f64>,
+    income_tax_city: Option<f64>,
+    income_tax_fed: Option<f64>,
+    personal_tax: Option<f64>,
+    tax_credit: Option<f64>,
+    total_net_tax: Option<f64>,
+    total_tax: Option<f64>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calculator::{LocalBackend, TransientError};
+    use crate::generate::{Household, Strategy};
+    use crate::request::CivilStatus;
+    use crate::testdata::{YEAR, make_data_dir};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Backend that fails on married households.
+    struct FailingBackend(LocalBackend);
+
+    impl CalculatorBackend for FailingBackend {
+        async fn calculate(&self, request: &Request) -> Result<Value> {
+            match request.relationship() {
+                Relationship::Single => self.0.calculate(request).await,
+                Relationship::Married => Err(anyhow!("Service unavailable")),
+            }
         }
     }
 
-    fn make_married(
-        taxable_fortune: u32,
-        taxable_income_canton: u32,
-        taxable_income_fed: u32,
-        tax_location_id: u32,
-        tax_year: u32,
-    ) -> Self {
-        Self {
-            children: vec![],
-            confession1: 5,
-            confession2: 5,
-            relationship: 2,
-            taxable_fortune,
-            taxable_income_canton,
-            taxable_income_fed,
-            tax_location_id,
-            tax_year,
+    fn all_cantons() -> CantonArgs {
+        CantonArgs { cantons: vec![] }
+    }
+
+    fn generator() -> Generator {
+        Generator {
+            seed: 0,
+            strategies: vec![Strategy::Uniform],
+            locations_per_canton: Some(2),
+            samples: 1,
+            households: Household::defaults(),
+            church: false,
         }
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-struct Child;
+    fn options(mode: WriteMode) -> FetchOptions {
+        FetchOptions {
+            mode,
+            retries: 0,
+            ..Default::default()
+        }
+    }
+
+    fn load_suite(data_dir: &Path) -> (Vec<Generator>, Vec<Test>) {
+        read_test_suite(&data_dir.join(format!("tests-{YEAR}.json"))).unwrap()
+    }
+
+    fn load_tests(data_dir: &Path) -> Vec<Test> {
+        let (generators, tests) = load_suite(data_dir);
+        assert_eq!(generators, vec![generator()]);
+        tests
+    }
+
+    #[test]
+    fn fetch_examples_local() {
+        let dir = make_data_dir();
+        let backend = LocalBackend::new(dir.path());
+        fetch_examples(
+            &backend,
+            &generator(),
+            dir.path(),
+            [YEAR].into_iter(),
+            &all_cantons(),
+            &options(WriteMode::CreateNew),
+        )
+        .unwrap();
+
+        let tests = load_tests(dir.path());
+        assert_eq!(tests.len(), 6);
+
+        let exact = Tolerances::exact();
+        let results = check_tests(dir.path(), YEAR, &exact).unwrap();
+        assert_eq!(results.len(), 6);
+        for test in results {
+            let key = (test.canton, test.relationship);
+            assert!(test.result.unwrap().is_match(&exact), "{key:?}");
+        }
+    }
+
+    /// Backend evaluating all households as if they were single persons
+    /// without children.
+    struct SinglesBackend(LocalBackend);
+
+    impl CalculatorBackend for SinglesBackend {
+        async fn calculate(&self, request: &Request) -> Result<Value> {
+            let mut request = request.clone();
+            request.children.clear();
+            request.civil_status = CivilStatus::Single;
+            request.partner_confession = None;
+            self.0.calculate(&request).await
+        }
+    }
+
+    #[test]
+    fn unmodeled_households_are_not_checked() {
+        let dir = make_data_dir();
+        let mut generator = generator();
+        generator.households = vec![
+            Household::Single,
+            Household::SingleParent,
+            Household::Concubinage,
+        ];
+        let cantons = CantonArgs {
+            cantons: vec!["VD".into()],
+        };
+        let parent = Request::builder(YEAR, 200).child(5).build().unwrap();
+        let rt = Runtime::new().unwrap();
+        assert!(
+            rt.block_on(LocalBackend::new(dir.path()).calculate(&parent))
+                .is_err()
+        );
+        fetch_examples(
+            &SinglesBackend(LocalBackend::new(dir.path())),
+            &generator,
+            dir.path(),
+            [YEAR].into_iter(),
+            &cantons,
+            &options(WriteMode::CreateNew),
+        )
+        .unwrap();
+
+        let results = check_tests(dir.path(), YEAR, &Tolerances::exact()).unwrap();
+        assert_eq!(results.len(), 3);
+        for test in results {
+            assert_eq!(test.result.is_some(), test.request.is_modeled());
+        }
+    }
+
+    #[test]
+    fn fetch_examples_drops_failures() {
+        let dir = make_data_dir();
+        let backend = FailingBackend(LocalBackend::new(dir.path()));
+        let cantons = CantonArgs {
+            cantons: vec!["VD".into()],
+        };
+        fetch_examples(
+            &backend,
+            &generator(),
+            dir.path(),
+            [YEAR].into_iter(),
+            &cantons,
+            &options(WriteMode::CreateNew),
+        )
+        .unwrap();
+
+        let tests = load_tests(dir.path());
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].request.relationship(), Relationship::Single);
+        assert_eq!(tests[0].response.response.location.canton, "VD");
+    }
+
+    #[test]
+    fn fetch_examples_no_overwrite() {
+        let dir = make_data_dir();
+        let path = dir.path().join(format!("tests-{YEAR}.json"));
+        fs::write(&path, "[]").unwrap();
+
+        let backend = LocalBackend::new(dir.path());
+        fetch_examples(
+            &backend,
+            &generator(),
+            dir.path(),
+            [YEAR].into_iter(),
+            &all_cantons(),
+            &options(WriteMode::CreateNew),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]");
+
+        fetch_examples(
+            &backend,
+            &generator(),
+            dir.path(),
+            [YEAR].into_iter(),
+            &all_cantons(),
+            &options(WriteMode::Overwrite),
+        )
+        .unwrap();
+        assert_eq!(load_tests(dir.path()).len(), 6);
+    }
+
+    #[test]
+    fn fetch_examples_append() {
+        let dir = make_data_dir();
+        let backend = LocalBackend::new(dir.path());
+        let fetch = |generator: &Generator, mode| {
+            fetch_examples(
+                &backend,
+                generator,
+                dir.path(),
+                [YEAR].into_iter(),
+                &all_cantons(),
+                &options(mode),
+            )
+            .unwrap()
+        };
+        let other = Generator {
+            seed: 1,
+            ..generator()
+        };
+
+        fetch(&generator(), WriteMode::CreateNew);
+        let old = load_tests(dir.path());
+        fetch(&other, WriteMode::Append);
+        let (generators, tests) = load_suite(dir.path());
+        assert_eq!(generators, vec![generator(), other.clone()]);
+        assert_eq!(tests.len(), 12);
+        for (old, new) in old.iter().zip(&tests) {
+            assert_eq!(old.request.digest(), new.request.digest());
+        }
+
+        // Appending the same examples again doesn't duplicate them.
+        fetch(&other, WriteMode::Append);
+        assert_eq!(load_suite(dir.path()).1.len(), 12);
+    }
+
+    #[test]
+    fn fetch_examples_resume() {
+        let dir = make_data_dir();
+        let failing = FailingBackend(LocalBackend::new(dir.path()));
+        let backend = LocalBackend::new(dir.path());
+        fetch_examples(
+            &failing,
+            &generator(),
+            dir.path(),
+            [YEAR].into_iter(),
+            &all_cantons(),
+            &options(WriteMode::CreateNew),
+        )
+        .unwrap();
+        assert_eq!(load_tests(dir.path()).len(), 3);
+
+        // The recorded generator is used, not the given one.
+        fetch_examples(
+            &backend,
+            &Generator {
+                seed: 1,
+                ..generator()
+            },
+            dir.path(),
+            [YEAR].into_iter(),
+            &all_cantons(),
+            &options(WriteMode::Resume),
+        )
+        .unwrap();
+        assert_eq!(load_tests(dir.path()).len(), 6);
+    }
+
+    #[test]
+    fn fetch_examples_retry() {
+        /// Backend failing the first requests, with server errors or with
+        /// other errors, which aren't retried.
+        struct FlakyBackend(LocalBackend, AtomicUsize, bool);
+
+        impl CalculatorBackend for FlakyBackend {
+            async fn calculate(&self, request: &Request) -> Result<Value> {
+                if self.1.fetch_add(1, Ordering::Relaxed) < 2 {
+                    return Err(if self.2 {
+                        TransientError("Server error: 503".into()).into()
+                    } else {
+                        anyhow!("Client error: 400")
+                    });
+                }
+                self.0.calculate(request).await
+            }
+        }
+
+        let options = |mode| FetchOptions {
+            mode,
+            concurrency: 2,
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let dir = make_data_dir();
+        let backend = FlakyBackend(LocalBackend::new(dir.path()), AtomicUsize::new(0), true);
+        fetch_examples(
+            &backend,
+            &generator(),
+            dir.path(),
+            [YEAR].into_iter(),
+            &all_cantons(),
+            &options(WriteMode::CreateNew),
+        )
+        .unwrap();
+        assert_eq!(load_tests(dir.path()).len(), 6);
+
+        let backend = FlakyBackend(LocalBackend::new(dir.path()), AtomicUsize::new(0), false);
+        fetch_examples(
+            &backend,
+            &generator(),
+            dir.path(),
+            [YEAR].into_iter(),
+            &all_cantons(),
+            &options(WriteMode::Overwrite),
+        )
+        .unwrap();
+        assert_eq!(load_tests(dir.path()).len(), 4);
+        assert_eq!(backend.1.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn tolerances() {
+        let tolerance = Tolerance { abs: 1.0, rel: 0.0 };
+        assert!(tolerance.accepts(100.0, 101.0));
+        assert!(!tolerance.accepts(100.0, 102.0));
+        let tolerance = Tolerance {
+            abs: 0.0,
+            rel: 0.01,
+        };
+        assert!(tolerance.accepts(1000.0, 1010.0));
+        assert!(!tolerance.accepts(1000.0, 1011.0));
+
+        let tolerances = Tolerances::new([
+            ("*".to_owned(), Tolerance { abs: 1.0, rel: 0.0 }),
+            (
+                "income_tax_canton".to_owned(),
+                Tolerance { abs: 5.0, rel: 0.0 },
+            ),
+        ])
+        .unwrap();
+        assert_eq!(tolerances.get("income_simple_tax_city").abs, 1.0);
+        assert_eq!(tolerances.get("income_tax_canton").abs, 5.0);
+        assert!(Tolerances::new([("foo".to_owned(), Tolerance::default())]).is_err());
+    }
+
+    #[test]
+    fn field_matches() {
+        let exact = Tolerance::default();
+        let mut matches = FieldMatches::new(100.0, Some(100.0), exact);
+        matches += FieldMatches::new(100.0, Some(150.0), exact);
+        matches += FieldMatches::new(2000.0, Some(2001.0), exact);
+        assert_eq!(matches.count, 3);
+        assert_eq!(matches.matches, 1);
+        assert_eq!(matches.mean_abs_error, 17.0);
+        assert_eq!(matches.max_abs_error, 50.0);
+        assert_eq!(matches.max_rel_error, 0.5);
+        assert_eq!(matches.histogram, [1, 1, 0, 1, 0, 0]);
+
+        matches += FieldMatches::new(100.0, None, exact);
+        assert_eq!(matches.count, 3);
+        assert_eq!(matches.unsupported, 1);
+        assert_eq!(matches.mean_abs_error, 17.0);
+    }
+}
@@ -9,9 +9,10 @@ use nom::number::complete::recognize_float;
 use nom::sequence::{delimited, preceded};
 use nom::{IResult, Parser};
 use ordered_float::OrderedFloat;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Formula {
     Input,
     Const(OrderedFloat<f64>),
@@ -59,10 +60,10 @@ impl Formula {
     }
 }
 
-impl TryFrom<&str> for Formula {
-    type Error = anyhow::Error;
+impl FromStr for Formula {
+    type Err = anyhow::Error;
 
-    fn try_from(text: &str) -> Result<Self, Self::Error> {
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
         if text.is_empty() {
             Ok(Formula::constant(0.0))
         } else {
@@ -182,7 +183,7 @@ mod test {
     #[test]
     fn parse_formula() {
         assert_eq!(
-            Formula::try_from(
+            Formula::from_str(
                 "-0.827429* $wert$ + 0.089718* $wert$ * (log $wert$ - 1) + 829.418770"
             )
             .unwrap(),
@@ -204,21 +205,21 @@ mod test {
 
     #[test]
     fn parse_input() {
-        assert_eq!(Formula::try_from("$wert$").unwrap(), Formula::Input);
-        assert_eq!(Formula::try_from("  $wert$").unwrap(), Formula::Input);
-        assert_eq!(Formula::try_from("$wert$  ").unwrap(), Formula::Input);
-        assert_eq!(Formula::try_from(" $wert$  ").unwrap(), Formula::Input);
+        assert_eq!(Formula::from_str("$wert$").unwrap(), Formula::Input);
+        assert_eq!(Formula::from_str("  $wert$").unwrap(), Formula::Input);
+        assert_eq!(Formula::from_str("$wert$  ").unwrap(), Formula::Input);
+        assert_eq!(Formula::from_str(" $wert$  ").unwrap(), Formula::Input);
     }
 
     #[test]
     fn parse_const() {
-        assert_eq!(Formula::try_from("100").unwrap(), Formula::constant(100.0));
+        assert_eq!(Formula::from_str("100").unwrap(), Formula::constant(100.0));
         assert_eq!(
-            Formula::try_from("12.34").unwrap(),
+            Formula::from_str("12.34").unwrap(),
             Formula::constant(12.34)
         );
         assert_eq!(
-            Formula::try_from("-42.42").unwrap(),
+            Formula::from_str("-42.42").unwrap(),
             Formula::constant(-42.42)
         );
     }
@@ -0,0 +1,327 @@
+use crate::cli::CantonArgs;
+use crate::examples::Relationship;
+use crate::load::{CantonalScale, get_cantonal_scales};
+use crate::request::{CivilStatus, Confession, Request, RequestBuilder};
+use crate::schema::{Location, Rates};
+use anyhow::Result;
+use clap::ValueEnum;
+use log::{debug, trace};
+use rand::rngs::ChaCha12Rng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::{RngExt, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Configuration of the example generator. It's recorded alongside the
+/// generated examples, so that they can be reproduced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Generator {
+    pub seed: u64,
+    pub strategies: Vec<Strategy>,
+    /// Number of municipalities sampled in each canton, or all of them if
+    /// `None`.
+    pub locations_per_canton: Option<usize>,
+    /// Number of samples per municipality for the randomized strategies.
+    pub samples: usize,
+    #[serde(default = "Household::defaults")]
+    pub households: Vec<Household>,
+    /// Whether to sample the confessions of the taxpayers, so that examples
+    /// cover the church tax.
+    #[serde(default)]
+    pub church: bool,
+}
+
+/// Shape of the generated households.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Household {
+    Single,
+    Married,
+    /// Single person with children.
+    SingleParent,
+    /// Married couple with children.
+    MarriedParents,
+    /// Unmarried partners living together.
+    Concubinage,
+}
+
+impl Household {
+    /// Households of test files that didn't record them.
+    pub fn defaults() -> Vec<Household> {
+        vec![Household::Single, Household::Married]
+    }
+
+    fn civil_status(self) -> CivilStatus {
+        match self {
+            Household::Single | Household::SingleParent => CivilStatus::Single,
+            Household::Married | Household::MarriedParents => CivilStatus::Married,
+            Household::Concubinage => CivilStatus::Concubinage,
+        }
+    }
+
+    fn has_children(self) -> bool {
+        matches!(self, Household::SingleParent | Household::MarriedParents)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Uniform incomes between 50'000 and 200'000 CHF.
+    Uniform,
+    /// Fixed grid of incomes between 0 and 1'000'000 CHF.
+    Grid,
+    /// Log-uniform incomes between 10'000 and 5'000'000 CHF.
+    LogUniform,
+    /// Incomes just around multiples of 100 CHF.
+    Round100,
+    /// Incomes above the top bracket of the cantonal scale.
+    TopBracket,
+}
+
+/// Confessions sampled for the church tax.
+const CONFESSIONS: [Confession; 4] = [
+    Confession::Protestant,
+    Confession::RomanCatholic,
+    Confession::ChristCatholic,
+    Confession::None,
+];
+
+const INCOME_GRID: [u32; 11] = [
+    0, 10_000, 25_000, 50_000, 75_000, 100_000, 150_000, 200_000, 300_000, 500_000, 1_000_000,
+];
+
+impl Generator {
+    pub fn make_examples(
+        &self,
+        data_dir: &Path,
+        year: u32,
+        cantons: &CantonArgs,
+    ) -> Result<Vec<Request>> {
+        debug!("Making examples for {year} with {self:?}");
+        let rates: Rates = serde_json::from_reader(BufReader::new(File::open(
+            data_dir.join(format!("rates-{year}.json")),
+        )?))?;
+        let cantonal_scales = get_cantonal_scales(data_dir, year)?;
+
+        // Sorted by canton, so that the sampling only depends on the seed.
+        let mut locations: BTreeMap<&str, Vec<&Location>> = BTreeMap::new();
+        for rate in &rates.response {
+            if !cantons.contains(&rate.location.canton) {
+                continue;
+            }
+            locations
+                .entry(&rate.location.canton)
+                .or_default()
+                .push(&rate.location);
+        }
+
+        let mut requests = Vec::new();
+        let mut rng = ChaCha12Rng::seed_from_u64(self.seed);
+        for (canton, mut locations) in locations {
+            let locations = match self.locations_per_canton {
+                Some(count) => locations.partial_shuffle(&mut rng, count).0,
+                None => locations.as_mut_slice(),
+            };
+
+            trace!("- Canton: {canton}");
+            let scale = cantonal_scales.get(canton);
+            for location in locations.iter() {
+                trace!("  {location:?}");
+                for &strategy in &self.strategies {
+                    for &household in &self.households {
+                        let builder = Request::builder(year, location.tax_location_id)
+                            .civil_status(household.civil_status());
+                        let relationship = household.civil_status().relationship();
+                        for income in self.incomes(strategy, relationship, scale, &mut rng) {
+                            let taxable_fortune = rng.random_range(500_000..2_000_000);
+                            let builder = builder.clone().income(income).fortune(taxable_fortune);
+                            requests.push(self.sample_household(builder, household, &mut rng)?);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(requests)
+    }
+
+    /// Samples the children and confessions of the household.
+    fn sample_household(
+        &self,
+        mut builder: RequestBuilder,
+        household: Household,
+        rng: &mut ChaCha12Rng,
+    ) -> Result<Request> {
+        if household.has_children() {
+            for _ in 0..rng.random_range(1..=3) {
+                builder = builder.child(rng.random_range(0..25));
+            }
+        }
+        if self.church {
+            builder = builder.confession(*CONFESSIONS.choose(rng).unwrap());
+            if household.civil_status().is_couple() {
+                builder = builder.partner_confession(*CONFESSIONS.choose(rng).unwrap());
+            }
+        }
+        builder.build()
+    }
+
+    fn incomes(
+        &self,
+        strategy: Strategy,
+        relationship: Relationship,
+        scale: Option<&CantonalScale>,
+        rng: &mut ChaCha12Rng,
+    ) -> Vec<u32> {
+        match strategy {
+            Strategy::Uniform => (0..self.samples)
+                .map(|_| rng.random_range(50_000..200_000))
+                .collect(),
+            Strategy::Grid => INCOME_GRID.to_vec(),
+            Strategy::LogUniform => {
+                let range = 10_000f64.ln()..5_000_000f64.ln();
+                (0..self.samples)
+                    .map(|_| rng.random_range(range.clone()).exp().round() as u32)
+                    .collect()
+            }
+            Strategy::Round100 => (0..self.samples)
+                .flat_map(|_| {
+                    let base = rng.random_range(200..3_000) * 100;
+                    [base - 1, base, base + 1, base + 99]
+                })
+                .collect(),
+            Strategy::TopBracket => {
+                let Some(scale) = scale else {
+                    return Vec::new();
+                };
+                let top = match relationship {
+                    Relationship::Single => scale.single.top_bracket(),
+                    Relationship::Married if *scale.splitting == 0.0 => scale.married.top_bracket(),
+                    Relationship::Married => scale.married.top_bracket() * *scale.splitting,
+                };
+                if top == 0.0 {
+                    // Flat tax, there is no top bracket.
+                    return Vec::new();
+                }
+                std::iter::once(top + 100.0)
+                    .chain((0..self.samples).map(|_| top * rng.random_range(1.0..3.0)))
+                    .map(|income| income.round() as u32)
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testdata::{YEAR, make_data_dir};
+
+    fn generator(seed: u64, strategies: Vec<Strategy>) -> Generator {
+        Generator {
+            seed,
+            strategies,
+            locations_per_canton: Some(1),
+            samples: 3,
+            households: Household::defaults(),
+            church: false,
+        }
+    }
+
+    fn incomes(requests: &[Request]) -> Vec<u32> {
+        requests.iter().map(|r| r.taxable_income_canton).collect()
+    }
+
+    #[test]
+    fn deterministic() {
+        let dir = make_data_dir();
+        let all = CantonArgs { cantons: vec![] };
+        let strategies = vec![Strategy::Uniform, Strategy::LogUniform];
+
+        let a = generator(42, strategies.clone())
+            .make_examples(dir.path(), YEAR, &all)
+            .unwrap();
+        let b = generator(42, strategies.clone())
+            .make_examples(dir.path(), YEAR, &all)
+            .unwrap();
+        let c = generator(43, strategies)
+            .make_examples(dir.path(), YEAR, &all)
+            .unwrap();
+        assert_eq!(incomes(&a), incomes(&b));
+        assert_ne!(incomes(&a), incomes(&c));
+    }
+
+    #[test]
+    fn all_municipalities() {
+        let dir = make_data_dir();
+        let zh = CantonArgs {
+            cantons: vec!["ZH".into()],
+        };
+        let mut generator = generator(0, vec![Strategy::Grid]);
+        generator.locations_per_canton = None;
+
+        let requests = generator.make_examples(dir.path(), YEAR, &zh).unwrap();
+        assert_eq!(requests.len(), 2 * 2 * INCOME_GRID.len());
+    }
+
+    #[test]
+    fn round100() {
+        let dir = make_data_dir();
+        let vd = CantonArgs {
+            cantons: vec!["VD".into()],
+        };
+        let requests = generator(0, vec![Strategy::Round100])
+            .make_examples(dir.path(), YEAR, &vd)
+            .unwrap();
+        assert_eq!(requests.len(), 2 * 3 * 4);
+        for chunk in incomes(&requests).chunks(4) {
+            assert_eq!(chunk[0] % 100, 99);
+            assert_eq!(chunk[1] % 100, 0);
+            assert_eq!(chunk[2] % 100, 1);
+            assert_eq!(chunk[3] % 100, 99);
+        }
+    }
+
+    #[test]
+    fn top_bracket() {
+        let dir = make_data_dir();
+        let zh = CantonArgs {
+            cantons: vec!["ZH".into()],
+        };
+        let requests = generator(0, vec![Strategy::TopBracket])
+            .make_examples(dir.path(), YEAR, &zh)
+            .unwrap();
+        assert_eq!(requests.len(), 2 * 4);
+        // The top bracket of the test scale starts at 34'000 CHF.
+        assert!(incomes(&requests).iter().all(|&income| income > 34_000));
+        assert_eq!(requests[0].taxable_income_canton, 34_100);
+    }
+
+    #[test]
+    fn households() {
+        let dir = make_data_dir();
+        let zh = CantonArgs {
+            cantons: vec!["ZH".into()],
+        };
+        let mut generator = generator(0, vec![Strategy::Uniform]);
+        generator.households = vec![Household::SingleParent, Household::Concubinage];
+        generator.church = true;
+
+        let requests = generator.make_examples(dir.path(), YEAR, &zh).unwrap();
+        assert_eq!(requests.len(), 2 * 3);
+        let (parents, partners) = requests.split_at(3);
+        for request in parents {
+            assert_eq!(request.civil_status, CivilStatus::Single);
+            assert!((1..=3).contains(&request.children.len()));
+            assert_eq!(request.partner_confession, None);
+        }
+        for request in partners {
+            assert_eq!(request.civil_status, CivilStatus::Concubinage);
+            assert!(request.children.is_empty());
+            assert!(request.partner_confession.is_some());
+        }
+    }
+}
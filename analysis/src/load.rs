@@ -1,80 +1,15 @@
-use crate::schema::{Group, Rates, Scales, Target, TaxType};
+use crate::examples::Relationship;
+use crate::schema::{Deductions, Group, Rates, Scales, Target, TaxType};
 use crate::table::{EvalPolicy, Table};
 use anyhow::{Result, anyhow};
-use blazinterner::{Arena, Interned};
 use log::{debug, trace};
 use ordered_float::OrderedFloat;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::collections::hash_map::Entry;
-use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-
-#[derive(Serialize)]
-pub struct Database {
-    arena_scale: Arena<InternedCantonalScale>,
-    arena_table: Arena<Table>,
-    db: BTreeMap<u32, Year>,
-}
-
-impl Database {
-    pub fn new(years: impl Iterator<Item = u32>) -> Result<Self> {
-        let mut arena_scale = Arena::default();
-        let mut arena_table = Arena::default();
-        let db = years
-            .map(|year| -> Result<_> {
-                Ok((year, Year::new(year, &mut arena_scale, &mut arena_table)?))
-            })
-            .try_collect()?;
-        Ok(Database {
-            arena_scale,
-            arena_table,
-            db,
-        })
-    }
-
-    pub fn serialize(&self) -> Result<()> {
-        let file = File::create_new("data/tables.db")?;
-        postcard::to_io(self, BufWriter::new(file))?;
-        Ok(())
-    }
-}
-
-#[derive(Serialize)]
-pub struct Year(BTreeMap<String, CantonalBase>);
-
-impl Year {
-    fn new(
-        year: u32,
-        arena_scale: &mut Arena<InternedCantonalScale>,
-        arena_table: &mut Arena<Table>,
-    ) -> Result<Self> {
-        let rates = get_cantonal_rates(year)?;
-        let scales = get_cantonal_scales(year)?;
-
-        let mut map = BTreeMap::new();
-        for (canton, scale) in scales {
-            if canton == "VS" {
-                continue;
-            }
-            let rate = rates[&canton];
-            map.insert(
-                canton,
-                CantonalBase {
-                    rate,
-                    scale: arena_scale.intern(InternedCantonalScale::new(scale, arena_table)),
-                },
-            );
-        }
-        Ok(Year(map))
-    }
-}
-
-#[derive(Serialize)]
-struct CantonalBase {
-    rate: f64,
-    scale: Interned<InternedCantonalScale>,
-}
+use std::io::BufReader;
+use std::path::Path;
 
 pub fn canton_policy(canton: &str) -> Result<EvalPolicy> {
     match canton {
@@ -92,12 +27,14 @@ pub fn canton_policy(canton: &str) -> Result<EvalPolicy> {
     }
 }
 
-pub fn get_cantonal_rates(year: u32) -> Result<HashMap<String, f64>> {
+pub fn get_cantonal_rates(data_dir: &Path, year: u32) -> Result<HashMap<String, f64>> {
     debug!("Loading cantonal rates for {year}");
-    let rates: Rates = serde_json::from_reader(BufReader::new(File::open(format!(
-        "data/rates-{year}.json"
-    ))?))?;
+    cantonal_rates(&load_rates(data_dir, year)?, year)
+}
 
+/// Returns the cantonal income tax multiplier of each canton, in percent of
+/// the simple tax, which must be the same for all its municipalities.
+pub fn cantonal_rates(rates: &Rates, year: u32) -> Result<HashMap<String, f64>> {
     let mut cantonal_rates: HashMap<String, f64> = HashMap::new();
     for rate in &rates.response {
         trace!("Rate: {:?}", rate);
@@ -134,21 +71,27 @@ pub fn get_cantonal_rates(year: u32) -> Result<HashMap<String, f64>> {
     Ok(cantonal_rates)
 }
 
-#[derive(PartialEq, Eq, Hash, Serialize)]
-pub struct InternedCantonalScale {
-    pub splitting: OrderedFloat<f64>,
-    pub single: Interned<Table>,
-    pub married: Interned<Table>,
-}
+/// Returns the median communal income tax multiplier of each canton, in
+/// percent of the cantonal simple tax.
+pub fn get_communal_rates(data_dir: &Path, year: u32) -> Result<HashMap<String, f64>> {
+    debug!("Loading communal rates for {year}");
+    let rates = load_rates(data_dir, year)?;
 
-impl InternedCantonalScale {
-    fn new(scale: CantonalScale, arena: &mut Arena<Table>) -> Self {
-        Self {
-            splitting: scale.splitting,
-            single: arena.intern(scale.single),
-            married: arena.intern(scale.married),
-        }
+    let mut communal_rates: HashMap<String, Vec<f64>> = HashMap::new();
+    for rate in &rates.response {
+        communal_rates
+            .entry(rate.location.canton.clone())
+            .or_default()
+            .push(rate.income_rate_city);
     }
+
+    Ok(communal_rates
+        .into_iter()
+        .map(|(canton, mut rates)| {
+            rates.sort_by(f64::total_cmp);
+            (canton, rates[rates.len() / 2])
+        })
+        .collect())
 }
 
 #[derive(PartialEq, Eq, Hash, Serialize)]
@@ -156,29 +99,90 @@ pub struct CantonalScale {
     pub splitting: OrderedFloat<f64>,
     pub single: Table,
     pub married: Table,
+    /// Tariff of single parents, if the scales list one explicitly.
+    pub single_parent: Option<SingleParentScale>,
+}
+
+/// Tariff of single parents (`LedigMitKinder`), which is often the married
+/// tariff with its splitting.
+#[derive(Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct SingleParentScale {
+    pub splitting: OrderedFloat<f64>,
+    pub table: Table,
+}
+
+impl SingleParentScale {
+    pub fn eval(&self, x: f64) -> f64 {
+        self.table.eval_split(x, *self.splitting)
+    }
+}
+
+impl CantonalScale {
+    pub fn eval(&self, relationship: Relationship, x: f64) -> f64 {
+        match relationship {
+            Relationship::Single => self.single.eval(x),
+            Relationship::Married => self.married.eval_split(x, *self.splitting),
+        }
+    }
+}
+
+pub fn load_rates(data_dir: &Path, year: u32) -> Result<Rates> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(
+        data_dir.join(format!("rates-{year}.json")),
+    )?))?)
+}
+
+pub fn load_scales(data_dir: &Path, year: u32) -> Result<Scales> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(
+        data_dir.join(format!("scales-{year}.json")),
+    )?))?)
+}
+
+pub fn load_deductions(data_dir: &Path, year: u32) -> Result<Deductions> {
+    Ok(serde_json::from_reader(BufReader::new(File::open(
+        data_dir.join(format!("deductions-{year}.json")),
+    )?))?)
 }
 
-pub fn get_cantonal_scales(year: u32) -> Result<HashMap<String, CantonalScale>> {
-    let scales: Scales = serde_json::from_reader(BufReader::new(File::open(format!(
-        "data/scales-{year}.json"
-    ))?))?;
+pub fn get_cantonal_scales(data_dir: &Path, year: u32) -> Result<HashMap<String, CantonalScale>> {
+    cantonal_scales(&load_scales(data_dir, year)?, TaxType::EinkommensSteuer)
+}
+
+pub fn cantonal_scales(
+    scales: &Scales,
+    tax_type: TaxType,
+) -> Result<HashMap<String, CantonalScale>> {
+    // Rounding rules were only investigated for the income tax.
+    let policy = |canton: &str| match tax_type {
+        TaxType::EinkommensSteuer => canton_policy(canton),
+        _ => Ok(EvalPolicy::Raw),
+    };
 
     let mut cantonal_scales_single = HashMap::new();
     let mut cantonal_scales_married = HashMap::new();
+    let mut cantonal_scales_single_parent = HashMap::new();
     scales
         .response
         .iter()
-        .filter(|scale| {
-            scale.tax_type == TaxType::EinkommensSteuer && scale.target == Target::Kanton
-        })
+        .filter(|scale| scale.tax_type == tax_type && scale.target == Target::Kanton)
         .try_for_each(|scale| -> Result<()> {
             trace!("Cantonal scale: {scale:?}");
             let single = is_single(&scale.group);
             let married = is_married(&scale.group);
-            let policy = canton_policy(&scale.location.canton)?;
-            if (single || married)
+            let single_parent = is_single_parent(&scale.group);
+            let policy = policy(&scale.location.canton)?;
+            if (single || married || single_parent)
                 && let Ok(table) = Table::try_from(scale, policy)
             {
+                if single_parent {
+                    cantonal_scales_single_parent.insert(
+                        scale.location.canton.clone(),
+                        SingleParentScale {
+                            splitting: OrderedFloat(scale.splitting),
+                            table: table.clone(),
+                        },
+                    );
+                }
                 if single {
                     cantonal_scales_single.insert(scale.location.canton.clone(), table.clone());
                 }
@@ -196,7 +200,7 @@ pub fn get_cantonal_scales(year: u32) -> Result<HashMap<String, CantonalScale>>
         .response
         .iter()
         .filter(|scale| {
-            scale.tax_type == TaxType::EinkommensSteuer
+            scale.tax_type == tax_type
                 && scale.target == Target::Bund
                 && scale.location.canton_id == 1
         })
@@ -204,10 +208,20 @@ pub fn get_cantonal_scales(year: u32) -> Result<HashMap<String, CantonalScale>>
             trace!("Federal scale: {scale:?}");
             let single = is_single(&scale.group);
             let married = is_married(&scale.group);
-            let policy = canton_policy("CH")?;
-            if (single || married)
+            let single_parent = is_single_parent(&scale.group);
+            let policy = policy("CH")?;
+            if (single || married || single_parent)
                 && let Ok(table) = Table::try_from(scale, policy)
             {
+                if single_parent {
+                    cantonal_scales_single_parent.insert(
+                        "CH".into(),
+                        SingleParentScale {
+                            splitting: OrderedFloat(scale.splitting),
+                            table: table.clone(),
+                        },
+                    );
+                }
                 if single {
                     cantonal_scales_single.insert("CH".into(), table.clone());
                 }
@@ -222,12 +236,14 @@ pub fn get_cantonal_scales(year: u32) -> Result<HashMap<String, CantonalScale>>
     let mut cantonal_scales = HashMap::new();
     for (canton, table_single) in cantonal_scales_single {
         if let Some((splitting, table_married)) = cantonal_scales_married.remove(&canton) {
+            let single_parent = cantonal_scales_single_parent.remove(&canton);
             cantonal_scales.insert(
                 canton,
                 CantonalScale {
                     splitting: OrderedFloat(splitting),
                     single: table_single,
                     married: table_married,
+                    single_parent,
                 },
             );
         }
@@ -243,8 +259,37 @@ pub fn is_single(group: &[Group]) -> bool {
         && !group.contains(&Group::Verheiratet)
 }
 
+pub fn is_single_parent(group: &[Group]) -> bool {
+    group.contains(&Group::LedigMitKinder)
+}
+
 pub fn is_married(group: &[Group]) -> bool {
     group
         .iter()
         .any(|&x| x == Group::Alle || x == Group::Verheiratet)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testdata::{YEAR, make_data_dir};
+
+    #[test]
+    fn single_parent_scales() {
+        let dir = make_data_dir();
+        let scales = get_cantonal_scales(dir.path(), YEAR).unwrap();
+
+        // The federal married tariff also applies to single parents.
+        let federal = &scales["CH"];
+        assert_eq!(
+            federal.single_parent,
+            Some(SingleParentScale {
+                splitting: OrderedFloat(0.0),
+                table: federal.married.clone(),
+            })
+        );
+        assert_ne!(federal.single, federal.married);
+        assert_eq!(scales["ZH"].single_parent, None);
+        assert_eq!(scales["VD"].single_parent, None);
+    }
+}
@@ -1,74 +1,288 @@
 #![forbid(unsafe_code)]
-#![feature(iterator_try_collect, result_option_map_or_default)]
+#![feature(iterator_try_collect)]
 
+mod calculator;
+mod cli;
+mod compute;
+mod couple;
+mod db;
+mod deduction;
 mod examples;
 mod formula;
+mod generate;
 mod load;
 mod plot;
+mod probe;
+mod report;
+mod request;
 mod schema;
 mod table;
+#[cfg(test)]
+mod testdata;
 
-use anyhow::Result;
-use examples::{check_all_tests, fetch_examples};
+use anyhow::{Result, anyhow};
+use calculator::{CachedBackend, EstvBackend, LocalBackend, ThrottledBackend};
+use clap::Parser;
+use cli::{Backend, CantonArgs, Cli, Command};
+use compute::{compute_all_taxes, read_households, write_breakdowns};
+use couple::{HouseholdPair, extra_pairs};
+use db::{DECODE_BUDGET, Database, DbEncoding, default_source_date};
+use deduction::DeductionModel;
+use examples::{Tolerances, check_all_tests, fetch_examples};
 use load::{
-    Database, canton_policy, get_cantonal_rates, get_cantonal_scales, is_married, is_single,
+    CantonalScale, canton_policy, get_cantonal_rates, get_cantonal_scales, get_communal_rates,
+    is_married, is_single, load_deductions,
 };
 use log::{debug, info, trace, warn};
-use plot::{plot_all_income_tax, plot_income_tax};
+use plot::{
+    DiffScale, PlotOutput, plot_all_income_tax, plot_income_tax, plot_income_tax_diff,
+    plot_income_tax_layers,
+};
+use probe::{PROBE_BACKOFF, PROBE_RETRIES, probe_all_tests};
+use report::AccuracyReport;
 use schema::{Deductions, OtherDeductions, Rates, Scales, TableType, Target, TaxType};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant};
 use table::Table;
 
 fn main() -> Result<()> {
     env_logger::init();
 
-    check_data(2010, 2025)?;
-
-    fetch_examples(2010..=2025)?;
-    check_all_tests(2010..=2025)?;
-
-    if let Err(e) = Database::new(2010..=2025)?.serialize() {
-        warn!("Failed to serialize database: {e:?}");
-    }
+    let cli = Cli::parse();
+    let data_dir = cli.data_dir.as_path();
 
-    for year in [2010, 2015, 2020, 2025] {
-        plot_year(year)?;
+    match cli.command {
+        Command::Validate { years } => {
+            check_data(data_dir, years.years.iter())?;
+        }
+        Command::FetchExamples {
+            years,
+            cantons,
+            backend,
+            generator,
+            fetch,
+        } => {
+            let generator = generator.to_generator();
+            info!("Generating examples with {generator:?}");
+            let options = fetch.to_options();
+            match backend.backend {
+                Backend::Estv => fetch_examples(
+                    &CachedBackend::new(
+                        EstvBackend::new(&backend.base_url)?,
+                        fetch.cache_dir(data_dir),
+                    ),
+                    &generator,
+                    data_dir,
+                    years.years.iter(),
+                    &cantons,
+                    &options,
+                )?,
+                // The local evaluation changes with the code, so caching it
+                // would serve stale results.
+                Backend::Local => fetch_examples(
+                    &LocalBackend::new(data_dir),
+                    &generator,
+                    data_dir,
+                    years.years.iter(),
+                    &cantons,
+                    &options,
+                )?,
+            }
+        }
+        Command::Probe {
+            years,
+            cantons,
+            max_probes,
+            interval_ms,
+            backend,
+        } => match backend.backend {
+            Backend::Estv => probe_all_tests(
+                &ThrottledBackend::new(
+                    EstvBackend::new(&backend.base_url)?,
+                    Duration::from_millis(interval_ms),
+                    PROBE_RETRIES,
+                    PROBE_BACKOFF,
+                ),
+                data_dir,
+                years.years.iter(),
+                &cantons,
+                max_probes,
+            )?,
+            Backend::Local => probe_all_tests(
+                &LocalBackend::new(data_dir),
+                data_dir,
+                years.years.iter(),
+                &cantons,
+                max_probes,
+            )?,
+        },
+        Command::Check {
+            years,
+            cantons,
+            json,
+            html,
+            baseline,
+            tolerance,
+            overwrite,
+        } => {
+            let tolerances =
+                Tolerances::new(tolerance.into_iter().map(|t| (t.field, t.tolerance)))?;
+            let report = check_all_tests(data_dir, years.years.iter(), &cantons, &tolerances)?;
+            if let Some(path) = json {
+                report.write_json(&path, overwrite)?;
+            }
+            if let Some(path) = html {
+                report.write_html(&path, overwrite)?;
+            }
+            if let Some(path) = baseline {
+                let previous = AccuracyReport::load(&path)?;
+                if previous.tolerances != report.tolerances {
+                    warn!("The baseline report was computed with different tolerances");
+                }
+                let regressions = report.regressions(&previous);
+                for regression in &regressions {
+                    warn!("Regression: {regression}");
+                }
+                if !regressions.is_empty() {
+                    return Err(anyhow!(
+                        "Accuracy regressed in {} cases compared to {path:?}",
+                        regressions.len()
+                    ));
+                }
+            }
+        }
+        Command::BuildDb {
+            years,
+            cantons,
+            output_dir,
+            overwrite,
+            compact,
+            compression,
+            source_date,
+        } => {
+            let output_dir = output_dir.as_deref().unwrap_or(data_dir);
+            let source_date = match source_date {
+                Some(source_date) => source_date,
+                None => default_source_date(data_dir, years.years.iter())?,
+            };
+            let db = Database::new(data_dir, years.years.iter(), &cantons, source_date)?;
+            db.report_sizes()?;
+            let encoding = DbEncoding {
+                compact,
+                compression,
+            };
+            let path = db.serialize(output_dir, overwrite, encoding)?;
+            let start = Instant::now();
+            let (_, loaded) = Database::load(&path)?;
+            let elapsed = start.elapsed();
+            info!("Loaded {path:?} in {elapsed:?}");
+            if elapsed > DECODE_BUDGET {
+                warn!("Loading {path:?} took longer than {DECODE_BUDGET:?}");
+            }
+            db.verify(&loaded)
+                .map_err(|e| anyhow!("Database {path:?} doesn't round-trip: {e}"))?;
+            info!("Verified {path:?}");
+        }
+        Command::DbInfo { db, cantons } => {
+            let path = db.unwrap_or_else(|| data_dir.join("tables.db"));
+            let (header, db) = Database::load(&path)?;
+            db.print_summary(&header, &cantons);
+        }
+        Command::Plot {
+            years,
+            cantons,
+            colors,
+            contours,
+            percent_contours,
+            layers,
+            gross,
+            pairs,
+            output_dir,
+            overwrite,
+        } => {
+            let output = PlotOutput {
+                dir: &output_dir,
+                overwrite,
+                colors: colors.to_color_scale(false)?,
+                percent_colors: colors.to_color_scale(true)?,
+                contours,
+                percent_contours,
+                layers,
+                gross,
+                pairs: extra_pairs(&pairs, gross)?,
+            };
+            for year in years.years.iter() {
+                plot_year(data_dir, &output, year, &cantons)?;
+            }
+        }
+        Command::Report { years, cantons } => {
+            for year in years.years.iter() {
+                process_scales(data_dir, year, &cantons)?;
+            }
+        }
+        Command::Compute {
+            input,
+            household,
+            format,
+            output,
+        } => {
+            let households = match input {
+                Some(input) => read_households(&input)?,
+                None => vec![household.to_household()?],
+            };
+            let breakdowns = compute_all_taxes(data_dir, households)?;
+            match output {
+                Some(output) => {
+                    write_breakdowns(&breakdowns, format, BufWriter::new(File::create(output)?))?
+                }
+                None => write_breakdowns(&breakdowns, format, io::stdout().lock())?,
+            }
+        }
     }
 
-    for year in [2010, 2025] {
-        process_scales(year)?;
-    }
     Ok(())
 }
 
-fn check_data(start_year: u32, end_year: u32) -> Result<()> {
-    for year in start_year..=end_year {
+fn check_data(data_dir: &Path, years: impl Iterator<Item = u32>) -> Result<()> {
+    for year in years {
         info!("Validating year {year}...");
-        let _: Rates = serde_json::from_reader(BufReader::new(File::open(format!(
-            "data/rates-{year}.json"
-        ))?))?;
-        let _: Scales = serde_json::from_reader(BufReader::new(File::open(format!(
-            "data/scales-{year}.json"
-        ))?))?;
-        let _: Deductions = serde_json::from_reader(BufReader::new(File::open(format!(
-            "data/deductions-{year}.json"
-        ))?))?;
-        let _: OtherDeductions = serde_json::from_reader(BufReader::new(File::open(format!(
-            "data/other-deductions-{year}.json"
-        ))?))?;
+        let _: Rates = serde_json::from_reader(BufReader::new(File::open(
+            data_dir.join(format!("rates-{year}.json")),
+        )?))?;
+        let _: Scales = serde_json::from_reader(BufReader::new(File::open(
+            data_dir.join(format!("scales-{year}.json")),
+        )?))?;
+        let _: Deductions = serde_json::from_reader(BufReader::new(File::open(
+            data_dir.join(format!("deductions-{year}.json")),
+        )?))?;
+        let _: OtherDeductions = serde_json::from_reader(BufReader::new(File::open(
+            data_dir.join(format!("other-deductions-{year}.json")),
+        )?))?;
     }
 
     Ok(())
 }
 
-fn plot_year(year: u32) -> Result<()> {
-    let cantonal_rates = get_cantonal_rates(year)?;
-    let cantonal_scales = get_cantonal_scales(year)?;
+fn plot_year(data_dir: &Path, output: &PlotOutput, year: u32, cantons: &CantonArgs) -> Result<()> {
+    let cantonal_rates = get_cantonal_rates(data_dir, year)?;
+    let cantonal_scales = get_cantonal_scales(data_dir, year)?;
+    let communal_rates = get_communal_rates(data_dir, year)?;
+    let deductions = if output.gross {
+        Some(load_deductions(data_dir, year)?)
+    } else {
+        None
+    };
 
     for (canton, cantonal_rate) in &cantonal_rates {
+        if !cantons.contains(canton) {
+            continue;
+        }
         if let Some(cantonal_scale) = cantonal_scales.get(canton)
             && let Err(e) = plot_income_tax(
+                output,
                 canton,
                 year,
                 *cantonal_rate,
@@ -80,21 +294,140 @@ fn plot_year(year: u32) -> Result<()> {
             warn!("Failed to plot {canton} in {year}: {e:?}");
         }
 
-        if let Err(e) = plot_all_income_tax(year, &cantonal_rates, &cantonal_scales) {
-            warn!("Failed to plot {year}: {e:?}");
+        if (deductions.is_some() || !output.pairs.is_empty())
+            && canton != "VS"
+            && let Err(e) = plot_diffs(
+                output,
+                canton,
+                year,
+                *cantonal_rate,
+                &cantonal_scales,
+                deductions.as_ref(),
+            )
+        {
+            warn!("Failed to plot the tax differences of {canton} in {year}: {e:?}");
+        }
+        if output.layers
+            && canton != "CH"
+            && canton != "VS"
+            && let Err(e) = plot_layers(
+                output,
+                canton,
+                year,
+                *cantonal_rate,
+                &communal_rates,
+                &cantonal_scales,
+                deductions.as_ref(),
+            )
+        {
+            warn!("Failed to plot the layers of {canton} in {year}: {e:?}");
         }
     }
 
+    let selected: Vec<&str> = if cantons.cantons.is_empty() {
+        vec!["CH", "BL", "BS", "FR", "SZ", "UR", "VD"]
+    } else {
+        cantons.cantons.iter().map(|x| x.as_str()).collect()
+    };
+    if let Err(e) = plot_all_income_tax(output, year, &cantonal_rates, &cantonal_scales, &selected)
+    {
+        warn!("Failed to plot {year}: {e:?}");
+    }
+
     Ok(())
 }
 
-fn process_scales(year: u32) -> Result<()> {
-    let cantonal_rates = get_cantonal_rates(year)?;
+fn diff_scale<'a>(
+    canton: &str,
+    rate: f64,
+    cantonal_scales: &'a HashMap<String, CantonalScale>,
+    deductions: Option<&'a DeductionModel>,
+) -> Result<DiffScale<'a>> {
+    let scale = cantonal_scales
+        .get(canton)
+        .ok_or_else(|| anyhow!("No income tax scale for canton: {canton}"))?;
+    Ok(DiffScale {
+        rate,
+        splitting: *scale.splitting,
+        table_single: &scale.single,
+        table_married: &scale.married,
+        single_parent: scale.single_parent.as_ref(),
+        deductions,
+        pair: HouseholdPair::default(),
+    })
+}
+
+/// Plots the marriage penalties against gross salaries if deductions are
+/// given, and the differences between each household pair of the output.
+fn plot_diffs(
+    output: &PlotOutput,
+    canton: &str,
+    year: u32,
+    cantonal_rate: f64,
+    cantonal_scales: &HashMap<String, CantonalScale>,
+    deductions: Option<&Deductions>,
+) -> Result<()> {
+    let deductions = deductions.map(|deductions| deduction_model(deductions, canton));
+    let scale = diff_scale(canton, cantonal_rate, cantonal_scales, deductions.as_ref())?;
+    if deductions.is_some() {
+        plot_income_tax_diff(output, canton, year, &scale)?;
+    }
+    for &pair in &output.pairs {
+        plot_income_tax_diff(output, canton, year, &DiffScale { pair, ..scale })?;
+    }
+    Ok(())
+}
+
+/// Plots the layers of a canton, against gross salaries if deductions are
+/// given.
+fn plot_layers(
+    output: &PlotOutput,
+    canton: &str,
+    year: u32,
+    cantonal_rate: f64,
+    communal_rates: &HashMap<String, f64>,
+    cantonal_scales: &HashMap<String, CantonalScale>,
+    deductions: Option<&Deductions>,
+) -> Result<()> {
+    let communal_rate = communal_rates
+        .get(canton)
+        .ok_or_else(|| anyhow!("No communal rates for canton: {canton}"))?;
+    let federal_deductions = deductions.map(|deductions| deduction_model(deductions, "CH"));
+    let cantonal_deductions = deductions.map(|deductions| deduction_model(deductions, canton));
+    plot_income_tax_layers(
+        output,
+        canton,
+        year,
+        diff_scale("CH", 100.0, cantonal_scales, federal_deductions.as_ref())?,
+        diff_scale(
+            canton,
+            cantonal_rate,
+            cantonal_scales,
+            cantonal_deductions.as_ref(),
+        )?,
+        *communal_rate,
+    )
+}
+
+/// Returns the income tax deductions of a canton, or the federal ones for CH.
+fn deduction_model(deductions: &Deductions, canton: &str) -> DeductionModel {
+    let target = if canton == "CH" {
+        Target::Bund
+    } else {
+        Target::Kanton
+    };
+    DeductionModel::new(deductions, canton, target)
+}
+
+fn process_scales(data_dir: &Path, year: u32, cantons: &CantonArgs) -> Result<()> {
+    let cantonal_rates = get_cantonal_rates(data_dir, year)?;
     debug!("Cantonal rates: {cantonal_rates:?}");
 
-    let scales: Scales = serde_json::from_reader(BufReader::new(File::open(format!(
-        "data/scales-{year}.json"
-    ))?))?;
+    let scales: Scales = serde_json::from_reader(BufReader::new(File::open(
+        data_dir.join(format!("scales-{year}.json")),
+    )?))?;
+
+    println!("### {year} ###");
 
     println!("### Federal examples ###");
     println!(
@@ -107,6 +440,7 @@ fn process_scales(year: u32) -> Result<()> {
             scale.tax_type == TaxType::EinkommensSteuer
                 && scale.target == Target::Bund
                 && scale.location.canton_id == 1
+                && cantons.contains("CH")
         })
         .try_for_each(|scale| -> Result<()> {
             if let Ok(table) = Table::try_from(scale, canton_policy("CH")?) {
@@ -153,6 +487,7 @@ fn process_scales(year: u32) -> Result<()> {
                 scale.tax_type == TaxType::EinkommensSteuer
                     && scale.target == Target::Kanton
                     && scale.table_type == table_type
+                    && cantons.contains(&scale.location.canton)
             })
             .try_for_each(|scale| -> Result<()> {
                 let cantonal_rate = cantonal_rates.get(&scale.location.canton).unwrap();
@@ -1,7 +1,9 @@
 mod decorate;
 
 use crate::Table;
-use crate::load::CantonalScale;
+use crate::couple::{Couple, HouseholdPair};
+use crate::deduction::DeductionModel;
+use crate::load::{CantonalScale, SingleParentScale};
 use anyhow::Result;
 use decorate::make_line_styles;
 use log::{debug, info};
@@ -10,8 +12,47 @@ use plotters::prelude::*;
 use std::collections::HashMap;
 use std::fs;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
+use taxes_common::color::ColorScale;
+use taxes_common::contour::{contour_label, contours};
+
+pub struct PlotOutput<'a> {
+    pub dir: &'a Path,
+    pub overwrite: bool,
+    /// Colour scales of the heatmaps of absolute and relative differences.
+    pub colors: ColorScale,
+    pub percent_colors: ColorScale,
+    /// Levels of the contour lines of absolute and relative differences,
+    /// drawn at both signs besides the break-even line.
+    pub contours: Vec<f64>,
+    pub percent_contours: Vec<f64>,
+    /// Whether to also plot the federal, cantonal and communal layers of the
+    /// marriage penalties.
+    pub layers: bool,
+    /// Whether to also plot the marriage penalties against gross salaries,
+    /// and to plot the layers against them.
+    pub gross: bool,
+    /// Pairs of households whose tax differences are also plotted, besides
+    /// the default pair.
+    pub pairs: Vec<HouseholdPair>,
+}
+
+impl PlotOutput<'_> {
+    /// Returns the path of the given plot file, or `None` if the file exists
+    /// and shouldn't be overwritten.
+    fn path(&self, name: String) -> Option<PathBuf> {
+        let path = self.dir.join(name);
+        if !self.overwrite && path.exists() {
+            info!("Skipping existing plot: {path:?}");
+            None
+        } else {
+            Some(path)
+        }
+    }
+}
 
 pub fn plot_income_tax(
+    output: &PlotOutput,
     canton: &str,
     year: u32,
     cantonal_rate: f64,
@@ -23,9 +64,10 @@ pub fn plot_income_tax(
         info!("Creating plot for {canton} in {year} (rate={cantonal_rate}, split={splitting})");
         debug!("Single table: {table_single:?}");
         debug!("Married table: {table_married:?}");
-        fs::create_dir_all("plots")?;
+        fs::create_dir_all(output.dir)?;
 
         plot_income_rates(
+            output,
             canton,
             year,
             cantonal_rate,
@@ -34,6 +76,7 @@ pub fn plot_income_tax(
             table_married,
         )?;
         plot_income_rates_compare(
+            output,
             canton,
             year,
             cantonal_rate,
@@ -41,50 +84,107 @@ pub fn plot_income_tax(
             table_single,
             table_married,
         )?;
-        plot_income_diff_png(
-            canton,
-            year,
-            cantonal_rate,
+        let scale = DiffScale {
+            rate: cantonal_rate,
             splitting,
             table_single,
             table_married,
-        )?;
-        plot_income_percent_diff_png(
-            canton,
-            year,
-            cantonal_rate,
-            splitting,
-            table_single,
-            table_married,
-        )?;
+            single_parent: None,
+            deductions: None,
+            pair: HouseholdPair::default(),
+        };
+        plot_income_diff_png(output, canton, year, &scale)?;
+        plot_income_percent_diff_png(output, canton, year, &scale)?;
+    }
+    Ok(())
+}
+
+/// Plots the tax differences between the households of the given scale,
+/// against taxable incomes or, with deductions, gross salaries.
+pub fn plot_income_tax_diff(
+    output: &PlotOutput,
+    canton: &str,
+    year: u32,
+    scale: &DiffScale,
+) -> Result<()> {
+    info!(
+        "Creating {} plot for {canton} in {year} (rate={})",
+        scale.prefix(),
+        scale.rate
+    );
+    debug!("Deductions: {:?}", scale.deductions);
+    fs::create_dir_all(output.dir)?;
+
+    plot_income_diff_png(output, canton, year, scale)?;
+    plot_income_percent_diff_png(output, canton, year, scale)?;
+    Ok(())
+}
+
+/// Plots the marriage penalties of the federal, cantonal and communal income
+/// taxes, each in its own panel, along with their total.
+pub fn plot_income_tax_layers(
+    output: &PlotOutput,
+    canton: &str,
+    year: u32,
+    federal: DiffScale,
+    cantonal: DiffScale,
+    communal_rate: f64,
+) -> Result<()> {
+    let cantonal_rate = cantonal.rate;
+    info!(
+        "Creating layered plot for {canton} in {year} (rate={cantonal_rate}, communal rate={communal_rate})"
+    );
+    fs::create_dir_all(output.dir)?;
+
+    let prefix = cantonal.prefix();
+    let communal = DiffScale {
+        rate: communal_rate,
+        ..cantonal
+    };
+    let layers = [
+        ("Federal".to_owned(), federal),
+        (format!("Cantonal ({cantonal_rate}%)"), cantonal),
+        (format!("Communal (median {communal_rate}%)"), communal),
+    ];
+    for percent in [false, true] {
+        let name = if percent {
+            "percent-diff-layers"
+        } else {
+            "diff-layers"
+        };
+        if let Some(path) = output.path(format!("{prefix}-{name}-{canton}-{year}.png")) {
+            plot_income_layers_png(output, &path, &layers, percent)?;
+        }
     }
     Ok(())
 }
 
 pub fn plot_all_income_tax(
+    output: &PlotOutput,
     year: u32,
     cantonal_rates: &HashMap<String, f64>,
     cantonal_scales: &HashMap<String, CantonalScale>,
+    selected: &[&str],
 ) -> Result<()> {
-    plot_all_income_tax_selected(
-        year,
-        cantonal_rates,
-        cantonal_scales,
-        &["CH", "BL", "BS", "FR", "SZ", "UR", "VD"],
-    )?;
-    plot_all_income_tax_partial(year, cantonal_rates, cantonal_scales, 1, 0..8)?;
-    plot_all_income_tax_partial(year, cantonal_rates, cantonal_scales, 2, 8..16)?;
-    plot_all_income_tax_partial(year, cantonal_rates, cantonal_scales, 3, 16..25)?;
+    fs::create_dir_all(output.dir)?;
+
+    plot_all_income_tax_selected(output, year, cantonal_rates, cantonal_scales, selected)?;
+    plot_all_income_tax_partial(output, year, cantonal_rates, cantonal_scales, 1, 0..8)?;
+    plot_all_income_tax_partial(output, year, cantonal_rates, cantonal_scales, 2, 8..16)?;
+    plot_all_income_tax_partial(output, year, cantonal_rates, cantonal_scales, 3, 16..25)?;
     Ok(())
 }
 
 fn plot_all_income_tax_selected(
+    output: &PlotOutput,
     year: u32,
     cantonal_rates: &HashMap<String, f64>,
     cantonal_scales: &HashMap<String, CantonalScale>,
     cantons: &[&str],
 ) -> Result<()> {
-    let path = format!("plots/income-rates-selected-{year}.svg");
+    let Some(path) = output.path(format!("income-rates-selected-{year}.svg")) else {
+        return Ok(());
+    };
     let root = SVGBackend::new(&path, (800, 700)).into_drawing_area();
 
     let max_salary = 1_000_000;
@@ -153,13 +253,16 @@ fn plot_all_income_tax_selected(
 }
 
 fn plot_all_income_tax_partial(
+    output: &PlotOutput,
     year: u32,
     cantonal_rates: &HashMap<String, f64>,
     cantonal_scales: &HashMap<String, CantonalScale>,
     index: usize,
     range: Range<usize>,
 ) -> Result<()> {
-    let path = format!("plots/income-rates-{year}-{index}.svg");
+    let Some(path) = output.path(format!("income-rates-{year}-{index}.svg")) else {
+        return Ok(());
+    };
     let root = SVGBackend::new(&path, (800, 700)).into_drawing_area();
 
     let max_salary = 1_000_000;
@@ -236,6 +339,7 @@ fn plot_all_income_tax_partial(
 }
 
 fn plot_income_rates(
+    output: &PlotOutput,
     canton: &str,
     year: u32,
     cantonal_rate: f64,
@@ -243,7 +347,9 @@ fn plot_income_rates(
     table_single: &Table,
     table_married: &Table,
 ) -> Result<()> {
-    let path = format!("plots/income-rates-{canton}-{year}.svg");
+    let Some(path) = output.path(format!("income-rates-{canton}-{year}.svg")) else {
+        return Ok(());
+    };
     let root = SVGBackend::new(&path, (800, 700)).into_drawing_area();
 
     let line_styles = make_line_styles();
@@ -341,6 +447,7 @@ fn plot_income_rates(
 }
 
 fn plot_income_rates_compare(
+    output: &PlotOutput,
     canton: &str,
     year: u32,
     cantonal_rate: f64,
@@ -348,7 +455,9 @@ fn plot_income_rates_compare(
     table_single: &Table,
     table_married: &Table,
 ) -> Result<()> {
-    let path = format!("plots/income-rates-compare-{canton}-{year}.svg");
+    let Some(path) = output.path(format!("income-rates-compare-{canton}-{year}.svg")) else {
+        return Ok(());
+    };
     let root = SVGBackend::new(&path, (800, 700)).into_drawing_area();
 
     let line_styles = make_line_styles();
@@ -473,14 +582,15 @@ fn plot_income_rates_compare(
 }
 
 fn plot_income_diff_png(
+    output: &PlotOutput,
     canton: &str,
     year: u32,
-    cantonal_rate: f64,
-    splitting: f64,
-    table_single: &Table,
-    table_married: &Table,
+    scale: &DiffScale,
 ) -> Result<()> {
-    let path = format!("plots/income-diff-{canton}-{year}.png");
+    let prefix = scale.prefix();
+    let Some(path) = output.path(format!("{prefix}-diff-{canton}-{year}.png")) else {
+        return Ok(());
+    };
     let root = BitMapBackend::new(&path, (1000, 900)).into_drawing_area();
     root.fill(&WHITE)?;
 
@@ -499,7 +609,7 @@ fn plot_income_diff_png(
         .label_style(("sans-serif", 22))
         .x_labels(6)
         .y_labels(6)
-        .x_desc("Taxable income (person 1)")
+        .x_desc(scale.axis_label())
         .y_desc("Person 2")
         .axis_desc_style(("sans-serif", 26))
         .draw()?;
@@ -512,49 +622,31 @@ fn plot_income_diff_png(
 
     let mut min: f64 = -10.0;
     let mut max: f64 = 10.0;
+    let mut diffs = Vec::with_capacity((x_len * y_len) as usize);
     for i in 0..x_len {
         let x = (max_salary as f64 * i as f64) / x_len as f64;
         for j in 0..y_len {
             let y = (max_salary as f64 * j as f64) / y_len as f64;
 
-            let diff = get_diff(x, y, cantonal_rate, splitting, table_single, table_married);
+            let diff = scale.get_diff(x, y);
             if diff.is_nan() {
-                panic!("NaN in get_color({x}, {y}, {cantonal_rate}, {splitting}): diff={diff}");
+                panic!(
+                    "NaN in get_color({x}, {y}, {}, {}): diff={diff}",
+                    scale.rate, scale.splitting
+                );
             } else {
                 min = min.min(diff);
                 max = max.max(diff);
             }
-
-            plotting_area.draw_pixel((i, y_len - j - 1), &colorize(diff))?;
+            diffs.push(diff);
         }
     }
 
-    let mut legend = ChartBuilder::on(&legend_area)
-        .caption("Tax diff.", ("sans-serif", 26))
-        .margin_right(25)
-        .margin_top(200)
-        .margin_bottom(200)
-        .y_label_area_size(25)
-        .x_label_area_size(25)
-        .build_cartesian_2d(0..100, min.round() as i32..max.round() as i32)?;
-    legend
-        .configure_mesh()
-        .disable_mesh()
-        .disable_x_axis()
-        .label_style(("sans-serif", 22))
-        .draw()?;
-    let plotting_area = legend.plotting_area().strip_coord_spec();
-
-    let (range_x, range_y) = plotting_area.get_pixel_range();
-    let x_len = range_x.end - range_x.start;
-    let y_len = range_y.end - range_y.start;
+    let (colors, min, max) = output.colors.fit(min, max);
+    draw_heatmap(&plotting_area, &colors, &diffs)?;
+    draw_contours(&plotting_area, &diffs, &output.contours, false)?;
 
-    for j in 0..y_len {
-        let salary = (max - min) * j as f64 / y_len as f64 + min;
-        for i in 0..x_len {
-            plotting_area.draw_pixel((i, y_len - j - 1), &colorize(salary))?;
-        }
-    }
+    draw_legend(&legend_area, &colors, min, max, false)?;
 
     root.present()?;
 
@@ -562,14 +654,15 @@ fn plot_income_diff_png(
 }
 
 fn plot_income_percent_diff_png(
+    output: &PlotOutput,
     canton: &str,
     year: u32,
-    cantonal_rate: f64,
-    splitting: f64,
-    table_single: &Table,
-    table_married: &Table,
+    scale: &DiffScale,
 ) -> Result<()> {
-    let path = format!("plots/income-percent-diff-{canton}-{year}.png");
+    let prefix = scale.prefix();
+    let Some(path) = output.path(format!("{prefix}-percent-diff-{canton}-{year}.png")) else {
+        return Ok(());
+    };
     let root = BitMapBackend::new(&path, (1000, 900)).into_drawing_area();
     root.fill(&WHITE)?;
 
@@ -588,7 +681,7 @@ fn plot_income_percent_diff_png(
         .label_style(("sans-serif", 22))
         .x_labels(6)
         .y_labels(6)
-        .x_desc("Taxable income (person 1)")
+        .x_desc(scale.axis_label())
         .y_desc("Person 2")
         .axis_desc_style(("sans-serif", 26))
         .draw()?;
@@ -601,6 +694,7 @@ fn plot_income_percent_diff_png(
 
     let mut min: f64 = -0.1;
     let mut max: f64 = 0.1;
+    let mut diffs = Vec::with_capacity((x_len * y_len) as usize);
     for i in 0..x_len {
         let x = (max_salary as f64 * i as f64) / x_len as f64;
         for j in 0..y_len {
@@ -610,144 +704,368 @@ fn plot_income_percent_diff_png(
             let diff = if denom == 0.0 {
                 0.0
             } else {
-                100.0 * get_diff(x, y, cantonal_rate, splitting, table_single, table_married)
-                    / denom
+                100.0 * scale.get_diff(x, y) / denom
             };
             if diff.is_nan() {
-                panic!("NaN in get_color({x}, {y}, {cantonal_rate}, {splitting}): diff={diff}");
+                panic!(
+                    "NaN in get_color({x}, {y}, {}, {}): diff={diff}",
+                    scale.rate, scale.splitting
+                );
             } else {
                 min = min.min(diff);
                 max = max.max(diff);
             }
+            diffs.push(diff);
+        }
+    }
+
+    let (colors, min, max) = output.percent_colors.fit(min, max);
+    draw_heatmap(&plotting_area, &colors, &diffs)?;
+    draw_contours(&plotting_area, &diffs, &output.percent_contours, true)?;
+
+    draw_legend(&legend_area, &colors, min, max, true)?;
+
+    root.present()?;
+
+    Ok(())
+}
 
-            plotting_area.draw_pixel((i, y_len - j - 1), &colorize_percent(diff))?;
+/// Scale of one tax in the marriage penalty heatmaps, comparing the taxes of
+/// a pair of households. With deductions, the axes are gross salaries, from
+/// which the taxable incomes of each household are derived.
+#[derive(Clone, Copy, Debug)]
+pub struct DiffScale<'a> {
+    pub rate: f64,
+    pub splitting: f64,
+    pub table_single: &'a Table,
+    pub table_married: &'a Table,
+    /// Tariff of single parents, or the single tariff if `None`.
+    pub single_parent: Option<&'a SingleParentScale>,
+    pub deductions: Option<&'a DeductionModel>,
+    pub pair: HouseholdPair,
+}
+
+impl DiffScale<'_> {
+    fn get_diff(&self, x: f64, y: f64) -> f64 {
+        let tax_first = self.simple_tax(self.pair.first, x, y);
+        let tax_second = self.simple_tax(self.pair.second, x, y);
+        (tax_first - tax_second) * self.rate / 100.0
+    }
+
+    /// Returns the tax of a couple whose partners earn the given incomes,
+    /// before the multiplier.
+    fn simple_tax(&self, couple: Couple, x: f64, y: f64) -> f64 {
+        match couple {
+            Couple::Married { children } => {
+                let income = match self.deductions {
+                    None => x + y,
+                    Some(deductions) => deductions.taxable_married(x, y, children),
+                };
+                self.table_married.eval_split(income, self.splitting)
+            }
+            Couple::Cohabiting {
+                children_1,
+                children_2,
+            } => self.simple_tax_single(x, children_1) + self.simple_tax_single(y, children_2),
         }
     }
 
-    let precision = if max - min >= 0.8 { 1 } else { 2 };
-    let mut legend = ChartBuilder::on(&legend_area)
-        .caption("Tax diff.", ("sans-serif", 26))
-        .margin_right(25)
-        .margin_top(200)
-        .margin_bottom(200)
-        .y_label_area_size(25)
-        .x_label_area_size(25)
-        .build_cartesian_2d(0..100, min..max)?;
-    legend
-        .configure_mesh()
-        .disable_mesh()
-        .disable_x_axis()
-        .y_label_formatter(&|percent| format!("{percent:.*}%", precision))
-        .label_style(("sans-serif", 22))
-        .draw()?;
-    let plotting_area = legend.plotting_area().strip_coord_spec();
+    fn simple_tax_single(&self, x: f64, children: u32) -> f64 {
+        let income = match self.deductions {
+            None => x,
+            Some(deductions) => deductions.taxable_single(x, children),
+        };
+        match self.single_parent {
+            Some(scale) if children > 0 => scale.eval(income),
+            _ => self.table_single.eval(income),
+        }
+    }
 
-    let (range_x, range_y) = plotting_area.get_pixel_range();
-    let x_len = range_x.end - range_x.start;
-    let y_len = range_y.end - range_y.start;
+    /// Returns the prefix of the heatmap file names.
+    fn prefix(&self) -> String {
+        let income = match self.deductions {
+            None => "income",
+            Some(_) => "gross-income",
+        };
+        if self.pair == HouseholdPair::default() {
+            income.to_owned()
+        } else {
+            format!("{income}-{}", self.pair)
+        }
+    }
 
-    for j in 0..y_len {
-        let percent = (max - min) * j as f64 / y_len as f64 + min;
+    fn axis_label(&self) -> &'static str {
+        match self.deductions {
+            None => "Taxable income (person 1)",
+            Some(_) => "Gross salary (person 1)",
+        }
+    }
+}
+
+fn plot_income_layers_png(
+    output: &PlotOutput,
+    path: &Path,
+    layers: &[(String, DiffScale)],
+    percent: bool,
+) -> Result<()> {
+    let root = BitMapBackend::new(path, (1350, 1200)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let (panels_area, legend_area) = root.split_horizontally(1200);
+    let panels = panels_area.split_evenly(((layers.len() + 1).div_ceil(2), 2));
+
+    // Differences of each layer, followed by their total.
+    let max_salary = 500_000;
+    let (mut min, mut max): (f64, f64) = if percent { (-0.1, 0.1) } else { (-10.0, 10.0) };
+    let mut heatmaps = Vec::new();
+    for (k, area) in panels.iter().enumerate().take(layers.len() + 1) {
+        let caption = layers
+            .get(k)
+            .map_or("Total", |(caption, _)| caption.as_str());
+        let mut chart = ChartBuilder::on(area)
+            .caption(caption, ("sans-serif", 26))
+            .margin(20)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0..max_salary, 0..max_salary)?;
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .label_style(("sans-serif", 16))
+            .x_labels(6)
+            .y_labels(6)
+            .draw()?;
+
+        let plotting_area = chart.plotting_area().strip_coord_spec();
+        let (range_x, range_y) = plotting_area.get_pixel_range();
+        let x_len = range_x.end - range_x.start;
+        let y_len = range_y.end - range_y.start;
+
+        let mut diffs = Vec::with_capacity((x_len * y_len) as usize);
         for i in 0..x_len {
-            plotting_area.draw_pixel((i, y_len - j - 1), &colorize_percent(percent))?;
+            let x = (max_salary as f64 * i as f64) / x_len as f64;
+            for j in 0..y_len {
+                let y = (max_salary as f64 * j as f64) / y_len as f64;
+                let diff = match layers.get(k) {
+                    Some((_, layer)) => layer.get_diff(x, y),
+                    None => layers.iter().map(|(_, layer)| layer.get_diff(x, y)).sum(),
+                };
+                let diff = if !percent {
+                    diff
+                } else if x + y == 0.0 {
+                    0.0
+                } else {
+                    100.0 * diff / (x + y)
+                };
+                if diff.is_nan() {
+                    panic!("NaN in layer {caption} at ({x}, {y})");
+                }
+                min = min.min(diff);
+                max = max.max(diff);
+                diffs.push(diff);
+            }
         }
+        heatmaps.push((plotting_area, diffs));
     }
 
+    // All panels share the same colours, to compare the layers.
+    let (colors, min, max) = if percent {
+        output.percent_colors.fit(min, max)
+    } else {
+        output.colors.fit(min, max)
+    };
+    let levels = if percent {
+        &output.percent_contours
+    } else {
+        &output.contours
+    };
+    for (plotting_area, diffs) in &heatmaps {
+        draw_heatmap(plotting_area, &colors, diffs)?;
+        draw_contours(plotting_area, diffs, levels, percent)?;
+    }
+    draw_legend(&legend_area, &colors, min, max, percent)?;
+
     root.present()?;
 
     Ok(())
 }
 
-fn get_diff(
-    x: f64,
-    y: f64,
-    cantonal_rate: f64,
-    splitting: f64,
-    table_single: &Table,
-    table_married: &Table,
-) -> f64 {
-    let tax_married = table_married.eval_split(x + y, splitting);
-    let tax_singles = table_single.eval(x) + table_single.eval(y);
-    (tax_singles - tax_married) * cantonal_rate / 100.0
+/// Draws the differences computed column by column from the bottom left of
+/// the plotting area.
+fn draw_heatmap<DB: DrawingBackend>(
+    plotting_area: &DrawingArea<DB, plotters::coord::Shift>,
+    colors: &ColorScale,
+    diffs: &[f64],
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let (range_x, range_y) = plotting_area.get_pixel_range();
+    let y_len = range_y.end - range_y.start;
+    for i in 0..range_x.end - range_x.start {
+        for j in 0..y_len {
+            let diff = diffs[(i * y_len + j) as usize];
+            plotting_area.draw_pixel((i, y_len - j - 1), &colors.color(diff))?;
+        }
+    }
+    Ok(())
 }
 
-fn colorize(diff: f64) -> RGBColor {
-    let c0 = RGBColor(0xc0, 0xc0, 0xc0);
-    let m10 = RGBColor(0xc0, 0xa0, 0xa0);
-    let m3000 = RGBColor(0xe0, 0xa0, 0x00);
-    let m8000 = RGBColor(0xc0, 0x40, 0x40);
-    let m12000 = RGBColor(0xa0, 0x60, 0x80);
-    let p10 = RGBColor(0x80, 0xa0, 0xc0);
-    let p3000 = RGBColor(0x20, 0xa0, 0xa0);
-    let p6000 = RGBColor(0x50, 0xa0, 0x60);
-    let p9000 = RGBColor(0x40, 0xc0, 0x00);
-    let p12000 = RGBColor(0x80, 0xc0, 0x00);
-
-    if (-10.0..=10.0).contains(&diff) {
-        c0
-    } else if (-3000.0..=-10.0).contains(&diff) {
-        interpolate(m10, m3000, -10.0, -3000.0, diff)
-    } else if (-8000.0..=-3000.0).contains(&diff) {
-        interpolate(m3000, m8000, -3000.0, -8000.0, diff)
-    } else if diff < 0.0 {
-        interpolate(m8000, m12000, -8000.0, -12000.0, diff)
-    } else if (10.0..=3000.0).contains(&diff) {
-        interpolate(p10, p3000, 10.0, 3000.0, diff)
-    } else if (3000.0..=6000.0).contains(&diff) {
-        interpolate(p3000, p6000, 3000.0, 6000.0, diff)
-    } else if (6000.0..=9000.0).contains(&diff) {
-        interpolate(p6000, p9000, 6000.0, 9000.0, diff)
-    } else if diff > 0.0 {
-        interpolate(p9000, p12000, 9000.0, 12000.0, diff)
-    } else {
-        panic!("NaN in colorize(diff={diff})");
+/// Draws the labelled contour lines of the differences at zero and at both
+/// signs of the given levels.
+fn draw_contours<DB: DrawingBackend>(
+    plotting_area: &DrawingArea<DB, plotters::coord::Shift>,
+    diffs: &[f64],
+    levels: &[f64],
+    percent: bool,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let (range_x, range_y) = plotting_area.get_pixel_range();
+    let x_len = (range_x.end - range_x.start) as usize;
+    let y_len = (range_y.end - range_y.start) as usize;
+    let levels: Vec<f64> = std::iter::once(0.0)
+        .chain(levels.iter().flat_map(|&level| [-level, level]))
+        .collect();
+    let to_pixel = |(x, y): (f64, f64)| (x.round() as i32, (y_len as f64 - 1.0 - y).round() as i32);
+
+    for contour in contours(x_len, y_len, |i, j| diffs[i * y_len + j], &levels) {
+        let style = if contour.level == 0.0 {
+            BLACK.stroke_width(2)
+        } else {
+            BLACK.mix(0.6).stroke_width(1)
+        };
+        for segment in &contour.segments {
+            plotting_area.draw(&PathElement::new(segment.map(to_pixel), style))?;
+        }
+        if let Some(position) = contour.label_position() {
+            plotting_area.draw(&Text::new(
+                contour_label(contour.level, percent),
+                to_pixel(position),
+                ("sans-serif", 18),
+            ))?;
+        }
     }
+    Ok(())
 }
 
-fn colorize_percent(diff: f64) -> RGBColor {
-    let c0 = RGBColor(0xc0, 0xc0, 0xc0);
-    let m025 = RGBColor(0xc0, 0xa0, 0xa0);
-    let m1 = RGBColor(0xe0, 0xa0, 0x00);
-    let m3 = RGBColor(0xc0, 0x40, 0x40);
-    let m5 = RGBColor(0xa0, 0x60, 0x80);
-    let p025 = RGBColor(0x80, 0xa0, 0xc0);
-    let p1 = RGBColor(0x20, 0xa0, 0xa0);
-    let p3 = RGBColor(0x50, 0xa0, 0x60);
-    let p5 = RGBColor(0x40, 0xc0, 0x00);
-    let p8 = RGBColor(0x80, 0xc0, 0x00);
-
-    if (-0.025..=0.025).contains(&diff) {
-        c0
-    } else if (-1.0..=-0.025).contains(&diff) {
-        interpolate(m025, m1, -0.025, -1.0, diff)
-    } else if (-3.0..=-1.0).contains(&diff) {
-        interpolate(m1, m3, -1.0, -3.0, diff)
-    } else if diff < 0.0 {
-        interpolate(m3, m5, -3.0, -5.0, diff)
-    } else if (0.025..=1.0).contains(&diff) {
-        interpolate(p025, p1, 0.025, 1.0, diff)
-    } else if (1.0..=3.0).contains(&diff) {
-        interpolate(p1, p3, 1.0, 3.0, diff)
-    } else if (3.0..=5.0).contains(&diff) {
-        interpolate(p3, p5, 3.0, 5.0, diff)
-    } else if diff > 0.0 {
-        interpolate(p5, p8, 5.0, 8.0, diff)
+/// Draws the legend of a heatmap, with the colours of the given scale from
+/// min to max.
+fn draw_legend<DB: DrawingBackend>(
+    legend_area: &DrawingArea<DB, plotters::coord::Shift>,
+    colors: &ColorScale,
+    min: f64,
+    max: f64,
+    percent: bool,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let vertical_margin = legend_area.dim_in_pixel().1 * 2 / 9;
+    let mut builder = ChartBuilder::on(legend_area);
+    builder
+        .caption("Tax diff.", ("sans-serif", 26))
+        .margin_right(25)
+        .margin_top(vertical_margin)
+        .margin_bottom(vertical_margin)
+        .y_label_area_size(25)
+        .x_label_area_size(25);
+    let plotting_area = if percent {
+        let precision = if max - min >= 0.8 { 1 } else { 2 };
+        let mut legend = builder.build_cartesian_2d(0..100, min..max)?;
+        legend
+            .configure_mesh()
+            .disable_mesh()
+            .disable_x_axis()
+            .y_label_formatter(&|percent| format!("{percent:.*}%", precision))
+            .label_style(("sans-serif", 22))
+            .draw()?;
+        legend.plotting_area().strip_coord_spec()
     } else {
-        panic!("NaN in colorize(diff={diff})");
+        let mut legend =
+            builder.build_cartesian_2d(0..100, min.round() as i32..max.round() as i32)?;
+        legend
+            .configure_mesh()
+            .disable_mesh()
+            .disable_x_axis()
+            .label_style(("sans-serif", 22))
+            .draw()?;
+        legend.plotting_area().strip_coord_spec()
+    };
+
+    let (range_x, range_y) = plotting_area.get_pixel_range();
+    let x_len = range_x.end - range_x.start;
+    let y_len = range_y.end - range_y.start;
+
+    for j in 0..y_len {
+        let value = (max - min) * j as f64 / y_len as f64 + min;
+        for i in 0..x_len {
+            plotting_area.draw_pixel((i, y_len - j - 1), &colors.color(value))?;
+        }
     }
+    Ok(())
 }
 
-fn interpolate(color1: RGBColor, color2: RGBColor, start: f64, end: f64, value: f64) -> RGBColor {
-    let x = (value - start) / (end - start);
-    if x <= 0.0 {
-        color1
-    } else if x >= 1.0 {
-        color2
-    } else {
-        RGBColor(
-            (color1.0 as f64 * (1.0 - x) + color2.0 as f64 * x) as u8,
-            (color1.1 as f64 * (1.0 - x) + color2.1 as f64 * x) as u8,
-            (color1.2 as f64 * (1.0 - x) + color2.2 as f64 * x) as u8,
-        )
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::load::{get_cantonal_scales, load_deductions};
+    use crate::schema::Target;
+    use crate::testdata::{YEAR, make_data_dir};
+
+    #[test]
+    fn simple_tax() {
+        let dir = make_data_dir();
+        let scales = get_cantonal_scales(dir.path(), YEAR).unwrap();
+        let deductions = load_deductions(dir.path(), YEAR).unwrap();
+        let model = DeductionModel::new(&deductions, "CH", Target::Bund);
+        let federal = &scales["CH"];
+        let scale = DiffScale {
+            rate: 100.0,
+            splitting: *federal.splitting,
+            table_single: &federal.single,
+            table_married: &federal.married,
+            single_parent: federal.single_parent.as_ref(),
+            deductions: None,
+            pair: HouseholdPair::default(),
+        };
+
+        // Taxable incomes.
+        let singles = Couple::Cohabiting {
+            children_1: 0,
+            children_2: 0,
+        };
+        assert_eq!(scale.simple_tax(singles, 60_000.0, 40_000.0), 900.0);
+        assert_eq!(
+            scale.simple_tax(Couple::Married { children: 0 }, 60_000.0, 40_000.0),
+            1_500.0
+        );
+        assert_eq!(scale.get_diff(60_000.0, 40_000.0), -600.0);
+
+        // Gross salaries: 84'092 CHF for the single parent after the child
+        // deduction, 81'292 CHF for the married couple.
+        let scale = DiffScale {
+            deductions: Some(&model),
+            pair: "cohabiting-1-0:married-1".parse().unwrap(),
+            ..scale
+        };
+        let parent = Couple::Cohabiting {
+            children_1: 1,
+            children_2: 0,
+        };
+        assert_eq!(scale.simple_tax(parent, 100_000.0, 0.0), 1_020.0);
+        assert_eq!(
+            scale.simple_tax(Couple::Married { children: 1 }, 100_000.0, 0.0),
+            936.0
+        );
+        assert_eq!(scale.get_diff(100_000.0, 0.0), 84.0);
+
+        // Without a single parent tariff, the single one applies.
+        let scale = DiffScale {
+            single_parent: None,
+            ..scale
+        };
+        assert_eq!(scale.simple_tax(parent, 100_000.0, 0.0), 1_370.0);
     }
 }
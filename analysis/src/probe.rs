@@ -0,0 +1,425 @@
+use crate::calculator::CalculatorBackend;
+use crate::cli::CantonArgs;
+use crate::compute::TaxData;
+use crate::examples::{Evaluation, Relationship, Response, TestResult, Tolerances, load_tests};
+use crate::load::CantonalScale;
+use crate::request::Request;
+use anyhow::{Result, anyhow};
+use log::{debug, info, warn};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Incomes above this bound aren't probed.
+const MAX_INCOME: u32 = 10_000_000;
+/// Distance to a bracket start under which a threshold is attributed to it.
+const BRACKET_TOLERANCE: f64 = 100.0;
+/// Mismatches are probed without tolerance.
+const EXACT: Tolerances = Tolerances::exact();
+/// Number of retries of a probe failing with a server error or a timeout.
+pub const PROBE_RETRIES: u32 = 3;
+/// Delay before the first retry of a probe, doubled at each subsequent retry.
+pub const PROBE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Interval of incomes around an example over which our evaluation diverges
+/// from the calculator.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// Lowest mismatching income.
+    pub from: u32,
+    /// Highest mismatching income, or `None` if the mismatch extends up to
+    /// [`MAX_INCOME`].
+    pub to: Option<u32>,
+    pub cause: Cause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// The simple tax matches, but not the cantonal tax.
+    Multiplier,
+    /// The simple tax differs by at most 1 CHF.
+    Rounding,
+    /// A threshold is close to the start of a bracket.
+    BracketBoundary,
+    /// The single tariff matches at the split income, but not the married one.
+    Splitting,
+    Unknown,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to {
+            Some(to) => write!(f, "[{}, {}]", self.from, to)?,
+            None => write!(f, "[{}, ...]", self.from)?,
+        }
+        write!(f, " ({:?})", self.cause)
+    }
+}
+
+pub fn probe_all_tests(
+    backend: &impl CalculatorBackend,
+    data_dir: &Path,
+    years: impl Iterator<Item = u32>,
+    canton_filter: &CantonArgs,
+    max_probes: usize,
+) -> Result<()> {
+    let rt = Runtime::new()?;
+
+    rt.block_on(async {
+        let mut probes = 0;
+        for year in years {
+            if probes >= max_probes {
+                break;
+            }
+            info!("Probing examples for {year}");
+            let tests = load_tests(data_dir, year)?;
+            let data = TaxData::load(data_dir, year)?;
+
+            for test in tests {
+                if probes >= max_probes {
+                    break;
+                }
+                let canton = &test.response.response.location.canton;
+                if !canton_filter.contains(canton) {
+                    continue;
+                }
+                if data.income_scale(canton).is_none() || !test.request.is_modeled() {
+                    continue;
+                }
+                let result = TestResult {
+                    expected: Evaluation::from(&test.response.response),
+                    actual: Evaluation::evaluate(&test.request, &data)?,
+                };
+                if result.is_match(&EXACT) {
+                    continue;
+                }
+
+                probes += 1;
+                let request = &test.request;
+                match probe(backend, request, &data).await {
+                    Ok(divergence) => println!(
+                        "[{canton}, {year}, {:?}] income {}: {}, mismatch over {divergence}",
+                        request.relationship(),
+                        request.taxable_income_canton,
+                        result
+                            .mismatches(&EXACT)
+                            .map(|(field, expected, actual)| format!(
+                                "{field} expected {expected} got {actual}"
+                            ))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    ),
+                    Err(e) => warn!(
+                        "[{canton}, {year}] Failed to probe income {}: {e:?}",
+                        request.taxable_income_canton
+                    ),
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Finds the interval of incomes around the given mismatching request over
+/// which our evaluation diverges from the backend, and classifies it.
+pub async fn probe(
+    backend: &impl CalculatorBackend,
+    request: &Request,
+    data: &TaxData,
+) -> Result<Divergence> {
+    let location = data
+        .location(request.tax_location_id)
+        .ok_or_else(|| anyhow!("Unknown tax location: {}", request.tax_location_id))?;
+    let scale = data
+        .income_scale(&location.canton)
+        .ok_or_else(|| anyhow!("No income tax scale for canton: {}", location.canton))?;
+    let prober = Prober {
+        backend,
+        data,
+        scale,
+    };
+    let income = request.taxable_income_canton;
+    let result = prober.query(request, income).await?;
+    if result.is_match(&EXACT) {
+        return Err(anyhow!("No mismatch at income {income}"));
+    }
+
+    let from = prober.lower_threshold(request, income).await?;
+    let to = prober.upper_threshold(request, income).await?;
+    debug!("Mismatch between {from} and {to:?}");
+
+    let cause = prober.classify(request, &result, from, to).await?;
+    Ok(Divergence { from, to, cause })
+}
+
+struct Prober<'a, B> {
+    backend: &'a B,
+    data: &'a TaxData,
+    scale: &'a CantonalScale,
+}
+
+impl<B: CalculatorBackend> Prober<'_, B> {
+    /// Queries the backend for the request at the given income, and compares
+    /// it with our evaluation.
+    async fn query(&self, request: &Request, income: u32) -> Result<TestResult> {
+        let mut request = request.clone();
+        request.taxable_income_canton = income;
+        request.taxable_income_fed = income;
+
+        let response: Response = serde_json::from_value(self.backend.calculate(&request).await?)?;
+        Ok(TestResult {
+            expected: Evaluation::from(&response.response),
+            actual: Evaluation::evaluate(&request, self.data)?,
+        })
+    }
+
+    async fn mismatches(&self, request: &Request, income: u32) -> Result<bool> {
+        Ok(!self.query(request, income).await?.is_match(&EXACT))
+    }
+
+    /// Returns the lowest income of the mismatching interval containing the
+    /// request's income.
+    async fn lower_threshold(&self, request: &Request, income: u32) -> Result<u32> {
+        // Exponential search for a matching income below.
+        let mut mismatch = income;
+        let mut step = 1;
+        let matching = loop {
+            let candidate = income.saturating_sub(step);
+            if !self.mismatches(request, candidate).await? {
+                break candidate;
+            }
+            mismatch = candidate;
+            if candidate == 0 {
+                return Ok(0);
+            }
+            step *= 2;
+        };
+
+        // Bisection between the matching and mismatching incomes.
+        let (mut lo, mut hi) = (matching, mismatch);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if self.mismatches(request, mid).await? {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Ok(hi)
+    }
+
+    /// Returns the highest income of the mismatching interval containing the
+    /// request's income, or `None` if it extends up to [`MAX_INCOME`].
+    async fn upper_threshold(&self, request: &Request, income: u32) -> Result<Option<u32>> {
+        if income >= MAX_INCOME {
+            return Ok(None);
+        }
+        let mut mismatch = income;
+        let mut step = 1;
+        let matching = loop {
+            let candidate = income.saturating_add(step).min(MAX_INCOME);
+            if candidate == mismatch {
+                return Ok(None);
+            }
+            if !self.mismatches(request, candidate).await? {
+                break candidate;
+            }
+            mismatch = candidate;
+            step *= 2;
+        };
+
+        let (mut lo, mut hi) = (mismatch, matching);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if self.mismatches(request, mid).await? {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(Some(lo))
+    }
+
+    async fn classify(
+        &self,
+        request: &Request,
+        result: &TestResult,
+        from: u32,
+        to: Option<u32>,
+    ) -> Result<Cause> {
+        let (expected, actual) = (&result.expected, &result.actual);
+        let simple_tax_error = actual
+            .income_simple_tax_canton
+            .zip(expected.income_simple_tax_canton)
+            .map(|(actual, expected)| (actual - expected).abs());
+        let tax_matches = actual.income_tax_canton == expected.income_tax_canton
+            && actual
+                .income_tax_city
+                .is_none_or(|actual| Some(actual) == expected.income_tax_city);
+        match simple_tax_error {
+            Some(0.0) if !tax_matches => return Ok(Cause::Multiplier),
+            Some(0.0) => return Ok(Cause::Unknown),
+            Some(error) if error <= 1.0 => return Ok(Cause::Rounding),
+            _ => (),
+        }
+
+        let relationship = request.relationship();
+        let splitting = *self.scale.splitting;
+        let (table, factor) = match relationship {
+            Relationship::Single => (&self.scale.single, 1.0),
+            Relationship::Married if splitting == 0.0 => (&self.scale.married, 1.0),
+            Relationship::Married => (&self.scale.married, splitting),
+        };
+        let near_bracket = |threshold: u32| {
+            table
+                .bracket_starts()
+                .iter()
+                .any(|start| (start * factor - threshold as f64).abs() <= BRACKET_TOLERANCE)
+        };
+        // The lower threshold is the first mismatching income, the upper
+        // one the last mismatching income before a match.
+        if near_bracket(from) || to.is_some_and(|to| near_bracket(to + 1)) {
+            return Ok(Cause::BracketBoundary);
+        }
+
+        if relationship == Relationship::Married && splitting != 0.0 {
+            let income = (request.taxable_income_canton as f64 / splitting).round() as u32;
+            let single = Request::builder(request.tax_year, request.tax_location_id)
+                .confession(request.confession)
+                .income(income)
+                .fortune(request.taxable_fortune)
+                .build()?;
+            if !self.mismatches(&single, income).await? {
+                return Ok(Cause::Splitting);
+            }
+        }
+
+        Ok(Cause::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calculator::LocalBackend;
+    use crate::request::CivilStatus;
+    use crate::testdata::{YEAR, make_data_dir};
+    use serde_json::Value;
+    use std::ops::Range;
+
+    /// Local backend whose response is perturbed over a range of incomes.
+    struct PerturbedBackend {
+        inner: LocalBackend,
+        range: Range<u32>,
+        perturb: fn(&Request, &mut Value),
+    }
+
+    impl CalculatorBackend for PerturbedBackend {
+        async fn calculate(&self, request: &Request) -> Result<Value> {
+            let mut json = self.inner.calculate(request).await?;
+            if self.range.contains(&request.taxable_income_canton) {
+                (self.perturb)(request, &mut json["response"]);
+            }
+            Ok(json)
+        }
+    }
+
+    fn add(value: &mut Value, delta: f64) {
+        *value = (value.as_f64().unwrap() + delta).into();
+    }
+
+    fn probe_with(
+        request: Request,
+        range: Range<u32>,
+        perturb: fn(&Request, &mut Value),
+    ) -> Divergence {
+        let dir = make_data_dir();
+        let data = TaxData::load(dir.path(), YEAR).unwrap();
+        let backend = PerturbedBackend {
+            inner: LocalBackend::new(dir.path()),
+            range,
+            perturb,
+        };
+        Runtime::new()
+            .unwrap()
+            .block_on(probe(&backend, &request, &data))
+            .unwrap()
+    }
+
+    /// Probes a single person in Zürich, whose simple cantonal tax is off by
+    /// 50 CHF over the given range of incomes.
+    fn probe_zh(range: Range<u32>, income: u32) -> Divergence {
+        let request = Request::builder(YEAR, 100).income(income).build().unwrap();
+        probe_with(request, range, |_, response| {
+            add(&mut response["IncomeSimpleTaxCanton"], 50.0)
+        })
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(
+            probe_zh(60_000..70_000, 65_000),
+            Divergence {
+                from: 60_000,
+                to: Some(69_999),
+                cause: Cause::Unknown,
+            }
+        );
+        // The top bracket of the test scale starts at 34'000 CHF.
+        assert_eq!(
+            probe_zh(34_000..MAX_INCOME + 1, 50_000),
+            Divergence {
+                from: 34_000,
+                to: None,
+                cause: Cause::BracketBoundary,
+            }
+        );
+        // Examples above the probed incomes only have a lower threshold.
+        assert_eq!(
+            probe_zh(MAX_INCOME + 1..u32::MAX, MAX_INCOME + 5_000),
+            Divergence {
+                from: MAX_INCOME + 1,
+                to: None,
+                cause: Cause::Unknown,
+            }
+        );
+    }
+
+    #[test]
+    fn causes() {
+        let single = Request::builder(YEAR, 100).income(65_000).build().unwrap();
+        let divergence =
+            |request: &Request, perturb| probe_with(request.clone(), 60_000..70_000, perturb).cause;
+        assert_eq!(
+            divergence(&single, |_, response| add(
+                &mut response["IncomeSimpleTaxCanton"],
+                1.0
+            )),
+            Cause::Rounding
+        );
+        assert_eq!(
+            divergence(&single, |_, response| add(
+                &mut response["IncomeTaxCanton"],
+                50.0
+            )),
+            Cause::Multiplier
+        );
+
+        // The married tariff of Lausanne has a splitting of 1.9, and only
+        // married couples are off.
+        let married = Request::builder(YEAR, 200)
+            .civil_status(CivilStatus::Married)
+            .income(65_000)
+            .build()
+            .unwrap();
+        assert_eq!(
+            divergence(&married, |request, response| {
+                if request.relationship() == Relationship::Married {
+                    add(&mut response["IncomeSimpleTaxCanton"], 50.0);
+                }
+            }),
+            Cause::Splitting
+        );
+    }
+}
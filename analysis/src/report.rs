@@ -0,0 +1,404 @@
+use crate::examples::{
+    CheckedTest, Evaluation, HISTOGRAM_BOUNDS, Matches, Relationship, Tolerances,
+};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Accuracy of our evaluation against the test examples, aggregated by year,
+/// canton and relationship.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccuracyReport {
+    /// Tolerances used to decide whether values match.
+    pub tolerances: Tolerances,
+    pub total: Stats,
+    pub by_year: BTreeMap<u32, Stats>,
+    pub by_canton: BTreeMap<String, Stats>,
+    pub by_relationship: BTreeMap<Relationship, Stats>,
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Number of examples.
+    pub examples: usize,
+    /// Number of examples for which we could evaluate the taxes.
+    pub checked: usize,
+    /// Fraction of the values that we compute, over all fields.
+    pub coverage: Option<f64>,
+    pub matches: Matches,
+}
+
+/// Example for which at least one field doesn't match, with the mismatching
+/// fields.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failure {
+    pub year: u32,
+    pub canton: String,
+    pub relationship: Relationship,
+    pub tax_location_id: u32,
+    pub taxable_income_canton: u32,
+    pub fields: Vec<FieldValues>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FieldValues {
+    pub field: String,
+    pub expected: f64,
+    pub actual: f64,
+}
+
+impl AccuracyReport {
+    pub fn new(tolerances: Tolerances) -> Self {
+        Self {
+            tolerances,
+            total: Stats::default(),
+            by_year: BTreeMap::new(),
+            by_canton: BTreeMap::new(),
+            by_relationship: BTreeMap::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| anyhow!("Failed to open {path:?}: {e}"))?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn add(&mut self, year: u32, test: &CheckedTest) {
+        for stats in [
+            &mut self.total,
+            self.by_year.entry(year).or_default(),
+            self.by_canton.entry(test.canton.clone()).or_default(),
+            self.by_relationship.entry(test.relationship).or_default(),
+        ] {
+            stats.add(test, &self.tolerances);
+        }
+
+        if let Some(result) = &test.result
+            && !result.is_match(&self.tolerances)
+        {
+            self.failures.push(Failure {
+                year,
+                canton: test.canton.clone(),
+                relationship: test.relationship,
+                tax_location_id: test.request.tax_location_id,
+                taxable_income_canton: test.request.taxable_income_canton,
+                fields: result
+                    .mismatches(&self.tolerances)
+                    .map(|(field, expected, actual)| FieldValues {
+                        field: field.to_owned(),
+                        expected,
+                        actual,
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    /// Returns the groups and fields whose match rate is lower than in the
+    /// previous report.
+    pub fn regressions(&self, previous: &AccuracyReport) -> Vec<String> {
+        let mut regressions = Vec::new();
+        let mut compare = |group: String, current: &Stats, previous: &Stats| {
+            for field in Evaluation::<f64>::FIELDS {
+                if let (Some(rate), Some(previous_rate)) =
+                    (current.match_rate(field), previous.match_rate(field))
+                    && rate < previous_rate
+                {
+                    regressions.push(format!(
+                        "{group}, {field}: {:.2}% -> {:.2}%",
+                        previous_rate * 100.0,
+                        rate * 100.0
+                    ));
+                }
+            }
+        };
+
+        compare("total".into(), &self.total, &previous.total);
+        for (year, stats) in &self.by_year {
+            if let Some(previous) = previous.by_year.get(year) {
+                compare(year.to_string(), stats, previous);
+            }
+        }
+        for (canton, stats) in &self.by_canton {
+            if let Some(previous) = previous.by_canton.get(canton) {
+                compare(canton.clone(), stats, previous);
+            }
+        }
+        for (relationship, stats) in &self.by_relationship {
+            if let Some(previous) = previous.by_relationship.get(relationship) {
+                compare(format!("{relationship:?}"), stats, previous);
+            }
+        }
+        regressions
+    }
+
+    pub fn write_json(&self, path: &Path, overwrite: bool) -> Result<()> {
+        let file = create_file(path, overwrite)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn write_html(&self, path: &Path, overwrite: bool) -> Result<()> {
+        let mut w = BufWriter::new(create_file(path, overwrite)?);
+        writeln!(
+            w,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Accuracy report</title>"
+        )?;
+        writeln!(
+            w,
+            "<style>table {{ border-collapse: collapse; }} td, th {{ border: 1px solid #ccc; padding: 2px 6px; text-align: right; }}</style>"
+        )?;
+        writeln!(w, "</head>\n<body>\n<h1>Accuracy report</h1>")?;
+
+        write_stats_table(&mut w, "Total", [("all", &self.total)])?;
+        write_fields_table(&mut w, &self.total.matches)?;
+        write_stats_table(&mut w, "By year", &self.by_year)?;
+        write_stats_table(&mut w, "By canton", &self.by_canton)?;
+        write_stats_table(
+            &mut w,
+            "By relationship",
+            self.by_relationship
+                .iter()
+                .map(|(relationship, stats)| (format!("{relationship:?}"), stats)),
+        )?;
+
+        writeln!(w, "<h2>Failures ({})</h2>", self.failures.len())?;
+        writeln!(
+            w,
+            "<table>\n<tr><th>Year</th><th>Canton</th><th>Relationship</th><th>Location</th><th>Income</th><th>Field</th><th>Expected</th><th>Actual</th></tr>"
+        )?;
+        for failure in &self.failures {
+            for (i, values) in failure.fields.iter().enumerate() {
+                write!(w, "<tr>")?;
+                if i == 0 {
+                    let rows = failure.fields.len();
+                    write!(
+                        w,
+                        "<td rowspan=\"{rows}\">{}</td><td rowspan=\"{rows}\">{}</td><td rowspan=\"{rows}\">{:?}</td><td rowspan=\"{rows}\">{}</td><td rowspan=\"{rows}\">{}</td>",
+                        failure.year,
+                        escape(&failure.canton),
+                        failure.relationship,
+                        failure.tax_location_id,
+                        failure.taxable_income_canton,
+                    )?;
+                }
+                writeln!(
+                    w,
+                    "<td>{}</td><td>{}</td><td>{}</td></tr>",
+                    values.field, values.expected, values.actual
+                )?;
+            }
+        }
+        writeln!(w, "</table>\n</body>\n</html>")?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+impl Stats {
+    fn add(&mut self, test: &CheckedTest, tolerances: &Tolerances) {
+        self.examples += 1;
+        if let Some(result) = &test.result {
+            self.checked += 1;
+            self.matches += result.matches(tolerances);
+            self.coverage = self.matches.coverage();
+        }
+    }
+
+    /// Returns the match rate of each field, or `None` if no example was
+    /// checked.
+    fn match_rate(&self, field: &str) -> Option<f64> {
+        self.matches
+            .get(field)
+            .and_then(|matches| matches.match_rate())
+    }
+}
+
+fn write_stats_table<'a, K: Display>(
+    w: &mut impl Write,
+    title: &str,
+    rows: impl IntoIterator<Item = (K, &'a Stats)>,
+) -> Result<()> {
+    writeln!(w, "<h2>{title}</h2>\n<table>")?;
+    write!(
+        w,
+        "<tr><th></th><th>Examples</th><th>Checked</th><th>Coverage</th>"
+    )?;
+    for field in Evaluation::<f64>::FIELDS {
+        write!(w, "<th>{field}</th>")?;
+    }
+    writeln!(w, "</tr>")?;
+
+    for (key, stats) in rows {
+        write!(
+            w,
+            "<tr><th>{}</th><td>{}</td><td>{}</td><td>{}</td>",
+            escape(&key.to_string()),
+            stats.examples,
+            stats.checked,
+            percent(stats.coverage)
+        )?;
+        for field in Evaluation::<f64>::FIELDS {
+            match stats.matches.get(field) {
+                Some(m) if m.count == 0 && m.unsupported != 0 => write!(w, "<td>unsupported</td>")?,
+                m => write!(w, "<td>{}</td>", percent(m.and_then(|m| m.match_rate())))?,
+            }
+        }
+        writeln!(w, "</tr>")?;
+    }
+    writeln!(w, "</table>")?;
+    Ok(())
+}
+
+fn write_fields_table(w: &mut impl Write, matches: &Matches) -> Result<()> {
+    writeln!(w, "<h2>Fields</h2>\n<table>")?;
+    write!(
+        w,
+        "<tr><th></th><th>Compared</th><th>Matches</th><th>Unsupported</th><th>Mean abs error</th><th>Max abs error</th><th>Mean rel error</th><th>Max rel error</th>"
+    )?;
+    for bound in HISTOGRAM_BOUNDS {
+        write!(w, "<th>&le; {bound} CHF</th>")?;
+    }
+    writeln!(
+        w,
+        "<th>&gt; {} CHF</th></tr>",
+        HISTOGRAM_BOUNDS[HISTOGRAM_BOUNDS.len() - 1]
+    )?;
+    for (field, m) in matches.iter() {
+        write!(
+            w,
+            "<tr><th>{field}</th><td>{}</td><td>{}</td><td>{}</td><td>{:.2}</td><td>{:.2}</td><td>{}</td><td>{}</td>",
+            m.count,
+            m.matches,
+            m.unsupported,
+            m.mean_abs_error,
+            m.max_abs_error,
+            percent(Some(m.mean_rel_error)),
+            percent(Some(m.max_rel_error))
+        )?;
+        for count in m.histogram {
+            write!(w, "<td>{count}</td>")?;
+        }
+        writeln!(w, "</tr>")?;
+    }
+    writeln!(w, "</table>")?;
+    Ok(())
+}
+
+fn percent(value: Option<f64>) -> String {
+    match value {
+        Some(value) => format!("{:.2}%", value * 100.0),
+        None => "-".into(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn create_file(path: &Path, overwrite: bool) -> Result<File> {
+    let file = if overwrite {
+        File::create(path)?
+    } else {
+        File::create_new(path)?
+    };
+    Ok(file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::calculator::LocalBackend;
+    use crate::cli::CantonArgs;
+    use crate::examples::{FetchOptions, Tolerance, check_all_tests, fetch_examples};
+    use crate::generate::{Generator, Household, Strategy};
+    use crate::testdata::{YEAR, make_data_dir};
+    use serde_json::Value;
+
+    #[test]
+    fn report_and_regressions() {
+        let dir = make_data_dir();
+        let generator = Generator {
+            seed: 0,
+            strategies: vec![Strategy::Grid],
+            locations_per_canton: Some(1),
+            samples: 1,
+            households: Household::defaults(),
+            church: false,
+        };
+        let all = CantonArgs { cantons: vec![] };
+        fetch_examples(
+            &LocalBackend::new(dir.path()),
+            &generator,
+            dir.path(),
+            [YEAR].into_iter(),
+            &all,
+            &FetchOptions::default(),
+        )
+        .unwrap();
+
+        let report = check_all_tests(dir.path(), [YEAR], &all, &Tolerances::exact()).unwrap();
+        assert_eq!(report.total.examples, 2 * 2 * 11);
+        assert_eq!(report.total.checked, report.total.examples);
+        assert!(report.failures.is_empty());
+        assert_eq!(report.by_canton["ZH"].examples, 2 * 11);
+        // The local backend doesn't return the personal tax, and we don't
+        // compute the total tax.
+        assert!(report.total.matches.get("personal_tax").is_none());
+        let total_tax = report.total.matches.get("total_tax").unwrap();
+        assert_eq!(total_tax.unsupported, report.total.examples);
+        assert!(report.total.coverage.unwrap() < 1.0);
+        assert_eq!(
+            report.by_relationship[&Relationship::Married].examples,
+            2 * 11
+        );
+
+        let path = dir.path().join("report.json");
+        report.write_json(&path, false).unwrap();
+        let previous = AccuracyReport::load(&path).unwrap();
+        assert!(report.regressions(&previous).is_empty());
+
+        // Increase the cantonal multiplier of VD by about 3%.
+        let rates_path = dir.path().join(format!("rates-{YEAR}.json"));
+        let mut rates: Value =
+            serde_json::from_reader(BufReader::new(File::open(&rates_path).unwrap())).unwrap();
+        for rate in rates["response"].as_array_mut().unwrap() {
+            if rate["Location"]["Canton"] == "VD" {
+                rate["IncomeRateCanton"] = 160.0.into();
+            }
+        }
+        serde_json::to_writer(File::create(&rates_path).unwrap(), &rates).unwrap();
+
+        let report = check_all_tests(dir.path(), [YEAR], &all, &Tolerances::exact()).unwrap();
+        let regressions = report.regressions(&previous);
+        // Total, year, canton and both relationships.
+        assert_eq!(regressions.len(), 5, "{regressions:?}");
+        assert!(regressions.iter().all(|r| r.contains("income_tax_canton")));
+        assert!(!report.failures.is_empty());
+        report
+            .write_html(&dir.path().join("report.html"), false)
+            .unwrap();
+
+        let tolerances = Tolerances::new([(
+            "*".to_owned(),
+            Tolerance {
+                abs: 0.0,
+                rel: 0.05,
+            },
+        )])
+        .unwrap();
+        let report = check_all_tests(dir.path(), [YEAR], &all, &tolerances).unwrap();
+        assert!(report.regressions(&previous).is_empty());
+        assert!(report.failures.is_empty());
+        let matches = report.total.matches.get("income_tax_canton").unwrap();
+        assert!(matches.max_abs_error > 0.0);
+    }
+}
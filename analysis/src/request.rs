@@ -0,0 +1,339 @@
+use crate::examples::Relationship;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Request to the ESTV tax calculator.
+///
+/// The field order matters: it's the serialization order, on which the
+/// [digest](Request::digest) of a request depends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "PascalCase")]
+pub struct Request {
+    pub children: Vec<Child>,
+    #[serde(rename = "Confession1")]
+    pub confession: Confession,
+    /// Confession of the partner, only for couples.
+    #[serde(rename = "Confession2", with = "partner_confession")]
+    pub partner_confession: Option<Confession>,
+    #[serde(rename = "Relationship")]
+    pub civil_status: CivilStatus,
+    pub taxable_fortune: u32,
+    pub taxable_income_canton: u32,
+    pub taxable_income_fed: u32,
+    #[serde(rename = "TaxLocationID")]
+    pub tax_location_id: u32,
+    pub tax_year: u32,
+}
+
+/// Civil status, encoded as in the ESTV calculator.
+///
+/// Only the codes 1 (single) and 2 (married) appear in the examples fetched
+/// so far. The code 3 for unmarried partners is an assumption, which hasn't
+/// been checked against a response of the calculator yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum CivilStatus {
+    Single,
+    /// Married couple, or registered partnership.
+    Married,
+    /// Unmarried partners living together.
+    Concubinage,
+}
+
+impl CivilStatus {
+    pub fn is_couple(self) -> bool {
+        match self {
+            CivilStatus::Single => false,
+            CivilStatus::Married | CivilStatus::Concubinage => true,
+        }
+    }
+
+    /// Returns the tariff that applies to this civil status. Unmarried
+    /// partners are taxed separately.
+    pub fn relationship(self) -> Relationship {
+        match self {
+            CivilStatus::Single | CivilStatus::Concubinage => Relationship::Single,
+            CivilStatus::Married => Relationship::Married,
+        }
+    }
+}
+
+impl From<CivilStatus> for u32 {
+    fn from(status: CivilStatus) -> u32 {
+        match status {
+            CivilStatus::Single => 1,
+            CivilStatus::Married => 2,
+            CivilStatus::Concubinage => 3,
+        }
+    }
+}
+
+impl TryFrom<u32> for CivilStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(code: u32) -> Result<Self> {
+        match code {
+            1 => Ok(CivilStatus::Single),
+            2 => Ok(CivilStatus::Married),
+            3 => Ok(CivilStatus::Concubinage),
+            _ => Err(anyhow!("Unknown relationship type: {code}")),
+        }
+    }
+}
+
+/// Confession, which determines the church tax. Encoded as the `Confession1`
+/// and `Confession2` fields sent by the web interface of the ESTV calculator
+/// (<https://swisstaxcalculator.estv.admin.ch>), whose options are listed in
+/// this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum Confession {
+    Protestant,
+    RomanCatholic,
+    ChristCatholic,
+    Other,
+    /// No confession, hence no church tax.
+    None,
+}
+
+impl From<Confession> for u32 {
+    fn from(confession: Confession) -> u32 {
+        match confession {
+            Confession::Protestant => 1,
+            Confession::RomanCatholic => 2,
+            Confession::ChristCatholic => 3,
+            Confession::Other => 4,
+            Confession::None => 5,
+        }
+    }
+}
+
+impl TryFrom<u32> for Confession {
+    type Error = anyhow::Error;
+
+    fn try_from(code: u32) -> Result<Self> {
+        match code {
+            1 => Ok(Confession::Protestant),
+            2 => Ok(Confession::RomanCatholic),
+            3 => Ok(Confession::ChristCatholic),
+            4 => Ok(Confession::Other),
+            5 => Ok(Confession::None),
+            _ => Err(anyhow!("Unknown confession: {code}")),
+        }
+    }
+}
+
+/// The ESTV calculator encodes a missing partner confession as 0.
+mod partner_confession {
+    use super::Confession;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        confession: &Option<Confession>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(confession.map_or(0, u32::from))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Confession>, D::Error> {
+        match u32::deserialize(deserializer)? {
+            0 => Ok(None),
+            code => Confession::try_from(code)
+                .map(Some)
+                .map_err(D::Error::custom),
+        }
+    }
+}
+
+/// Child of the household.
+///
+/// The examples fetched so far have no children, so the `{"Age": ...}` shape
+/// hasn't been checked against a response of the ESTV calculator yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "PascalCase")]
+pub struct Child {
+    pub age: u32,
+}
+
+impl Request {
+    pub fn builder(tax_year: u32, tax_location_id: u32) -> RequestBuilder {
+        RequestBuilder {
+            request: Request {
+                children: Vec::new(),
+                confession: Confession::None,
+                partner_confession: None,
+                civil_status: CivilStatus::Single,
+                taxable_fortune: 0,
+                taxable_income_canton: 0,
+                taxable_income_fed: 0,
+                tax_location_id,
+                tax_year,
+            },
+        }
+    }
+
+    pub fn relationship(&self) -> Relationship {
+        self.civil_status.relationship()
+    }
+
+    /// Whether our own evaluation models this request. It ignores children,
+    /// and doesn't split the income of unmarried partners.
+    pub fn is_modeled(&self) -> bool {
+        self.children.is_empty() && self.civil_status != CivilStatus::Concubinage
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    /// Returns a hash of this request, identifying it in test files and in the
+    /// response cache.
+    pub fn digest(&self) -> String {
+        Sha256::digest(serde_json::to_string(self).unwrap())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// Builder of [`Request`]s, defaulting to a single person without confession,
+/// children, income or fortune.
+#[derive(Clone)]
+pub struct RequestBuilder {
+    request: Request,
+}
+
+impl RequestBuilder {
+    /// Sets the civil status. The partner of a couple has no confession unless
+    /// set with [`partner_confession`](Self::partner_confession), and a single
+    /// person has no partner confession.
+    pub fn civil_status(mut self, civil_status: CivilStatus) -> Self {
+        self.request.civil_status = civil_status;
+        if !civil_status.is_couple() {
+            self.request.partner_confession = None;
+        } else if self.request.partner_confession.is_none() {
+            self.request.partner_confession = Some(Confession::None);
+        }
+        self
+    }
+
+    pub fn confession(mut self, confession: Confession) -> Self {
+        self.request.confession = confession;
+        self
+    }
+
+    pub fn partner_confession(mut self, confession: Confession) -> Self {
+        self.request.partner_confession = Some(confession);
+        self
+    }
+
+    pub fn child(mut self, age: u32) -> Self {
+        self.request.children.push(Child { age });
+        self
+    }
+
+    /// Sets the same taxable income for the cantonal and federal taxes.
+    pub fn income(self, income: u32) -> Self {
+        self.income_canton(income).income_fed(income)
+    }
+
+    pub fn income_canton(mut self, income: u32) -> Self {
+        self.request.taxable_income_canton = income;
+        self
+    }
+
+    pub fn income_fed(mut self, income: u32) -> Self {
+        self.request.taxable_income_fed = income;
+        self
+    }
+
+    pub fn fortune(mut self, fortune: u32) -> Self {
+        self.request.taxable_fortune = fortune;
+        self
+    }
+
+    pub fn build(self) -> Result<Request> {
+        let request = self.request;
+        if !request.civil_status.is_couple() && request.partner_confession.is_some() {
+            return Err(anyhow!("A single person has no partner confession"));
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn wire_format() {
+        let single = Request::builder(2025, 100)
+            .income(50_000)
+            .fortune(10_000)
+            .build()
+            .unwrap();
+        assert_eq!(
+            single.to_json(),
+            json!({
+                "Children": [],
+                "Confession1": 5,
+                "Confession2": 0,
+                "Relationship": 1,
+                "TaxableFortune": 10_000,
+                "TaxableIncomeCanton": 50_000,
+                "TaxableIncomeFed": 50_000,
+                "TaxLocationID": 100,
+                "TaxYear": 2025,
+            })
+        );
+
+        let family = Request::builder(2025, 100)
+            .civil_status(CivilStatus::Married)
+            .confession(Confession::RomanCatholic)
+            .child(3)
+            .child(7)
+            .build()
+            .unwrap();
+        let json = family.to_json();
+        assert_eq!(json["Relationship"], 2);
+        assert_eq!(json["Confession1"], 2);
+        assert_eq!(json["Confession2"], 5);
+        assert_eq!(json["Children"], json!([{ "Age": 3 }, { "Age": 7 }]));
+
+        let parsed: Request = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.digest(), family.digest());
+        assert_eq!(parsed.relationship(), Relationship::Married);
+        assert!(!parsed.is_modeled());
+
+        let single = Request::builder(2025, 100)
+            .civil_status(CivilStatus::Married)
+            .partner_confession(Confession::Protestant)
+            .civil_status(CivilStatus::Single)
+            .build()
+            .unwrap();
+        assert_eq!(single.partner_confession, None);
+        assert_eq!(single.to_json()["Confession2"], 0);
+        assert!(single.is_modeled());
+    }
+
+    #[test]
+    fn invalid_requests() {
+        assert!(
+            Request::builder(2025, 100)
+                .partner_confession(Confession::Protestant)
+                .build()
+                .is_err()
+        );
+
+        let mut json = Request::builder(2025, 100).build().unwrap().to_json();
+        json["Relationship"] = 7.into();
+        let error = serde_json::from_value::<Request>(json).unwrap_err();
+        assert!(error.to_string().contains("Unknown relationship type: 7"));
+    }
+}
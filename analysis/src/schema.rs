@@ -1,5 +1,5 @@
 use serde::de::{self, Visitor};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::{self, Display};
 use std::marker::PhantomData;
 use std::str::FromStr;
@@ -19,16 +19,16 @@ pub struct Rate {
     capital_tax_rate_canton: f64,
     capital_tax_rate_church: f64,
     capital_tax_rate_city: f64,
-    fortune_rate_canton: f64,
-    fortune_rate_christ: f64,
-    fortune_rate_city: f64,
-    fortune_rate_protestant: f64,
-    fortune_rate_roman: f64,
+    pub fortune_rate_canton: f64,
+    pub fortune_rate_christ: f64,
+    pub fortune_rate_city: f64,
+    pub fortune_rate_protestant: f64,
+    pub fortune_rate_roman: f64,
     pub income_rate_canton: f64,
-    income_rate_christ: f64,
-    income_rate_city: f64,
-    income_rate_protestant: f64,
-    income_rate_roman: f64,
+    pub income_rate_christ: f64,
+    pub income_rate_city: f64,
+    pub income_rate_protestant: f64,
+    pub income_rate_roman: f64,
     profit_tax_rate_canton: f64,
     profit_tax_rate_church: f64,
     profit_tax_rate_city: f64,
@@ -76,38 +76,35 @@ pub enum TableType {
 }
 
 // Deductions
-#[expect(dead_code)]
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Deductions {
-    response: Vec<Deduction>,
+    pub response: Vec<Deduction>,
 }
 
-#[expect(dead_code)]
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "PascalCase")]
-struct Deduction {
-    location: Location,
-    target: Target,
-    tax_type: TaxType,
-    table: Vec<DeductionEntry>,
+pub struct Deduction {
+    pub location: Location,
+    pub target: Target,
+    pub tax_type: TaxType,
+    pub table: Vec<DeductionEntry>,
 }
 
-#[expect(dead_code)]
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "PascalCase")]
-struct DeductionEntry {
-    minimum: f64,
-    maximum: f64,
+pub struct DeductionEntry {
+    pub minimum: f64,
+    pub maximum: f64,
     #[serde(deserialize_with = "comma_separated")]
-    format: Vec<Format>,
-    percent: f64,
-    amount: f64,
-    name: Name,
+    pub format: Vec<Format>,
+    pub percent: f64,
+    pub amount: f64,
+    pub name: Name,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum Format {
+pub enum Format {
     Maximum,
     Minimum,
     Percent,
@@ -152,26 +149,26 @@ struct OtherDeduction {
 }
 
 // Common
-#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "PascalCase")]
 pub struct Location {
     #[serde(rename = "BfsID")]
-    bfs_id: u32,
-    bfs_name: String,
+    pub bfs_id: u32,
+    pub bfs_name: String,
     #[serde(rename = "CantonID")]
     pub canton_id: u32,
     pub canton: String,
-    city: String,
+    pub city: String,
     #[serde(rename = "TaxLocationID")]
     pub tax_location_id: u32,
-    zip_code: String,
+    pub zip_code: String,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "UPPERCASE")]
-struct Name {
+pub struct Name {
     id: String,
-    de: String,
+    pub de: String,
     en: String,
     fr: String,
     it: String,
@@ -3,10 +3,11 @@ use crate::schema::{Scale, ScaleEntry, TableType};
 use anyhow::anyhow;
 use log::{debug, warn};
 use ordered_float::OrderedFloat;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use taxes_common::compact::{Deltas, Number, zip_columns};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Table {
     table: RawTable,
     policy: EvalPolicy,
@@ -46,9 +47,156 @@ impl Table {
             EvalPolicy::Valais => self.table.eval_split_raw(x, split),
         }
     }
+
+    /// Returns the start of the last bracket of this table, above which the
+    /// marginal rate doesn't change anymore.
+    pub fn top_bracket(&self) -> f64 {
+        self.bracket_starts().last().copied().unwrap_or_default()
+    }
+
+    /// Returns the incomes at which the marginal rate of this table changes.
+    pub fn bracket_starts(&self) -> Vec<f64> {
+        self.table.bracket_starts()
+    }
+}
+
+/// Compact encoding of a [`Table`], with columns of [`Number`]s and
+/// delta-encoded bracket starts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompactTable {
+    table: CompactRawTable,
+    policy: EvalPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum CompactRawTable {
+    Bund {
+        bracket_starts: Deltas,
+        base_taxes: Vec<Number>,
+        marginal_rates: Vec<Number>,
+    },
+    Flattax(Number),
+    Formel {
+        bracket_starts: Deltas,
+        formulas: Vec<Formula>,
+    },
+    Freiburg {
+        bracket_starts: Deltas,
+        tax_rates: Vec<Number>,
+    },
+    Zuerich {
+        bracket_lens: Vec<Number>,
+        marginal_rates: Vec<Number>,
+    },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+fn numbers<T>(entries: &[T], f: impl Fn(&T) -> OrderedFloat<f64>) -> Vec<Number> {
+    entries.iter().map(|entry| f(entry).0.into()).collect()
+}
+
+impl From<&Table> for CompactTable {
+    fn from(table: &Table) -> Self {
+        let compact = match &table.table {
+            RawTable::Bund(TableBund(entries)) => CompactRawTable::Bund {
+                bracket_starts: Deltas::encode(entries.iter().map(|entry| *entry.bracket_start)),
+                base_taxes: numbers(entries, |entry| entry.base_tax),
+                marginal_rates: numbers(entries, |entry| entry.marginal_rate),
+            },
+            RawTable::Flattax(TableFlattax(rate)) => CompactRawTable::Flattax(rate.0.into()),
+            RawTable::Formel(TableFormel(entries)) => CompactRawTable::Formel {
+                bracket_starts: Deltas::encode(entries.iter().map(|entry| *entry.bracket_start)),
+                formulas: entries.iter().map(|entry| entry.formula.clone()).collect(),
+            },
+            RawTable::Freiburg(TableFreiburg(entries)) => CompactRawTable::Freiburg {
+                bracket_starts: Deltas::encode(entries.iter().map(|entry| *entry.bracket_start)),
+                tax_rates: numbers(entries, |entry| entry.tax_rate),
+            },
+            RawTable::Zuerich(TableZuerich(entries)) => CompactRawTable::Zuerich {
+                bracket_lens: numbers(entries, |entry| entry.bracket_len),
+                marginal_rates: numbers(entries, |entry| entry.marginal_rate),
+            },
+        };
+        CompactTable {
+            table: compact,
+            policy: table.policy,
+        }
+    }
+}
+
+impl TryFrom<CompactTable> for Table {
+    type Error = anyhow::Error;
+
+    fn try_from(compact: CompactTable) -> Result<Self, Self::Error> {
+        Ok(Table {
+            table: compact.table.try_into().map_err(anyhow::Error::msg)?,
+            policy: compact.policy,
+        })
+    }
+}
+
+impl TryFrom<CompactRawTable> for RawTable {
+    type Error = String;
+
+    fn try_from(compact: CompactRawTable) -> Result<Self, String> {
+        let float = |number: Number| OrderedFloat(f64::from(number));
+        Ok(match compact {
+            CompactRawTable::Bund {
+                bracket_starts,
+                base_taxes,
+                marginal_rates,
+            } => {
+                let rates = zip_columns(base_taxes, marginal_rates, |base_tax, marginal_rate| {
+                    (float(base_tax), float(marginal_rate))
+                })?;
+                RawTable::Bund(TableBund(zip_columns(
+                    bracket_starts.decode()?,
+                    rates,
+                    |bracket_start, (base_tax, marginal_rate)| TableBundEntry {
+                        bracket_start: OrderedFloat(bracket_start),
+                        base_tax,
+                        marginal_rate,
+                    },
+                )?))
+            }
+            CompactRawTable::Flattax(rate) => RawTable::Flattax(TableFlattax(float(rate))),
+            CompactRawTable::Formel {
+                bracket_starts,
+                formulas,
+            } => RawTable::Formel(TableFormel(zip_columns(
+                bracket_starts.decode()?,
+                formulas,
+                |bracket_start, formula| TableFormelEntry {
+                    bracket_start: OrderedFloat(bracket_start),
+                    formula,
+                },
+            )?)),
+            CompactRawTable::Freiburg {
+                bracket_starts,
+                tax_rates,
+            } => RawTable::Freiburg(TableFreiburg(zip_columns(
+                bracket_starts.decode()?,
+                tax_rates,
+                |bracket_start, tax_rate| TableFreiburgEntry {
+                    bracket_start: OrderedFloat(bracket_start),
+                    tax_rate: float(tax_rate),
+                },
+            )?)),
+            CompactRawTable::Zuerich {
+                bracket_lens,
+                marginal_rates,
+            } => RawTable::Zuerich(TableZuerich(zip_columns(
+                bracket_lens,
+                marginal_rates,
+                |bracket_len, marginal_rate| TableZuerichEntry {
+                    bracket_len: float(bracket_len),
+                    marginal_rate: float(marginal_rate),
+                },
+            )?)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EvalPolicy {
     Raw,
     Round100,
@@ -58,7 +206,7 @@ pub enum EvalPolicy {
     Valais,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 enum RawTable {
     Bund(TableBund),
     Flattax(TableFlattax),
@@ -108,6 +256,33 @@ impl RawTable {
         }
     }
 
+    fn bracket_starts(&self) -> Vec<f64> {
+        match self {
+            RawTable::Bund(TableBund(entries)) => {
+                entries.iter().map(|entry| *entry.bracket_start).collect()
+            }
+            RawTable::Flattax(_) => Vec::new(),
+            RawTable::Formel(TableFormel(entries)) => {
+                entries.iter().map(|entry| *entry.bracket_start).collect()
+            }
+            RawTable::Freiburg(TableFreiburg(entries)) => {
+                entries.iter().map(|entry| *entry.bracket_start).collect()
+            }
+            RawTable::Zuerich(TableZuerich(entries)) => std::iter::once(0.0)
+                .chain(
+                    entries
+                        .iter()
+                        .map(|entry| *entry.bracket_len)
+                        .filter(|len| len.is_finite())
+                        .scan(0.0, |start, len| {
+                            *start += len;
+                            Some(*start)
+                        }),
+                )
+                .collect(),
+        }
+    }
+
     fn eval_round100(&self, x: f64) -> f64 {
         // Round down to multiple of 100 CHF.
         self.eval_raw(Self::floor_100(x))
@@ -158,7 +333,7 @@ impl RawTable {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableBund(Vec<TableBundEntry>);
 
 impl TryFrom<&[ScaleEntry]> for TableBund {
@@ -202,14 +377,14 @@ impl TableBund {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableBundEntry {
     bracket_start: OrderedFloat<f64>,
     base_tax: OrderedFloat<f64>,
     marginal_rate: OrderedFloat<f64>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableFlattax(OrderedFloat<f64>);
 
 impl TryFrom<&[ScaleEntry]> for TableFlattax {
@@ -244,7 +419,7 @@ impl TableFlattax {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableFormel(Vec<TableFormelEntry>);
 
 impl TryFrom<&[ScaleEntry]> for TableFormel {
@@ -271,7 +446,7 @@ impl TryFrom<&[ScaleEntry]> for TableFormel {
                         if i == 0 && entry.amount != 0.0 {
                             warn!("No entry found for 0 in table of type Formel");
                         }
-                        let formula = Formula::try_from(entry.formula.as_str())?;
+                        let formula: Formula = entry.formula.parse()?;
                         debug!("Parsed formula: {formula:?}");
                         Ok(TableFormelEntry {
                             bracket_start: OrderedFloat(entry.amount),
@@ -295,13 +470,13 @@ impl TableFormel {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableFormelEntry {
     bracket_start: OrderedFloat<f64>,
     formula: Formula,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableFreiburg(Vec<TableFreiburgEntry>);
 
 impl TryFrom<&[ScaleEntry]> for TableFreiburg {
@@ -356,13 +531,13 @@ impl TableFreiburg {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableFreiburgEntry {
     bracket_start: OrderedFloat<f64>,
     tax_rate: OrderedFloat<f64>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableZuerich(Vec<TableZuerichEntry>);
 
 impl TryFrom<&[ScaleEntry]> for TableZuerich {
@@ -416,7 +591,7 @@ impl TableZuerich {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct TableZuerichEntry {
     bracket_len: OrderedFloat<f64>,
     marginal_rate: OrderedFloat<f64>,
@@ -0,0 +1,212 @@
+use serde_json::{Value, json};
+use std::fs::{self, File};
+use std::path::Path;
+use tempfile::TempDir;
+
+pub const YEAR: u32 = 2025;
+
+/// Creates a temporary data directory with a small synthetic data set for
+/// [`YEAR`]: the federal scales and deductions, and the cantons ZH (two
+/// municipalities) and VD (one municipality).
+pub fn make_data_dir() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    write_data(dir.path(), YEAR);
+    dir
+}
+
+pub fn write_data(dir: &Path, year: u32) {
+    let ch = location("CH", 1, 0, "Bund", 1);
+    let zh1 = location("ZH", 2, 261, "Zürich", 100);
+    let zh2 = location("ZH", 2, 230, "Winterthur", 101);
+    let vd = location("VD", 3, 5586, "Lausanne", 200);
+
+    let rates = json!({
+        "response": [
+            rate(&zh1, 100.0, 119.0),
+            rate(&zh2, 100.0, 125.0),
+            rate(&vd, 155.0, 78.5),
+        ]
+    });
+
+    let zuerich: Vec<Value> = [
+        (7_000.0, 0.0),
+        (6_000.0, 2.0),
+        (4_000.0, 3.0),
+        (7_000.0, 4.0),
+        (10_000.0, 5.0),
+        (100_000_000.0, 10.0),
+    ]
+    .into_iter()
+    .map(|(amount, percent)| entry("", 0.0, percent, amount))
+    .collect();
+    let scales = json!({
+        "response": [
+            scale(&ch, "LEDIG_ALLEINE", 0.0, "BUND", "BUND", "EINKOMMENSSTEUER", vec![
+                entry("", 0.0, 0.0, 0.0),
+                entry("", 0.0, 1.0, 15_000.0),
+                entry("", 350.0, 3.0, 50_000.0),
+                entry("", 1_850.0, 8.0, 100_000.0),
+            ]),
+            scale(&ch, "VERHEIRATET,LEDIG_MIT_KINDER", 0.0, "BUND", "BUND", "EINKOMMENSSTEUER", vec![
+                entry("", 0.0, 0.0, 0.0),
+                entry("", 0.0, 1.0, 30_000.0),
+                entry("", 300.0, 3.0, 60_000.0),
+                entry("", 3_000.0, 8.0, 150_000.0),
+            ]),
+            scale(&zh1, "LEDIG_ALLEINE", 0.0, "ZUERICH", "KANTON", "EINKOMMENSSTEUER",
+                zuerich.clone()),
+            scale(&zh1, "VERHEIRATET", 0.0, "ZUERICH", "KANTON", "EINKOMMENSSTEUER",
+                zuerich),
+            scale(&vd, "ALLE", 1.9, "FORMEL", "KANTON", "EINKOMMENSSTEUER", vec![
+                entry("", 0.0, 0.0, 0.0),
+                entry("0.08 * $wert$ - 500", 0.0, 0.0, 10_000.0),
+            ]),
+            scale(&zh1, "ALLE", 0.0, "FLATTAX", "KANTON", "VERMOEGENSSTEUER", vec![
+                entry("", 0.0, 0.1, 0.0),
+            ]),
+        ]
+    });
+
+    // Entries whose names resemble the recognized deductions come first.
+    let deductions = json!({
+        "response": [
+            deduction(&ch, "BUND", "EINKOMMENSSTEUER", vec![
+                deduction_entry("Berufsorientierte Aus- und Weiterbildungskosten", "MAXIMUM",
+                    0.0, 0.0, 0.0, 12_900.0),
+                deduction_entry("Berufsauslagen", "PERCENT,MINIMUM,MAXIMUM",
+                    3.0, 0.0, 2_000.0, 4_000.0),
+                deduction_entry("Versicherungsprämien verheiratet", "MAXIMUM",
+                    0.0, 0.0, 0.0, 3_700.0),
+                deduction_entry("Zweiverdienerabzug", "PERCENT,MINIMUM,MAXIMUM",
+                    50.0, 0.0, 8_600.0, 14_100.0),
+                deduction_entry("Verheiratetenabzug", "STANDARDIZED", 0.0, 2_800.0, 0.0, 0.0),
+                deduction_entry("Kinderdrittbetreuungskosten", "MAXIMUM",
+                    0.0, 0.0, 0.0, 25_500.0),
+                deduction_entry("Kinderabzug", "STANDARDIZED", 0.0, 6_700.0, 0.0, 0.0),
+            ]),
+            deduction(&zh1, "KANTON", "EINKOMMENSSTEUER", vec![
+                deduction_entry("Übrige Berufsauslagen", "PERCENT,MINIMUM,MAXIMUM",
+                    3.0, 0.0, 2_000.0, 4_000.0),
+                deduction_entry("Zweiverdienerabzug", "STANDARDIZED", 0.0, 5_900.0, 0.0, 0.0),
+                deduction_entry("Kinderbetreuungskosten", "MAXIMUM", 0.0, 0.0, 0.0, 25_000.0),
+                deduction_entry("Kinderabzug", "STANDARDIZED", 0.0, 9_300.0, 0.0, 0.0),
+            ]),
+            deduction(&zh1, "KANTON", "VERMOEGENSSTEUER", vec![
+                deduction_entry("Verheiratetenabzug", "STANDARDIZED", 0.0, 154_000.0, 0.0, 0.0),
+            ]),
+            deduction(&vd, "KANTON", "EINKOMMENSSTEUER", vec![
+                deduction_entry("Kosten der berufsorientierten Weiterbildung", "MAXIMUM",
+                    0.0, 0.0, 0.0, 12_900.0),
+            ]),
+        ]
+    });
+
+    fs::create_dir_all(dir).unwrap();
+    write_json(&dir.join(format!("rates-{year}.json")), &rates);
+    write_json(&dir.join(format!("scales-{year}.json")), &scales);
+    write_json(&dir.join(format!("deductions-{year}.json")), &deductions);
+    write_json(
+        &dir.join(format!("other-deductions-{year}.json")),
+        &json!({ "response": [] }),
+    );
+}
+
+fn write_json(path: &Path, value: &Value) {
+    serde_json::to_writer(File::create(path).unwrap(), value).unwrap();
+}
+
+fn location(canton: &str, canton_id: u32, bfs_id: u32, name: &str, tax_location_id: u32) -> Value {
+    json!({
+        "BfsID": bfs_id,
+        "BfsName": name,
+        "CantonID": canton_id,
+        "Canton": canton,
+        "City": name,
+        "TaxLocationID": tax_location_id,
+        "ZipCode": "1000",
+    })
+}
+
+fn rate(location: &Value, income_rate_canton: f64, income_rate_city: f64) -> Value {
+    json!({
+        "Location": location,
+        "CapitalTaxRateCanton": 0.0,
+        "CapitalTaxRateChurch": 0.0,
+        "CapitalTaxRateCity": 0.0,
+        "FortuneRateCanton": 100.0,
+        "FortuneRateChrist": 0.0,
+        "FortuneRateCity": 50.0,
+        "FortuneRateProtestant": 0.0,
+        "FortuneRateRoman": 0.0,
+        "IncomeRateCanton": income_rate_canton,
+        "IncomeRateChrist": 0.0,
+        "IncomeRateCity": income_rate_city,
+        "IncomeRateProtestant": 0.0,
+        "IncomeRateRoman": 0.0,
+        "ProfitTaxRateCanton": 0.0,
+        "ProfitTaxRateChurch": 0.0,
+        "ProfitTaxRateCity": 0.0,
+    })
+}
+
+fn scale(
+    location: &Value,
+    group: &str,
+    splitting: f64,
+    table_type: &str,
+    target: &str,
+    tax_type: &str,
+    table: Vec<Value>,
+) -> Value {
+    json!({
+        "Location": location,
+        "Group": group,
+        "Splitting": splitting,
+        "TableType": table_type,
+        "Target": target,
+        "TaxType": tax_type,
+        "Table": table,
+    })
+}
+
+fn entry(formula: &str, taxes: f64, percent: f64, amount: f64) -> Value {
+    json!({
+        "Formula": formula,
+        "Taxes": taxes,
+        "Percent": percent,
+        "Amount": amount,
+    })
+}
+
+fn deduction(location: &Value, target: &str, tax_type: &str, table: Vec<Value>) -> Value {
+    json!({
+        "Location": location,
+        "Target": target,
+        "TaxType": tax_type,
+        "Table": table,
+    })
+}
+
+fn deduction_entry(
+    name: &str,
+    format: &str,
+    percent: f64,
+    amount: f64,
+    minimum: f64,
+    maximum: f64,
+) -> Value {
+    json!({
+        "Minimum": minimum,
+        "Maximum": maximum,
+        "Format": format,
+        "Percent": percent,
+        "Amount": amount,
+        "Name": {
+            "ID": name,
+            "DE": name,
+            "EN": name,
+            "FR": name,
+            "IT": name,
+        },
+    })
+}
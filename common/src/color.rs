@@ -0,0 +1,348 @@
+use plotters::style::RGBColor;
+#[cfg(feature = "wasm-bindgen")]
+use wasm_bindgen::{Clamped, prelude::wasm_bindgen};
+
+/// Named palettes of the marriage penalty heatmaps.
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorPreset {
+    /// The original palette, orange to red for penalties and blue to green
+    /// for bonuses.
+    Classic,
+    /// Diverging red-blue palette, safe for colour blindness.
+    RedBlue,
+    /// Diverging purple-green palette, safe for colour blindness.
+    PurpleGreen,
+    /// Diverging orange-purple palette, safe for colour blindness.
+    OrangePurple,
+}
+
+/// Colours of a palette, from the break-point closest to zero outwards.
+struct Palette {
+    neutral: RGBColor,
+    negative: &'static [RGBColor],
+    positive: &'static [RGBColor],
+}
+
+impl ColorPreset {
+    fn palette(self) -> Palette {
+        match self {
+            ColorPreset::Classic => Palette {
+                neutral: RGBColor(0xc0, 0xc0, 0xc0),
+                negative: &[
+                    RGBColor(0xc0, 0xa0, 0xa0),
+                    RGBColor(0xe0, 0xa0, 0x00),
+                    RGBColor(0xc0, 0x40, 0x40),
+                    RGBColor(0xa0, 0x60, 0x80),
+                ],
+                positive: &[
+                    RGBColor(0x80, 0xa0, 0xc0),
+                    RGBColor(0x20, 0xa0, 0xa0),
+                    RGBColor(0x50, 0xa0, 0x60),
+                    RGBColor(0x40, 0xc0, 0x00),
+                    RGBColor(0x80, 0xc0, 0x00),
+                ],
+            },
+            // The following palettes are from ColorBrewer.
+            ColorPreset::RedBlue => Palette {
+                neutral: RGBColor(0xf7, 0xf7, 0xf7),
+                negative: &[
+                    RGBColor(0xfd, 0xdb, 0xc7),
+                    RGBColor(0xf4, 0xa5, 0x82),
+                    RGBColor(0xd6, 0x60, 0x4d),
+                    RGBColor(0xb2, 0x18, 0x2b),
+                    RGBColor(0x67, 0x00, 0x1f),
+                ],
+                positive: &[
+                    RGBColor(0xd1, 0xe5, 0xf0),
+                    RGBColor(0x92, 0xc5, 0xde),
+                    RGBColor(0x43, 0x93, 0xc3),
+                    RGBColor(0x21, 0x66, 0xac),
+                    RGBColor(0x05, 0x30, 0x61),
+                ],
+            },
+            ColorPreset::PurpleGreen => Palette {
+                neutral: RGBColor(0xf7, 0xf7, 0xf7),
+                negative: &[
+                    RGBColor(0xe7, 0xd4, 0xe8),
+                    RGBColor(0xc2, 0xa5, 0xcf),
+                    RGBColor(0x99, 0x70, 0xab),
+                    RGBColor(0x76, 0x2a, 0x83),
+                    RGBColor(0x40, 0x00, 0x4b),
+                ],
+                positive: &[
+                    RGBColor(0xd9, 0xf0, 0xd3),
+                    RGBColor(0xa6, 0xdb, 0xa0),
+                    RGBColor(0x5a, 0xae, 0x61),
+                    RGBColor(0x1b, 0x78, 0x37),
+                    RGBColor(0x00, 0x44, 0x1b),
+                ],
+            },
+            ColorPreset::OrangePurple => Palette {
+                neutral: RGBColor(0xf7, 0xf7, 0xf7),
+                negative: &[
+                    RGBColor(0xfe, 0xe0, 0xb6),
+                    RGBColor(0xfd, 0xb8, 0x63),
+                    RGBColor(0xe0, 0x82, 0x14),
+                    RGBColor(0xb3, 0x58, 0x06),
+                    RGBColor(0x7f, 0x3b, 0x08),
+                ],
+                positive: &[
+                    RGBColor(0xd8, 0xda, 0xeb),
+                    RGBColor(0xb2, 0xab, 0xd2),
+                    RGBColor(0x80, 0x73, 0xac),
+                    RGBColor(0x54, 0x27, 0x88),
+                    RGBColor(0x2d, 0x00, 0x4b),
+                ],
+            },
+        }
+    }
+}
+
+/// How the break-points of a colour scale adapt to the plotted differences.
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorRange {
+    /// Keep the break-points as given.
+    Fixed,
+    /// Stretch the break-points of each sign to the largest difference of
+    /// that sign.
+    Auto,
+    /// Stretch the break-points of both signs to the largest difference in
+    /// absolute value, with a legend centered on zero.
+    Symmetric,
+}
+
+/// Colour scale of the marriage penalty heatmaps. Differences within the
+/// first break-point are neutral, and the palette is spread over the
+/// break-points of each sign, interpolating linearly between them.
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorScale {
+    preset: ColorPreset,
+    range: ColorRange,
+    /// Increasing magnitudes of the negative differences (marriage
+    /// penalties) at the break-points.
+    negative: Vec<f64>,
+    /// Increasing positive differences at the break-points.
+    positive: Vec<f64>,
+}
+
+#[cfg_attr(feature = "wasm-bindgen", wasm_bindgen)]
+impl ColorScale {
+    /// Returns the scale with the default break-points, in CHF or in percent
+    /// of the combined income.
+    #[cfg_attr(feature = "wasm-bindgen", wasm_bindgen(constructor))]
+    pub fn new(preset: ColorPreset, percent: bool, range: ColorRange) -> Self {
+        let (negative, positive): (&[f64], &[f64]) = if percent {
+            (&[0.025, 1.0, 3.0, 5.0], &[0.025, 1.0, 3.0, 5.0, 8.0])
+        } else {
+            (
+                &[10.0, 3000.0, 8000.0, 12000.0],
+                &[10.0, 3000.0, 6000.0, 9000.0, 12000.0],
+            )
+        };
+        Self {
+            preset,
+            range,
+            negative: negative.to_vec(),
+            positive: positive.to_vec(),
+        }
+    }
+
+    /// Returns the scale with the same break-points for both signs, given as
+    /// increasing positive differences.
+    pub fn with_breaks(
+        preset: ColorPreset,
+        breaks: &[f64],
+        range: ColorRange,
+    ) -> Result<ColorScale, String> {
+        if breaks.len() < 2 {
+            return Err(format!(
+                "A colour scale needs at least 2 break-points, found {breaks:?}"
+            ));
+        }
+        if breaks[0] < 0.0 || !breaks.is_sorted_by(|a, b| a < b) {
+            return Err(format!(
+                "Break-points must be positive and increasing, found {breaks:?}"
+            ));
+        }
+        Ok(Self {
+            preset,
+            range,
+            negative: breaks.to_vec(),
+            positive: breaks.to_vec(),
+        })
+    }
+
+    /// Colours a grid of differences, such as the one computed by the wasm
+    /// tool's `State::diff_grid`, into RGBA pixels, which can be wrapped in an
+    /// `ImageData`.
+    #[cfg(feature = "wasm-bindgen")]
+    pub fn colorize(&self, grid: &[f32]) -> Clamped<Vec<u8>> {
+        let values: Vec<f64> = grid.iter().map(|&value| value as f64).collect();
+        let (min, max) = value_range(&values, 0.0, 0.0);
+        let (colors, _, _) = self.fit(min, max);
+        Clamped(colorize_values(&values, &colors))
+    }
+}
+
+impl ColorScale {
+    /// Returns the scale fitted to the range of the plotted differences,
+    /// along with the range of its legend.
+    pub fn fit(&self, min: f64, max: f64) -> (Self, f64, f64) {
+        let (negative, positive, min, max) = match self.range {
+            ColorRange::Fixed => return (self.clone(), min, max),
+            ColorRange::Auto => (
+                stretch(&self.negative, -min),
+                stretch(&self.positive, max),
+                min,
+                max,
+            ),
+            ColorRange::Symmetric => {
+                let extreme = max.max(-min);
+                (
+                    stretch(&self.negative, extreme),
+                    stretch(&self.positive, extreme),
+                    -extreme,
+                    extreme,
+                )
+            }
+        };
+        let scale = Self {
+            negative,
+            positive,
+            ..*self
+        };
+        (scale, min, max)
+    }
+
+    /// Returns the colour of the given difference, or black for NaN.
+    pub fn color(&self, value: f64) -> RGBColor {
+        if value.is_nan() {
+            return RGBColor(0, 0, 0);
+        }
+        let palette = self.preset.palette();
+        let (breaks, colors) = if value < 0.0 {
+            (&self.negative, palette.negative)
+        } else {
+            (&self.positive, palette.positive)
+        };
+        let magnitude = value.abs();
+        if magnitude <= breaks[0] {
+            return palette.neutral;
+        }
+
+        // Position along the palette, from 0 at the first break-point to 1 at
+        // the last one.
+        let k = breaks.partition_point(|&b| b < magnitude);
+        let position = if k == breaks.len() {
+            1.0
+        } else {
+            let fraction = (magnitude - breaks[k - 1]) / (breaks[k] - breaks[k - 1]);
+            ((k - 1) as f64 + fraction) / (breaks.len() - 1) as f64
+        };
+
+        let x = position * (colors.len() - 1) as f64;
+        let i = (x.floor() as usize).min(colors.len() - 2);
+        interpolate(colors[i], colors[i + 1], i as f64, (i + 1) as f64, x)
+    }
+}
+
+/// Extends the given range to the differences, skipping NaN.
+pub fn value_range(values: &[f64], min: f64, max: f64) -> (f64, f64) {
+    values
+        .iter()
+        .filter(|value| !value.is_nan())
+        .fold((min, max), |(min, max), &value| {
+            (min.min(value), max.max(value))
+        })
+}
+
+/// Colours differences into RGBA pixels.
+pub fn colorize_values(values: &[f64], colors: &ColorScale) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(4 * values.len());
+    for &value in values {
+        let color = colors.color(value);
+        pixels.extend_from_slice(&[color.0, color.1, color.2, 0xff]);
+    }
+    pixels
+}
+
+/// Stretches break-points so that the last one is at the given extreme,
+/// keeping the first one.
+fn stretch(breaks: &[f64], extreme: f64) -> Vec<f64> {
+    let (first, last) = (breaks[0], breaks[breaks.len() - 1]);
+    if extreme <= first {
+        return breaks.to_vec();
+    }
+    breaks
+        .iter()
+        .map(|b| first + (b - first) * (extreme - first) / (last - first))
+        .collect()
+}
+
+fn interpolate(color1: RGBColor, color2: RGBColor, start: f64, end: f64, value: f64) -> RGBColor {
+    let x = (value - start) / (end - start);
+    if x <= 0.0 {
+        color1
+    } else if x >= 1.0 {
+        color2
+    } else {
+        RGBColor(
+            (color1.0 as f64 * (1.0 - x) + color2.0 as f64 * x) as u8,
+            (color1.1 as f64 * (1.0 - x) + color2.1 as f64 * x) as u8,
+            (color1.2 as f64 * (1.0 - x) + color2.2 as f64 * x) as u8,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn color_scale() {
+        // The classic scale keeps the colours of the original break-points.
+        let scale = ColorScale::new(ColorPreset::Classic, false, ColorRange::Fixed);
+        assert_eq!(scale.color(0.0), RGBColor(0xc0, 0xc0, 0xc0));
+        assert_eq!(scale.color(-10.0), RGBColor(0xc0, 0xc0, 0xc0));
+        assert_eq!(scale.color(-3000.0), RGBColor(0xe0, 0xa0, 0x00));
+        assert_eq!(scale.color(-8000.0), RGBColor(0xc0, 0x40, 0x40));
+        assert_eq!(scale.color(-20000.0), RGBColor(0xa0, 0x60, 0x80));
+        assert_eq!(scale.color(6000.0), RGBColor(0x50, 0xa0, 0x60));
+        assert_eq!(scale.color(4500.0), RGBColor(0x38, 0xa0, 0x80));
+        assert_eq!(scale.color(f64::NAN), RGBColor(0, 0, 0));
+
+        let scale = ColorScale::with_breaks(
+            ColorPreset::RedBlue,
+            &[10.0, 1000.0, 2000.0],
+            ColorRange::Symmetric,
+        )
+        .unwrap();
+        assert_eq!(scale.color(-2000.0), RGBColor(0x67, 0x00, 0x1f));
+        assert_eq!(scale.color(1500.0), RGBColor(0x21, 0x66, 0xac));
+        let (fitted, min, max) = scale.fit(-500.0, 3990.0);
+        assert_eq!((min, max), (-3990.0, 3990.0));
+        assert_eq!(fitted.negative, [10.0, 1990.0, 3990.0]);
+        assert_eq!(fitted.color(-3990.0), RGBColor(0x67, 0x00, 0x1f));
+
+        assert!(ColorScale::with_breaks(ColorPreset::Classic, &[10.0], ColorRange::Fixed).is_err());
+        assert!(
+            ColorScale::with_breaks(ColorPreset::Classic, &[10.0, 10.0], ColorRange::Fixed)
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "wasm-bindgen")]
+    #[test]
+    fn colorize() {
+        let scale = ColorScale::new(ColorPreset::Classic, false, ColorRange::Fixed);
+        assert_eq!(
+            scale.colorize(&[0.0, -3000.0]).0,
+            [0xc0, 0xc0, 0xc0, 0xff, 0xe0, 0xa0, 0x00, 0xff]
+        );
+    }
+}
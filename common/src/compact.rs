@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+/// Number in the compact encoding of `tables.db`. Most rates and amounts of
+/// the source data are exact to 0.001, and are stored as a varint of
+/// thousandths rather than 8 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Number {
+    Milli(i64),
+    Exact(f64),
+}
+
+impl Number {
+    /// Largest number of thousandths that is stored as an integer, beyond
+    /// which not all integers are representable as a float.
+    const MAX_MILLI: f64 = (1u64 << 53) as f64;
+
+    fn into_f64(self) -> f64 {
+        match self {
+            Number::Milli(milli) => milli as f64 / 1000.0,
+            Number::Exact(x) => x,
+        }
+    }
+
+    fn milli(x: f64) -> Option<i64> {
+        let milli = (x * 1000.0).round();
+        if milli.is_nan() || milli.abs() >= Self::MAX_MILLI {
+            return None;
+        }
+        // Comparing bits rules out -0.0, which would decode as 0.0.
+        let milli = milli as i64;
+        (Number::Milli(milli).into_f64().to_bits() == x.to_bits()).then_some(milli)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(x: f64) -> Self {
+        match Self::milli(x) {
+            Some(milli) => Number::Milli(milli),
+            None => Number::Exact(x),
+        }
+    }
+}
+
+impl From<Number> for f64 {
+    fn from(number: Number) -> f64 {
+        number.into_f64()
+    }
+}
+
+/// Delta encoding of a sequence of bracket starts. Each start that is exact
+/// to 0.001, like the previous one, is stored as the difference in
+/// thousandths from the previous one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Deltas(Vec<Number>);
+
+impl Deltas {
+    pub fn encode(values: impl IntoIterator<Item = f64>) -> Self {
+        let mut previous = Some(0);
+        Deltas(
+            values
+                .into_iter()
+                .map(|x| {
+                    let milli = Number::milli(x);
+                    let number = match (previous, milli) {
+                        (Some(previous), Some(milli)) => Number::Milli(milli - previous),
+                        _ => Number::Exact(x),
+                    };
+                    previous = milli;
+                    number
+                })
+                .collect(),
+        )
+    }
+
+    pub fn decode(&self) -> Result<Vec<f64>, String> {
+        let mut previous = Some(0_i64);
+        self.0
+            .iter()
+            .map(|&number| match number {
+                Number::Milli(delta) => {
+                    let milli = previous
+                        .ok_or("Delta following an inexact number")?
+                        .checked_add(delta)
+                        .ok_or_else(|| format!("Overflowing delta: {delta}"))?;
+                    previous = Some(milli);
+                    Ok(milli as f64 / 1000.0)
+                }
+                Number::Exact(x) => {
+                    previous = Number::milli(x);
+                    Ok(x)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Zips two columns of a compact table, which must have the same length.
+pub fn zip_columns<T, U, V>(
+    first: Vec<T>,
+    second: Vec<U>,
+    f: impl Fn(T, U) -> V,
+) -> Result<Vec<V>, String> {
+    if first.len() != second.len() {
+        return Err(format!(
+            "Mismatching column lengths in compact table: {} != {}",
+            first.len(),
+            second.len()
+        ));
+    }
+    Ok(first
+        .into_iter()
+        .zip(second)
+        .map(|(x, y)| f(x, y))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numbers() {
+        for x in [0.0, 1.0, 0.001, 119.5, 149.575, -2.25, 1e12] {
+            assert_eq!(Number::from(x), Number::Milli((x * 1000.0) as i64));
+            assert_eq!(f64::from(Number::from(x)).to_bits(), x.to_bits());
+        }
+        for x in [-0.0, 0.0001, 1.0 / 3.0, 1e300, f64::INFINITY] {
+            assert_eq!(Number::from(x), Number::Exact(x));
+            assert_eq!(f64::from(Number::from(x)).to_bits(), x.to_bits());
+        }
+
+        let starts = [
+            0.0,
+            6_900.0,
+            6_900.5,
+            1.0 / 3.0,
+            20_000.0,
+            20_000.000_1,
+            1e6,
+        ];
+        let deltas = Deltas::encode(starts);
+        assert_eq!(
+            deltas.0[..3],
+            [
+                Number::Milli(0),
+                Number::Milli(6_900_000),
+                Number::Milli(500)
+            ]
+        );
+        let decoded: [f64; 7] = deltas.decode().unwrap().try_into().unwrap();
+        assert_eq!(decoded.map(f64::to_bits), starts.map(f64::to_bits));
+
+        let inexact = Deltas(vec![Number::Exact(1.0 / 3.0), Number::Milli(1)]);
+        assert!(inexact.decode().is_err());
+        let overflow = Deltas(vec![Number::Milli(1), Number::Milli(i64::MAX)]);
+        assert!(overflow.decode().is_err());
+    }
+
+    #[test]
+    fn encoded_round_trip() {
+        // The analysis tool writes with postcard, and the wasm tool reads the
+        // same bytes back.
+        let starts = [0.0, 14_500.0, 1.0 / 3.0, 31_600.25, 31_600.000_1];
+        let bytes = postcard::to_allocvec(&Deltas::encode(starts)).unwrap();
+        let deltas: Deltas = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(deltas.decode().unwrap(), starts);
+        assert!(zip_columns(vec![1], vec![2, 3], |x, y| x + y).is_err());
+    }
+}
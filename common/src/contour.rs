@@ -0,0 +1,130 @@
+/// Segment of a contour line, between two points in grid coordinates.
+pub type Segment = [(f64, f64); 2];
+
+/// Contour line of a grid at a given level, as unordered segments.
+pub struct Contour {
+    pub level: f64,
+    pub segments: Vec<Segment>,
+}
+
+impl Contour {
+    /// Returns where to label the contour: the middle of its median segment,
+    /// which is roughly halfway along the line as segments are found row by
+    /// row.
+    pub fn label_position(&self) -> Option<(f64, f64)> {
+        let [(x0, y0), (x1, y1)] = self.segments.get(self.segments.len() / 2)?;
+        Some(((x0 + x1) / 2.0, (y0 + y1) / 2.0))
+    }
+}
+
+/// Computes the contour lines at the given levels of a grid of
+/// `width × height` values, with marching squares. Cells with NaN values are
+/// skipped.
+pub fn contours(
+    width: usize,
+    height: usize,
+    value: impl Fn(usize, usize) -> f64,
+    levels: &[f64],
+) -> Vec<Contour> {
+    levels
+        .iter()
+        .map(|&level| Contour {
+            level,
+            segments: marching_squares(width, height, &value, level),
+        })
+        .collect()
+}
+
+fn marching_squares(
+    width: usize,
+    height: usize,
+    value: impl Fn(usize, usize) -> f64,
+    level: f64,
+) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    for j in 0..height.saturating_sub(1) {
+        for i in 0..width.saturating_sub(1) {
+            // Corners counter-clockwise from the bottom left.
+            let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)]
+                .map(|(x, y)| (x as f64, y as f64, value(x, y)));
+            if corners.iter().any(|&(_, _, v)| v.is_nan()) {
+                continue;
+            }
+
+            // Crossings of the bottom, right, top and left edges.
+            let crossings = [0, 1, 2, 3].map(|k| {
+                let (xa, ya, a) = corners[k];
+                let (xb, yb, b) = corners[(k + 1) % 4];
+                ((a >= level) != (b >= level)).then(|| {
+                    let t = (level - a) / (b - a);
+                    (xa + t * (xb - xa), ya + t * (yb - ya))
+                })
+            });
+            match crossings {
+                [None, None, None, None] => (),
+                [Some(b), Some(r), Some(t), Some(l)] => {
+                    // Saddle: the center decides which corners are connected.
+                    let center = corners.iter().map(|&(_, _, v)| v).sum::<f64>() / 4.0;
+                    if (center >= level) == (corners[0].2 >= level) {
+                        segments.push([b, r]);
+                        segments.push([t, l]);
+                    } else {
+                        segments.push([b, l]);
+                        segments.push([r, t]);
+                    }
+                }
+                crossings => {
+                    let mut points = crossings.into_iter().flatten();
+                    if let (Some(start), Some(end)) = (points.next(), points.next()) {
+                        segments.push([start, end]);
+                    }
+                }
+            }
+        }
+    }
+    segments
+}
+
+/// Returns the label of a contour level, in CHF or in percent.
+pub fn contour_label(level: f64, percent: bool) -> String {
+    let unit = if percent { "%" } else { "" };
+    if level == 0.0 {
+        format!("0{unit}")
+    } else {
+        format!("{level:+}{unit}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn contour_lines() {
+        // A vertical line at x = 2.5, with one segment per row of cells.
+        let [line] = &contours(5, 4, |x, _| x as f64, &[2.5])[..] else {
+            panic!("Expected one contour");
+        };
+        assert_eq!(line.segments.len(), 3);
+        assert!(line.segments.iter().flatten().all(|&(x, _)| x == 2.5));
+        assert_eq!(line.label_position(), Some((2.5, 1.5)));
+
+        // A saddle, whose center is above the level.
+        let saddle = [[1.0, -1.0], [-1.0, 1.0]];
+        let [saddle] = &contours(2, 2, |x, y| saddle[y][x] + 0.5, &[0.0])[..] else {
+            panic!("Expected one contour");
+        };
+        assert_eq!(
+            saddle.segments,
+            [[(0.75, 0.0), (1.0, 0.25)], [(0.25, 1.0), (0.0, 0.75)]]
+        );
+
+        assert!(
+            contours(2, 2, |_, _| f64::NAN, &[0.0])[0]
+                .segments
+                .is_empty()
+        );
+        assert_eq!(contour_label(-1000.0, false), "-1000");
+        assert_eq!(contour_label(0.5, true), "+0.5%");
+    }
+}
@@ -0,0 +1,7 @@
+#![forbid(unsafe_code)]
+
+//! Code shared by the analysis tool and the wasm display.
+
+pub mod color;
+pub mod compact;
+pub mod contour;
@@ -0,0 +1,142 @@
+use crate::plot::DiffScale;
+use crate::table::Database;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Number of points of rate curves.
+const CURVE_POINTS: usize = 500;
+
+/// Income step over which marginal rates are computed, as some tables round
+/// incomes down to a multiple of 100 CHF.
+const MARGINAL_STEP: f64 = 100.0;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum CivilStatus {
+    Single,
+    Married,
+}
+
+/// Income tax of a household.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct TaxBreakdown {
+    /// Tax given by the scale, before the cantonal multiplier.
+    pub simple_tax: f64,
+    /// Tax after the cantonal multiplier, or federal tax for "CH".
+    pub cantonal_tax: f64,
+    /// Tax in percent of the income.
+    pub average_rate: f64,
+    /// Tax on the next CHF of income, in percent.
+    pub marginal_rate: f64,
+}
+
+impl TaxBreakdown {
+    pub fn compute(
+        db: &Database,
+        year: u32,
+        zone: &str,
+        status: CivilStatus,
+        income: f64,
+    ) -> Result<Self, String> {
+        let (rate, scale) = db.income_scale(year, zone)?;
+        let simple_tax = db.simple_tax(scale, status, income)?;
+        let next_simple_tax = db.simple_tax(scale, status, income + MARGINAL_STEP)?;
+        let cantonal_tax = simple_tax * rate / 100.0;
+        let average_rate = if income == 0.0 {
+            0.0
+        } else {
+            100.0 * cantonal_tax / income
+        };
+        let marginal_rate = (next_simple_tax - simple_tax) * rate / MARGINAL_STEP;
+        Ok(TaxBreakdown {
+            simple_tax,
+            cantonal_tax,
+            average_rate,
+            marginal_rate,
+        })
+    }
+
+    pub fn rate(&self, marginal: bool) -> f64 {
+        if marginal {
+            self.marginal_rate
+        } else {
+            self.average_rate
+        }
+    }
+}
+
+/// Returns the average or marginal tax rate at regularly spaced incomes up to
+/// the given maximum.
+pub fn rate_curve(
+    db: &Database,
+    year: u32,
+    zone: &str,
+    status: CivilStatus,
+    max_income: f64,
+    marginal: bool,
+) -> Result<Vec<(f64, f64)>, String> {
+    (1..=CURVE_POINTS)
+        .map(|i| {
+            let income = max_income * i as f64 / CURVE_POINTS as f64;
+            let tax = TaxBreakdown::compute(db, year, zone, status, income)?;
+            Ok((income, tax.rate(marginal)))
+        })
+        .collect()
+}
+
+/// Taxes of which the marriage penalty is plotted.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq)]
+pub enum TaxLayer {
+    Federal,
+    Cantonal,
+    /// Communal tax of a municipality.
+    Communal,
+    /// Sum of the federal, cantonal and communal taxes.
+    Total,
+}
+
+/// Taxes of a couple in the marriage penalty heatmap, including the
+/// multipliers.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct HeatmapPoint {
+    pub income_1: f64,
+    pub income_2: f64,
+    /// Tax of each partner if they were single.
+    pub tax_single_1: f64,
+    pub tax_single_2: f64,
+    pub tax_married: f64,
+    /// Taxes saved by marriage, negative for a marriage penalty.
+    pub diff: f64,
+    /// Difference in percent of the combined income.
+    pub diff_percent: f64,
+}
+
+impl HeatmapPoint {
+    /// Computes the total taxes of the given scales.
+    pub fn compute(scales: &[DiffScale], income_1: f64, income_2: f64) -> Self {
+        let tax_single_1 = scales.iter().map(|scale| scale.tax_single(income_1)).sum();
+        let tax_single_2 = scales.iter().map(|scale| scale.tax_single(income_2)).sum();
+        let tax_married = scales
+            .iter()
+            .map(|scale| scale.tax_married(income_1 + income_2))
+            .sum();
+        let diff = tax_single_1 + tax_single_2 - tax_married;
+        let total = income_1 + income_2;
+        let diff_percent = if total == 0.0 {
+            0.0
+        } else {
+            100.0 * diff / total
+        };
+        HeatmapPoint {
+            income_1,
+            income_2,
+            tax_single_1,
+            tax_single_2,
+            tax_married,
+            diff,
+            diff_percent,
+        }
+    }
+}
@@ -1,66 +1,520 @@
 #![forbid(unsafe_code)]
 
+mod compute;
 mod plot;
 mod table;
+#[cfg(test)]
+mod testdata;
 
-use plot::plot_income_tax_diff;
+use compute::{CivilStatus, HeatmapPoint, TaxBreakdown, TaxLayer, rate_curve};
+use js_sys::Uint8Array;
+use plot::{
+    Curve, DiffScale, HeatmapGrid, HeatmapLayout, plot_income_tax_diff, plot_rate_curves,
+    report_nan,
+};
 use table::Database;
-use wasm_bindgen::JsValue;
+use taxes_common::color::{ColorPreset, ColorRange, ColorScale};
 use wasm_bindgen::prelude::wasm_bindgen;
-use web_sys::{HtmlCanvasElement, console};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{HtmlCanvasElement, Response, Window, WorkerGlobalScope, console};
+
+fn log_loaded(db: Database) -> Database {
+    let header = &db.header;
+    console::log_1(&JsValue::from_str(&format!(
+        "Loaded data for years {:?} and cantons {:?}, fetched at {} (Unix time)",
+        header.years, header.cantons, header.source_date
+    )));
+    db
+}
 
 #[wasm_bindgen]
 pub struct State {
     db: Option<Database>,
+    /// Last plotted heatmap, for inspection.
+    heatmap: Option<Heatmap>,
+    /// Colour scales of the heatmaps of absolute and relative differences.
+    colors: ColorScale,
+    percent_colors: ColorScale,
+    /// Levels of the contour lines of absolute and relative differences.
+    contours: Vec<f64>,
+    percent_contours: Vec<f64>,
+    /// Taxes of the heatmaps, and municipality of the communal tax.
+    layer: TaxLayer,
+    municipality: Option<String>,
+}
+
+struct Heatmap {
+    year: u32,
+    canton: String,
+    layer: TaxLayer,
+    municipality: Option<String>,
+    layout: HeatmapLayout,
+    grid: HeatmapGrid,
+}
+
+impl Heatmap {
+    /// Returns whether the grid of this heatmap can be reused for the given
+    /// year, canton and taxes.
+    fn matches(
+        &self,
+        year: u32,
+        canton: &str,
+        layer: TaxLayer,
+        municipality: Option<&str>,
+    ) -> bool {
+        self.year == year
+            && self.canton == canton
+            && self.layer == layer
+            && self.municipality.as_deref() == municipality
+    }
 }
 
 #[wasm_bindgen]
 impl State {
+    /// Loads the database embedded in the module. Errors are logged, and
+    /// reported when plotting.
+    #[cfg(feature = "embed")]
     #[expect(clippy::new_without_default)]
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
         let db = match Database::load() {
-            Ok(db) => Some(db),
+            Ok(db) => Some(log_loaded(db)),
             Err(e) => {
                 console::error_1(&JsValue::from_str(&format!("Failed to load data: {e:?}")));
                 None
             }
         };
-        Self { db }
+        Self::with_db(db)
     }
 
-    pub fn plot(
+    /// Loads the database from the contents of a `tables.db` file.
+    pub fn from_bytes(data: &[u8]) -> Result<State, JsValue> {
+        let db = Database::parse(data)?;
+        Ok(Self::with_db(Some(log_loaded(db))))
+    }
+
+    /// Fetches a `tables.db` file from the given URL, and loads it. This works
+    /// both in a window and in a Web Worker.
+    pub async fn fetch(url: String) -> Result<State, JsValue> {
+        let global = js_sys::global();
+        let promise = if let Some(window) = global.dyn_ref::<Window>() {
+            window.fetch_with_str(&url)
+        } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+            worker.fetch_with_str(&url)
+        } else {
+            return Err("No window or worker to fetch data from".into());
+        };
+        let response: Response = JsFuture::from(promise).await?.dyn_into()?;
+        if !response.ok() {
+            return Err(format!(
+                "Failed to fetch {url}: {} {}",
+                response.status(),
+                response.status_text()
+            )
+            .into());
+        }
+        let buffer = JsFuture::from(response.array_buffer()?).await?;
+        Self::from_bytes(&Uint8Array::new(&buffer).to_vec())
+    }
+
+    /// Returns the years of the database.
+    pub fn years(&self) -> Result<Vec<u32>, JsValue> {
+        Ok(self.db()?.db.keys().copied().collect())
+    }
+
+    /// Returns the zones available in the given year: "CH" for the federal
+    /// tax, followed by the cantons.
+    pub fn cantons(&self, year: u32) -> Result<Vec<String>, JsValue> {
+        let entry = self
+            .db()?
+            .db
+            .get(&year)
+            .ok_or_else(|| format!("Didn't find year: {year}"))?;
+        Ok(std::iter::once("CH".to_owned())
+            .chain(entry.cantons.keys().cloned())
+            .collect())
+    }
+
+    /// Computes the income tax of a household in the given canton, or the
+    /// federal tax for "CH".
+    pub fn compute(
         &self,
+        year: u32,
+        canton: &str,
+        status: CivilStatus,
+        income: f64,
+    ) -> Result<TaxBreakdown, JsValue> {
+        Ok(TaxBreakdown::compute(
+            self.db()?,
+            year,
+            canton,
+            status,
+            income,
+        )?)
+    }
+
+    pub fn plot(
+        &mut self,
         canvas: HtmlCanvasElement,
         year: u32,
         canton: &str,
         max_salary: i32,
         percent: bool,
     ) -> Result<(), JsValue> {
-        match &self.db {
-            None => Err("Failed to load data".into()),
-            Some(db) => {
-                let entry = db
-                    .db
-                    .get(&year)
-                    .ok_or_else(|| format!("Didn't find year: {year}"))?
-                    .0
-                    .get(canton)
-                    .ok_or_else(|| format!("Didn't find canton: {canton}"))?;
-                let scale = &db.arena_scale[entry.scale_index as usize];
-                let single = &db.arena_table[scale.single_index as usize];
-                let married = &db.arena_table[scale.married_index as usize];
-                plot_income_tax_diff(
-                    canvas,
-                    max_salary,
-                    entry.rate,
-                    scale.splitting,
-                    single,
-                    married,
-                    percent,
-                )?;
-                Ok(())
+        let db = self.db.as_ref().ok_or("Failed to load data")?;
+        let scales = diff_scales(db, year, canton, self.layer, self.municipality.as_deref())?;
+        // The grid only depends on the scales and on the layout, which is
+        // checked when plotting. The previous heatmap is kept if plotting
+        // fails.
+        let cached = self
+            .heatmap
+            .as_ref()
+            .filter(|heatmap| {
+                heatmap.matches(year, canton, self.layer, self.municipality.as_deref())
+            })
+            .map(|heatmap| &heatmap.grid);
+        let (colors, levels) = if percent {
+            (&self.percent_colors, &self.percent_contours)
+        } else {
+            (&self.colors, &self.contours)
+        };
+        let (layout, grid) =
+            plot_income_tax_diff(canvas, max_salary, &scales, percent, colors, levels, cached)?;
+        match grid {
+            Some(grid) => {
+                self.heatmap = Some(Heatmap {
+                    year,
+                    canton: canton.into(),
+                    layer: self.layer,
+                    municipality: self.municipality.clone(),
+                    layout,
+                    grid,
+                })
+            }
+            // The cached grid was reused, at a possibly different position.
+            None => {
+                if let Some(heatmap) = &mut self.heatmap {
+                    heatmap.layout = layout;
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Returns the names of the municipalities of a canton.
+    pub fn municipalities(&self, year: u32, canton: &str) -> Result<Vec<String>, JsValue> {
+        Ok(self
+            .db()?
+            .municipalities(year, canton)?
+            .map(|municipality| municipality.name.clone())
+            .collect())
+    }
+
+    /// Plots the marriage penalty of the given taxes in the next heatmaps,
+    /// the cantonal tax by default. Communal taxes, alone or in the total,
+    /// use the multiplier of the municipality with the given name or BFS
+    /// number, or else the median multiplier of the canton.
+    pub fn set_layer(&mut self, layer: TaxLayer, municipality: Option<String>) {
+        self.layer = layer;
+        self.municipality = municipality;
+    }
+
+    /// Returns the label of the taxes of the next heatmaps in a canton, with
+    /// their multiplier.
+    pub fn layer_label(&self, year: u32, canton: &str) -> Result<String, JsValue> {
+        Ok(layer_label(
+            self.db()?,
+            year,
+            canton,
+            self.layer,
+            self.municipality.as_deref(),
+        )?)
+    }
+
+    /// Uses the given colour scale for the heatmaps of absolute (or relative)
+    /// differences.
+    pub fn set_color_scale(&mut self, colors: &ColorScale, percent: bool) {
+        if percent {
+            self.percent_colors = colors.clone();
+        } else {
+            self.colors = colors.clone();
+        }
+    }
+
+    /// Sets the levels of the contour lines on the heatmaps of absolute
+    /// differences in CHF (or relative differences in percent). Each level is
+    /// drawn at both signs, besides the break-even line.
+    pub fn set_contours(&mut self, levels: Vec<f64>, percent: bool) {
+        if percent {
+            self.percent_contours = levels;
+        } else {
+            self.contours = levels;
+        }
+    }
+
+    /// Computes the marriage penalty heatmap without drawing it, for example
+    /// in a Web Worker. The differences are in CHF, or in percent of the
+    /// combined income, row by row from the top (the highest income of the
+    /// second partner), and can be coloured with [`ColorScale::colorize`].
+    pub fn diff_grid(
+        &self,
+        year: u32,
+        canton: &str,
+        width: usize,
+        height: usize,
+        max_salary: i32,
+        percent: bool,
+    ) -> Result<Vec<f32>, JsValue> {
+        let scales = diff_scales(
+            self.db()?,
+            year,
+            canton,
+            self.layer,
+            self.municipality.as_deref(),
+        )?;
+        let grid = HeatmapGrid::new(max_salary, width, height, &scales);
+        let values: Vec<f64> = grid.values(percent).collect();
+        report_nan(&values, width);
+        Ok(values.into_iter().map(|value| value as f32).collect())
+    }
+
+    /// Returns the incomes and taxes at the given pixel of the last plotted
+    /// heatmap, in canvas coordinates (not CSS pixels), or `undefined` outside
+    /// of the heatmap.
+    pub fn inspect(&self, x: i32, y: i32) -> Result<Option<HeatmapPoint>, JsValue> {
+        let heatmap = self.heatmap.as_ref().ok_or("No heatmap was plotted")?;
+        let Some((income_1, income_2)) = heatmap.layout.incomes(x, y) else {
+            return Ok(None);
+        };
+        let scales = diff_scales(
+            self.db()?,
+            heatmap.year,
+            &heatmap.canton,
+            heatmap.layer,
+            heatmap.municipality.as_deref(),
+        )?;
+        Ok(Some(HeatmapPoint::compute(&scales, income_1, income_2)))
+    }
+
+    /// Plots the average (or marginal) tax rate of single and married
+    /// taxpayers in a canton, or of the federal tax for "CH". With a
+    /// reference income, the rates of a single person at this income and of a
+    /// married couple at twice this income are highlighted.
+    pub fn plot_rates(
+        &self,
+        canvas: HtmlCanvasElement,
+        year: u32,
+        canton: &str,
+        max_income: f64,
+        marginal: bool,
+        reference_income: Option<f64>,
+    ) -> Result<(), JsValue> {
+        let db = self.db()?;
+        let curves = [
+            ("single", CivilStatus::Single),
+            ("married", CivilStatus::Married),
+        ]
+        .into_iter()
+        .map(|(label, status)| {
+            Ok(Curve {
+                label: label.into(),
+                points: rate_curve(db, year, canton, status, max_income, marginal)?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+        let references = match reference_income {
+            Some(income) => [
+                (income, CivilStatus::Single),
+                (2.0 * income, CivilStatus::Married),
+            ]
+            .into_iter()
+            .map(|(income, status)| {
+                let tax = TaxBreakdown::compute(db, year, canton, status, income)?;
+                Ok((income, tax.rate(marginal)))
+            })
+            .collect::<Result<Vec<_>, String>>()?,
+            None => Vec::new(),
+        };
+        plot_rate_curves(
+            canvas,
+            &format!("Income tax for {canton} in {year}"),
+            max_income,
+            &curves,
+            &references,
+        )
+    }
+
+    /// Plots the average (or marginal) tax rate in each of the given cantons.
+    pub fn plot_cantons(
+        &self,
+        canvas: HtmlCanvasElement,
+        year: u32,
+        cantons: Vec<String>,
+        status: CivilStatus,
+        max_income: f64,
+        marginal: bool,
+    ) -> Result<(), JsValue> {
+        let db = self.db()?;
+        let curves = cantons
+            .into_iter()
+            .map(|canton| {
+                let points = rate_curve(db, year, &canton, status, max_income, marginal)?;
+                Ok(Curve {
+                    label: canton,
+                    points,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let status = match status {
+            CivilStatus::Single => "Single",
+            CivilStatus::Married => "Married",
+        };
+        plot_rate_curves(
+            canvas,
+            &format!("{status} income tax in {year}"),
+            max_income,
+            &curves,
+            &[],
+        )
+    }
+}
+
+/// Returns the scales of which the marriage penalty is plotted in a canton,
+/// or of the federal tax for "CH".
+fn diff_scales<'a>(
+    db: &'a Database,
+    year: u32,
+    canton: &str,
+    layer: TaxLayer,
+    municipality: Option<&str>,
+) -> Result<Vec<DiffScale<'a>>, String> {
+    // Communal taxes are a multiple of the cantonal simple tax.
+    let scale = |zone: &str, rate: Option<f64>| -> Result<DiffScale<'a>, String> {
+        let (cantonal_rate, scale) = db.income_scale(year, zone)?;
+        Ok(DiffScale {
+            rate: rate.unwrap_or(cantonal_rate),
+            splitting: scale.splitting,
+            table_single: db.table(scale.single_index)?,
+            table_married: db.table(scale.married_index)?,
+        })
+    };
+    let communal = || {
+        let rate = match municipality {
+            Some(name) => db.municipality(year, canton, name)?.income_rate,
+            None => db.median_communal_rate(year, canton)?,
+        };
+        scale(canton, Some(rate))
+    };
+    match layer {
+        TaxLayer::Federal => Ok(vec![scale("CH", None)?]),
+        TaxLayer::Cantonal => Ok(vec![scale(canton, None)?]),
+        TaxLayer::Communal => Ok(vec![communal()?]),
+        TaxLayer::Total if canton == "CH" => Err("The total taxes need a canton".into()),
+        TaxLayer::Total => Ok(vec![scale("CH", None)?, scale(canton, None)?, communal()?]),
+    }
+}
+
+/// Returns the label of the given taxes in a canton, as in the layered plots of
+/// the analysis tool.
+fn layer_label(
+    db: &Database,
+    year: u32,
+    canton: &str,
+    layer: TaxLayer,
+    municipality: Option<&str>,
+) -> Result<String, String> {
+    Ok(match layer {
+        TaxLayer::Federal => "Federal".into(),
+        TaxLayer::Cantonal => format!("Cantonal ({}%)", db.income_scale(year, canton)?.0),
+        TaxLayer::Communal => match municipality {
+            Some(name) => {
+                let municipality = db.municipality(year, canton, name)?;
+                format!(
+                    "Communal ({} {}%)",
+                    municipality.name, municipality.income_rate
+                )
+            }
+            None => format!(
+                "Communal (median {}%)",
+                db.median_communal_rate(year, canton)?
+            ),
+        },
+        TaxLayer::Total => "Total".into(),
+    })
+}
+
+impl State {
+    fn with_db(db: Option<Database>) -> Self {
+        Self {
+            db,
+            heatmap: None,
+            colors: ColorScale::new(ColorPreset::Classic, false, ColorRange::Fixed),
+            percent_colors: ColorScale::new(ColorPreset::Classic, true, ColorRange::Fixed),
+            contours: Vec::new(),
+            percent_contours: Vec::new(),
+            layer: TaxLayer::Cantonal,
+            municipality: None,
+        }
+    }
+
+    fn db(&self) -> Result<&Database, JsValue> {
+        self.db.as_ref().ok_or_else(|| "Failed to load data".into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testdata::{YEAR, make_database};
+
+    #[test]
+    fn diff_grid() {
+        let mut state = State::with_db(Some(make_database()));
+        let cantonal = [1_000.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1_000.0, 1_000.0];
+        assert_eq!(
+            state.diff_grid(YEAR, "ZH", 4, 2, 40_000, false).unwrap(),
+            cantonal
+        );
+        assert_eq!(
+            state.diff_grid(YEAR, "ZH", 4, 2, 40_000, true).unwrap(),
+            [5.0, 0.0, 0.0, 0.0, 0.0, 0.0, 5.0, (100.0 / 30.0) as f32]
+        );
+
+        // The federal, cantonal and communal taxes share the scale, with a
+        // communal multiplier of 50%.
+        state.set_layer(TaxLayer::Total, Some("Zürich".into()));
+        assert_eq!(
+            state.diff_grid(YEAR, "ZH", 4, 2, 40_000, false).unwrap(),
+            cantonal.map(|value| 2.5 * value)
+        );
+
+        let scales = |layer, municipality| {
+            diff_scales(state.db().unwrap(), YEAR, "ZH", layer, municipality).map(|s| s.len())
+        };
+        assert_eq!(scales(TaxLayer::Communal, Some("261")), Ok(1));
+        assert_eq!(scales(TaxLayer::Communal, None), Ok(1));
+        assert_eq!(
+            layer_label(state.db().unwrap(), YEAR, "ZH", TaxLayer::Communal, None),
+            Ok("Communal (median 50%)".into())
+        );
+        assert!(scales(TaxLayer::Communal, Some("Winterthur")).is_err());
+        assert!(diff_scales(state.db().unwrap(), YEAR, "VD", TaxLayer::Cantonal, None).is_err());
+    }
+
+    #[test]
+    fn cached_grid() {
+        let heatmap = Heatmap {
+            year: YEAR,
+            canton: "ZH".into(),
+            layer: TaxLayer::Cantonal,
+            municipality: None,
+            layout: HeatmapLayout::default(),
+            grid: HeatmapGrid::default(),
+        };
+        assert!(heatmap.matches(YEAR, "ZH", TaxLayer::Cantonal, None));
+        assert!(!heatmap.matches(YEAR, "VD", TaxLayer::Cantonal, None));
+        assert!(!heatmap.matches(YEAR - 1, "ZH", TaxLayer::Cantonal, None));
+        assert!(!heatmap.matches(YEAR, "ZH", TaxLayer::Communal, Some("Zürich")));
     }
 }
@@ -1,18 +1,160 @@
 use crate::table::Table;
+use plotters::element::DashedPathElement;
 use plotters::prelude::*;
 use plotters_canvas::CanvasBackend;
-use wasm_bindgen::JsValue;
-use web_sys::{HtmlCanvasElement, console};
+use std::ops::Range;
+use taxes_common::color::{ColorScale, colorize_values, value_range};
+use taxes_common::contour::{contour_label, contours};
+use wasm_bindgen::{Clamped, JsCast, JsValue};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData, console};
 
+/// Position of the heatmap on the canvas, in pixels.
+#[cfg_attr(test, derive(Default))]
+pub struct HeatmapLayout {
+    max_salary: i32,
+    range_x: Range<i32>,
+    range_y: Range<i32>,
+}
+
+impl HeatmapLayout {
+    /// Returns the width and height of the heatmap, in pixels.
+    fn size(&self) -> (usize, usize) {
+        (
+            (self.range_x.end - self.range_x.start) as usize,
+            (self.range_y.end - self.range_y.start) as usize,
+        )
+    }
+
+    /// Returns the incomes of both partners at the given canvas pixel, or
+    /// `None` outside of the heatmap.
+    pub fn incomes(&self, x: i32, y: i32) -> Option<(f64, f64)> {
+        if !self.range_x.contains(&x) || !self.range_y.contains(&y) {
+            return None;
+        }
+        let (width, height) = self.size();
+        let i = (x - self.range_x.start) as usize;
+        let j = (self.range_y.end - 1 - y) as usize;
+        Some((
+            salary(self.max_salary, i, width),
+            salary(self.max_salary, j, height),
+        ))
+    }
+}
+
+/// Returns the income at the given pixel index along an axis of the heatmap.
+fn salary(max_salary: i32, index: usize, len: usize) -> f64 {
+    (max_salary as f64 * index as f64) / len as f64
+}
+
+/// Scale of one of the taxes of which the marriage penalty is plotted, with
+/// its multiplier.
+pub struct DiffScale<'a> {
+    pub rate: f64,
+    pub splitting: f64,
+    pub table_single: &'a Table,
+    pub table_married: &'a Table,
+}
+
+impl DiffScale<'_> {
+    pub fn tax_single(&self, income: f64) -> f64 {
+        self.table_single.eval(income) * self.rate / 100.0
+    }
+
+    pub fn tax_married(&self, income: f64) -> f64 {
+        self.table_married.eval_split(income, self.splitting) * self.rate / 100.0
+    }
+
+    /// Returns the taxes saved by a couple with the given incomes when
+    /// married, negative for a marriage penalty.
+    fn get_diff(&self, x: f64, y: f64) -> f64 {
+        self.tax_single(x) + self.tax_single(y) - self.tax_married(x + y)
+    }
+}
+
+/// Tax differences in CHF at each pixel of the heatmap. It's cached so that
+/// switching between absolute and relative differences only recolours it.
+#[cfg_attr(test, derive(Default))]
+pub struct HeatmapGrid {
+    max_salary: i32,
+    width: usize,
+    height: usize,
+    /// Rows from the top of the heatmap, i.e. from the highest income of the
+    /// second partner.
+    diffs: Vec<f64>,
+}
+
+impl HeatmapGrid {
+    /// Computes the total marriage penalty of the given scales.
+    pub fn new(max_salary: i32, width: usize, height: usize, scales: &[DiffScale]) -> Self {
+        let mut diffs = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let y = salary(max_salary, height - row - 1, height);
+            for i in 0..width {
+                let x = salary(max_salary, i, width);
+                diffs.push(scales.iter().map(|scale| scale.get_diff(x, y)).sum());
+            }
+        }
+        Self {
+            max_salary,
+            width,
+            height,
+            diffs,
+        }
+    }
+
+    fn matches(&self, layout: &HeatmapLayout) -> bool {
+        self.max_salary == layout.max_salary && (self.width, self.height) == layout.size()
+    }
+
+    /// Returns the differences in CHF, or in percent of the combined income,
+    /// row by row from the top.
+    pub fn values(&self, percent: bool) -> impl Iterator<Item = f64> + '_ {
+        self.diffs.iter().enumerate().map(move |(k, &diff)| {
+            if !percent {
+                return diff;
+            }
+            let (i, row) = (k % self.width, k / self.width);
+            let denom = salary(self.max_salary, i, self.width)
+                + salary(self.max_salary, self.height - row - 1, self.height);
+            if denom == 0.0 {
+                0.0
+            } else {
+                100.0 * diff / denom
+            }
+        })
+    }
+}
+
+/// Logs the pixels of a heatmap of the given width where the difference is
+/// NaN.
+pub fn report_nan(values: &[f64], width: usize) {
+    for (k, value) in values.iter().enumerate() {
+        if value.is_nan() {
+            let (i, row) = (k % width, k / width);
+            console::error_1(&JsValue::from_str(&format!(
+                "NaN in the heatmap at pixel ({i}, {row})"
+            )));
+        }
+    }
+}
+
+/// Plots the total marriage penalty of the given scales as a heatmap, reusing
+/// the cached grid if it has the same layout, with labelled contour lines at
+/// zero and at both signs of the given levels. Returns the layout of the
+/// heatmap, and its grid unless the cached one was reused.
 pub fn plot_income_tax_diff(
     canvas: HtmlCanvasElement,
     max_salary: i32,
-    cantonal_rate: f64,
-    splitting: f64,
-    table_single: &Table,
-    table_married: &Table,
+    scales: &[DiffScale],
     percent: bool,
-) -> Result<(), JsValue> {
+    colors: &ColorScale,
+    levels: &[f64],
+    cached: Option<&HeatmapGrid>,
+) -> Result<(HeatmapLayout, Option<HeatmapGrid>), JsValue> {
+    let context = canvas
+        .get_context("2d")?
+        .ok_or("Canvas has no 2D context")?
+        .dyn_into::<CanvasRenderingContext2d>()?;
     let backend = CanvasBackend::with_canvas_object(canvas).ok_or("Failed to create backend")?;
 
     let root = backend.into_drawing_area();
@@ -39,67 +181,37 @@ pub fn plot_income_tax_diff(
         .draw()
         .map_err(|e| format!("Failed to draw mesh: {e:?}"))?;
 
-    let plotting_area = chart.plotting_area().strip_coord_spec();
-
-    let (range_x, range_y) = plotting_area.get_pixel_range();
-    let x_len = range_x.end - range_x.start;
-    let y_len = range_y.end - range_y.start;
-
-    let (min, max) = if percent {
-        let mut min: f64 = -0.1;
-        let mut max: f64 = 0.1;
-        for i in 0..x_len {
-            let x = (max_salary as f64 * i as f64) / x_len as f64;
-            for j in 0..y_len {
-                let y = (max_salary as f64 * j as f64) / y_len as f64;
-
-                let denom = x + y;
-                let diff = if denom == 0.0 {
-                    0.0
-                } else {
-                    100.0 * get_diff(x, y, cantonal_rate, splitting, table_single, table_married)
-                        / denom
-                };
-                if diff.is_nan() {
-                    console::error_1(&JsValue::from_str(&format!(
-                        "NaN in get_diff({x}, {y}, {cantonal_rate}, {splitting}): diff={diff}"
-                    )));
-                } else {
-                    min = min.min(diff);
-                    max = max.max(diff);
-                }
-
-                plotting_area
-                    .draw_pixel((i, y_len - j - 1), &colorize_percent(diff))
-                    .map_err(|e| format!("Failed to draw pixel: {e:?}"))?;
-            }
+    let (range_x, range_y) = chart.plotting_area().get_pixel_range();
+    let layout = HeatmapLayout {
+        max_salary,
+        range_x,
+        range_y,
+    };
+    let mut computed = None;
+    let grid = match cached {
+        Some(grid) if grid.matches(&layout) => grid,
+        _ => {
+            let (width, height) = layout.size();
+            &*computed.insert(HeatmapGrid::new(max_salary, width, height, scales))
         }
-        (min, max)
+    };
+    let values: Vec<f64> = grid.values(percent).collect();
+    report_nan(&values, grid.width);
+    let (min, max) = if percent {
+        value_range(&values, -0.1, 0.1)
     } else {
-        let mut min: f64 = -10.0;
-        let mut max: f64 = 10.0;
-        for i in 0..x_len {
-            let x = (max_salary as f64 * i as f64) / x_len as f64;
-            for j in 0..y_len {
-                let y = (max_salary as f64 * j as f64) / y_len as f64;
-
-                let diff = get_diff(x, y, cantonal_rate, splitting, table_single, table_married);
-                if diff.is_nan() {
-                    console::error_1(&JsValue::from_str(&format!(
-                        "NaN in get_diff({x}, {y}, {cantonal_rate}, {splitting}): diff={diff}"
-                    )));
-                } else {
-                    min = min.min(diff);
-                    max = max.max(diff);
-                }
-
-                plotting_area
-                    .draw_pixel((i, y_len - j - 1), &colorize(diff))
-                    .map_err(|e| format!("Failed to draw pixel: {e:?}"))?;
-            }
-        }
-        (min, max)
+        value_range(&values, -10.0, 10.0)
     };
+    let (colors, min, max) = colors.fit(min, max);
+    let pixels = colorize_values(&values, &colors);
+    put_pixels(
+        &context,
+        &pixels,
+        grid.width,
+        layout.range_x.start,
+        layout.range_y.start,
+    )?;
+    draw_contours(&context, &layout, &values, levels, percent)?;
 
     let vertical_margin = height / 5;
     let mut builder = ChartBuilder::on(&legend_area);
@@ -111,7 +223,7 @@ pub fn plot_income_tax_diff(
         .y_label_area_size(30)
         .x_label_area_size(0);
 
-    if percent {
+    let (range_x, range_y) = if percent {
         let precision = if max - min >= 0.8 { 1 } else { 2 };
         let mut legend = builder
             .build_cartesian_2d(0..100, min..max)
@@ -124,20 +236,7 @@ pub fn plot_income_tax_diff(
             .label_style(("sans-serif", 22))
             .draw()
             .map_err(|e| format!("Failed to draw mesh: {e:?}"))?;
-        let plotting_area = legend.plotting_area().strip_coord_spec();
-
-        let (range_x, range_y) = plotting_area.get_pixel_range();
-        let x_len = range_x.end - range_x.start;
-        let y_len = range_y.end - range_y.start;
-
-        for j in 0..y_len {
-            let percent = (max - min) * j as f64 / y_len as f64 + min;
-            for i in 0..x_len {
-                plotting_area
-                    .draw_pixel((i, y_len - j - 1), &colorize_percent(percent))
-                    .map_err(|e| format!("Failed to draw pixel: {e:?}"))?;
-            }
-        }
+        legend.plotting_area().get_pixel_range()
     } else {
         let mut legend = builder
             .build_cartesian_2d(0..100, min.round() as i32..max.round() as i32)
@@ -149,121 +248,213 @@ pub fn plot_income_tax_diff(
             .label_style(("sans-serif", 22))
             .draw()
             .map_err(|e| format!("Failed to draw mesh: {e:?}"))?;
-        let plotting_area = legend.plotting_area().strip_coord_spec();
-
-        let (range_x, range_y) = plotting_area.get_pixel_range();
-        let x_len = range_x.end - range_x.start;
-        let y_len = range_y.end - range_y.start;
-
-        for j in 0..y_len {
-            let salary = (max - min) * j as f64 / y_len as f64 + min;
-            for i in 0..x_len {
-                plotting_area
-                    .draw_pixel((i, y_len - j - 1), &colorize(salary))
-                    .map_err(|e| format!("Failed to draw pixel: {e:?}"))?;
-            }
-        }
+        legend.plotting_area().get_pixel_range()
     };
 
+    // Draw the color scale of the legend, from the top.
+    let x_len = (range_x.end - range_x.start) as usize;
+    let y_len = (range_y.end - range_y.start) as usize;
+    let mut pixels = Vec::with_capacity(4 * x_len * y_len);
+    for row in 0..y_len {
+        let value = (max - min) * (y_len - row - 1) as f64 / y_len as f64 + min;
+        let color = colors.color(value);
+        for _ in 0..x_len {
+            pixels.extend_from_slice(&[color.0, color.1, color.2, 0xff]);
+        }
+    }
+    put_pixels(&context, &pixels, x_len, range_x.start, range_y.start)?;
+
     root.present()
         .map_err(|e| format!("Failed to present chart: {e:?}"))?;
-    Ok(())
+    Ok((layout, computed))
 }
 
-fn get_diff(
-    x: f64,
-    y: f64,
-    cantonal_rate: f64,
-    splitting: f64,
-    table_single: &Table,
-    table_married: &Table,
-) -> f64 {
-    let tax_married = table_married.eval_split(x + y, splitting);
-    let tax_singles = table_single.eval(x) + table_single.eval(y);
-    (tax_singles - tax_married) * cantonal_rate / 100.0
+/// Draws the labelled contour lines of the differences of the heatmap, given
+/// row by row from the top, at zero and at both signs of the given levels.
+fn draw_contours(
+    context: &CanvasRenderingContext2d,
+    layout: &HeatmapLayout,
+    values: &[f64],
+    levels: &[f64],
+    percent: bool,
+) -> Result<(), JsValue> {
+    let (width, height) = layout.size();
+    let levels: Vec<f64> = std::iter::once(0.0)
+        .chain(levels.iter().flat_map(|&level| [-level, level]))
+        .collect();
+    // Grid coordinates are from the bottom left, at the center of pixels.
+    let to_canvas = |(x, y): (f64, f64)| {
+        (
+            layout.range_x.start as f64 + x + 0.5,
+            layout.range_y.end as f64 - y - 0.5,
+        )
+    };
+
+    context.set_fill_style_str("black");
+    context.set_font("16px sans-serif");
+    for contour in contours(
+        width,
+        height,
+        |i, j| values[(height - 1 - j) * width + i],
+        &levels,
+    ) {
+        if contour.level == 0.0 {
+            context.set_stroke_style_str("black");
+            context.set_line_width(2.0);
+        } else {
+            context.set_stroke_style_str("rgba(0, 0, 0, 0.6)");
+            context.set_line_width(1.0);
+        }
+        context.begin_path();
+        for segment in &contour.segments {
+            let [(x0, y0), (x1, y1)] = segment.map(to_canvas);
+            context.move_to(x0, y0);
+            context.line_to(x1, y1);
+        }
+        context.stroke();
+        if let Some(position) = contour.label_position() {
+            let (x, y) = to_canvas(position);
+            context.fill_text(&contour_label(contour.level, percent), x, y)?;
+        }
+    }
+    Ok(())
 }
 
-fn colorize(diff: f64) -> RGBColor {
-    let c0 = RGBColor(0xc0, 0xc0, 0xc0);
-    let m10 = RGBColor(0xc0, 0xa0, 0xa0);
-    let m3000 = RGBColor(0xe0, 0xa0, 0x00);
-    let m8000 = RGBColor(0xc0, 0x40, 0x40);
-    let m12000 = RGBColor(0xa0, 0x60, 0x80);
-    let p10 = RGBColor(0x80, 0xa0, 0xc0);
-    let p3000 = RGBColor(0x20, 0xa0, 0xa0);
-    let p6000 = RGBColor(0x50, 0xa0, 0x60);
-    let p9000 = RGBColor(0x40, 0xc0, 0x00);
-    let p12000 = RGBColor(0x80, 0xc0, 0x00);
-
-    if (-10.0..=10.0).contains(&diff) {
-        c0
-    } else if (-3000.0..=-10.0).contains(&diff) {
-        interpolate(m10, m3000, -10.0, -3000.0, diff)
-    } else if (-8000.0..=-3000.0).contains(&diff) {
-        interpolate(m3000, m8000, -3000.0, -8000.0, diff)
-    } else if diff < 0.0 {
-        interpolate(m8000, m12000, -8000.0, -12000.0, diff)
-    } else if (10.0..=3000.0).contains(&diff) {
-        interpolate(p10, p3000, 10.0, 3000.0, diff)
-    } else if (3000.0..=6000.0).contains(&diff) {
-        interpolate(p3000, p6000, 3000.0, 6000.0, diff)
-    } else if (6000.0..=9000.0).contains(&diff) {
-        interpolate(p6000, p9000, 6000.0, 9000.0, diff)
-    } else if diff > 0.0 {
-        interpolate(p9000, p12000, 9000.0, 12000.0, diff)
-    } else {
-        console::error_1(&JsValue::from_str(&format!("NaN in colorize: diff={diff}")));
-        RGBColor(0, 0, 0)
+/// Copies RGBA pixels to the canvas in one call, at the given position.
+fn put_pixels(
+    context: &CanvasRenderingContext2d,
+    pixels: &[u8],
+    width: usize,
+    x: i32,
+    y: i32,
+) -> Result<(), JsValue> {
+    if width == 0 || pixels.is_empty() {
+        return Ok(());
     }
+    let image = ImageData::new_with_u8_clamped_array(Clamped(pixels), width as u32)?;
+    context.put_image_data(&image, x as f64, y as f64)
 }
 
-fn colorize_percent(diff: f64) -> RGBColor {
-    let c0 = RGBColor(0xc0, 0xc0, 0xc0);
-    let m025 = RGBColor(0xc0, 0xa0, 0xa0);
-    let m1 = RGBColor(0xe0, 0xa0, 0x00);
-    let m3 = RGBColor(0xc0, 0x40, 0x40);
-    let m5 = RGBColor(0xa0, 0x60, 0x80);
-    let p025 = RGBColor(0x80, 0xa0, 0xc0);
-    let p1 = RGBColor(0x20, 0xa0, 0xa0);
-    let p3 = RGBColor(0x50, 0xa0, 0x60);
-    let p5 = RGBColor(0x40, 0xc0, 0x00);
-    let p8 = RGBColor(0x80, 0xc0, 0x00);
-
-    if (-0.025..=0.025).contains(&diff) {
-        c0
-    } else if (-1.0..=-0.025).contains(&diff) {
-        interpolate(m025, m1, -0.025, -1.0, diff)
-    } else if (-3.0..=-1.0).contains(&diff) {
-        interpolate(m1, m3, -1.0, -3.0, diff)
-    } else if diff < 0.0 {
-        interpolate(m3, m5, -3.0, -5.0, diff)
-    } else if (0.025..=1.0).contains(&diff) {
-        interpolate(p025, p1, 0.025, 1.0, diff)
-    } else if (1.0..=3.0).contains(&diff) {
-        interpolate(p1, p3, 1.0, 3.0, diff)
-    } else if (3.0..=5.0).contains(&diff) {
-        interpolate(p3, p5, 3.0, 5.0, diff)
-    } else if diff > 0.0 {
-        interpolate(p5, p8, 5.0, 8.0, diff)
-    } else {
-        console::error_1(&JsValue::from_str(&format!(
-            "NaN in colorize_percent: diff={diff}"
-        )));
-        RGBColor(0, 0, 0)
+/// Labelled curve of a line chart.
+pub struct Curve {
+    pub label: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Plots curves of tax rates, in percent, as a function of the income. Each
+/// reference point is highlighted with dashed lines to the axes.
+pub fn plot_rate_curves(
+    canvas: HtmlCanvasElement,
+    caption: &str,
+    max_income: f64,
+    curves: &[Curve],
+    references: &[(f64, f64)],
+) -> Result<(), JsValue> {
+    let backend = CanvasBackend::with_canvas_object(canvas).ok_or("Failed to create backend")?;
+
+    let root = backend.into_drawing_area();
+    root.fill(&WHITE)
+        .map_err(|e| format!("Failed to clear background: {e:?}"))?;
+
+    // Round the maximal rate up to a multiple of 5%.
+    let max_rate = curves
+        .iter()
+        .flat_map(|curve| curve.points.iter().map(|&(_, rate)| rate))
+        .fold(5.0, f64::max);
+    let max_rate = (max_rate / 5.0).ceil() * 5.0;
+
+    let mut chart = ChartBuilder::on(&root)
+        .x_label_area_size(50)
+        .y_label_area_size(60)
+        .margin(10)
+        .margin_right(40)
+        .caption(caption, ("sans-serif", 26))
+        .build_cartesian_2d(0.0..max_income, 0.0..max_rate)
+        .map_err(|e| format!("Failed to create chart: {e:?}"))?;
+
+    chart
+        .configure_mesh()
+        .label_style(("sans-serif", 20))
+        .x_desc("Taxable income")
+        .y_desc("Income tax rate")
+        .x_labels(10)
+        .x_label_formatter(&|income| format!("{income:.0}"))
+        .y_label_formatter(&|percent| format!("{percent:.0}%"))
+        .draw()
+        .map_err(|e| format!("Failed to draw mesh: {e:?}"))?;
+
+    for (i, curve) in curves.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(
+                curve.points.iter().copied(),
+                color.stroke_width(2),
+            ))
+            .map_err(|e| format!("Failed to draw curve: {e:?}"))?
+            .label(&curve.label)
+            .legend(move |(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+            });
     }
+
+    for &(x, y) in references {
+        chart
+            .plotting_area()
+            .draw(&DashedPathElement::new(
+                [(0.0, y), (x, y), (x, 0.0)],
+                8,
+                5,
+                BLACK,
+            ))
+            .map_err(|e| format!("Failed to draw reference: {e:?}"))?;
+    }
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::LowerRight)
+        .border_style(BLACK)
+        .background_style(WHITE.filled())
+        .label_font(("sans-serif", 20))
+        .draw()
+        .map_err(|e| format!("Failed to draw legend: {e:?}"))?;
+
+    root.present()
+        .map_err(|e| format!("Failed to present chart: {e:?}"))?;
+    Ok(())
 }
 
-fn interpolate(color1: RGBColor, color2: RGBColor, start: f64, end: f64, value: f64) -> RGBColor {
-    let x = (value - start) / (end - start);
-    if x <= 0.0 {
-        color1
-    } else if x >= 1.0 {
-        color2
-    } else {
-        RGBColor(
-            (color1.0 as f64 * (1.0 - x) + color2.0 as f64 * x) as u8,
-            (color1.1 as f64 * (1.0 - x) + color2.1 as f64 * x) as u8,
-            (color1.2 as f64 * (1.0 - x) + color2.2 as f64 * x) as u8,
-        )
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn heatmap_grid() {
+        let table = Table::zuerich(&[(10_000.0, 0.0), (1e12, 10.0)]);
+        let scale = DiffScale {
+            rate: 100.0,
+            splitting: 2.0,
+            table_single: &table,
+            table_married: &table,
+        };
+        // Incomes of 0 to 30'000 CHF for the first partner, and 20'000 then 0
+        // CHF for the second one.
+        let grid = HeatmapGrid::new(40_000, 4, 2, &[scale]);
+        assert_eq!(
+            grid.values(false).collect::<Vec<_>>(),
+            [1_000.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1_000.0, 1_000.0]
+        );
+        assert_eq!(
+            grid.values(true).collect::<Vec<_>>(),
+            [5.0, 0.0, 0.0, 0.0, 0.0, 0.0, 5.0, 100.0 / 30.0]
+        );
+
+        let layout = |max_salary, width| HeatmapLayout {
+            max_salary,
+            range_x: 80..80 + width,
+            range_y: 50..52,
+        };
+        assert!(grid.matches(&layout(40_000, 4)));
+        assert!(!grid.matches(&layout(50_000, 4)));
+        assert!(!grid.matches(&layout(40_000, 5)));
     }
 }
@@ -1,5 +1,8 @@
+use crate::compute::CivilStatus;
 use serde::Deserialize;
-use std::borrow::Borrow;
+use serde::de::DeserializeOwned;
+use std::borrow::{Borrow, Cow};
+use taxes_common::compact::{Deltas, Number, zip_columns};
 
 #[derive(Deserialize)]
 pub struct SortedMap<K, V>(Vec<(K, V)>);
@@ -15,44 +18,534 @@ impl<K, V> SortedMap<K, V> {
             .ok()
             .map(|i| &self.0[i].1)
     }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.0.iter().map(|(k, _)| k)
+    }
+}
+
+#[cfg(test)]
+impl<K: Ord, V> FromIterator<(K, V)> for SortedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries: Vec<(K, V)> = iter.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        SortedMap(entries)
+    }
+}
+
+/// Magic bytes at the start of `tables.db`.
+const MAGIC: [u8; 8] = *b"CHTAXDB\0";
+/// Latest supported version of the `tables.db` format.
+const VERSION: u32 = 3;
+
+/// Metadata of the database, stored before it in `tables.db`.
+#[derive(Default, Deserialize)]
+pub struct Header {
+    /// Date when the input data was fetched, in seconds since the Unix epoch.
+    pub source_date: u64,
+    pub years: Vec<u32>,
+    pub cantons: Vec<String>,
+    pub encoding: Encoding,
+    checksum: u32,
+}
+
+#[derive(Default, Deserialize)]
+pub struct Encoding {
+    /// Whether tables and rates use the compact encoding.
+    pub compact: bool,
+    pub compression: Compression,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
 }
 
 #[derive(Deserialize)]
 pub struct Database {
+    #[serde(skip)]
+    pub header: Header,
     pub arena_scale: Vec<CantonalScale>,
     pub arena_table: Vec<Table>,
+    pub arena_municipality: Vec<Municipality>,
     pub db: SortedMap<u32, Year>,
 }
 
 impl Database {
+    /// Loads the database embedded in the module.
+    #[cfg(feature = "embed")]
     pub fn load() -> Result<Self, String> {
         const DATA: &[u8] = include_bytes!("../data/tables.db");
-        match postcard::from_bytes::<Self>(DATA) {
-            Ok(db) => {
-                if !db.db.0.is_sorted_by_key(|(year, _)| year) {
-                    return Err("Table isn't sorted by year".into());
-                }
+        Self::parse(DATA)
+    }
+
+    /// Parses the contents of a `tables.db` file, after checking its header.
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let (magic, data) = data
+            .split_first_chunk::<8>()
+            .ok_or("Table is too short to contain a header")?;
+        if *magic != MAGIC {
+            return Err(
+                "Table has no valid header, it may have been built by an older version".into(),
+            );
+        }
+        let (version, data) = data
+            .split_first_chunk::<4>()
+            .ok_or("Table is too short to contain a header")?;
+
+        match u32::from_le_bytes(*version) {
+            // When the format changes, bump the version and decode the older
+            // layouts here into the current one.
+            VERSION => Self::parse_current(data),
+            2 => Self::parse_v2(data),
+            1 => Self::parse_v1(data),
+            version if version > VERSION => Err(format!(
+                "Table format version {version} is newer than the supported version {VERSION}"
+            )),
+            version => Err(format!(
+                "Table format version {version} is no longer supported, please rebuild it"
+            )),
+        }
+    }
+
+    fn parse_current(data: &[u8]) -> Result<Self, String> {
+        let (header, body) = Self::parse_header::<Header>(data)?;
+        let body = match header.encoding.compression {
+            Compression::None => Cow::Borrowed(body),
+            Compression::Deflate => Cow::Owned(
+                miniz_oxide::inflate::decompress_to_vec(body)
+                    .map_err(|e| format!("Failed to decompress table: {e:?}"))?,
+            ),
+        };
+        let db = if header.encoding.compact {
+            postcard::from_bytes::<CompactDatabase>(&body)
+                .map_err(|e| format!("Failed to parse table: {e:?}"))?
+                .try_into()?
+        } else {
+            postcard::from_bytes::<Self>(&body)
+                .map_err(|e| format!("Failed to parse table: {e:?}"))?
+        };
+        db.validate(header)
+    }
+
+    /// Version 2 had the same layout, without encoding options.
+    fn parse_v2(data: &[u8]) -> Result<Self, String> {
+        let (header, body) = Self::parse_header::<v2::Header>(data)?;
+        let db = postcard::from_bytes::<Self>(body)
+            .map_err(|e| format!("Failed to parse table: {e:?}"))?;
+        db.validate(header)
+    }
+
+    /// Version 1 contained neither fortune scales nor municipalities, and
+    /// stored the federal scale as the "CH" canton.
+    fn parse_v1(data: &[u8]) -> Result<Self, String> {
+        let (header, body) = Self::parse_header::<v2::Header>(data)?;
+        let db = postcard::from_bytes::<v1::Database>(body)
+            .map_err(|e| format!("Failed to parse table: {e:?}"))?;
+
+        let mut years = Vec::with_capacity(db.db.0.len());
+        for (year, cantons) in db.db.0 {
+            let federal = cantons
+                .0
+                .get("CH")
+                .ok_or_else(|| format!("No federal scale for year {year}"))?
+                .scale_index;
+            let cantons = cantons
+                .0
+                .0
+                .into_iter()
+                .filter(|(canton, _)| canton != "CH")
+                .map(|(canton, base)| {
+                    let base = CantonalBase {
+                        rate: base.rate,
+                        scale_index: base.scale_index,
+                        fortune_rate: 0.0,
+                        fortune_scale_index: None,
+                        municipalities: Vec::new(),
+                    };
+                    (canton, base)
+                })
+                .collect();
+            years.push((
+                year,
+                Year {
+                    federal_index: federal,
+                    cantons: SortedMap(cantons),
+                },
+            ));
+        }
+
+        let db = Database {
+            header: Header::default(),
+            arena_scale: db.arena_scale,
+            arena_table: db.arena_table,
+            arena_municipality: Vec::new(),
+            db: SortedMap(years),
+        };
+        db.validate(header)
+    }
+
+    fn parse_header<H: DeserializeOwned + Into<Header>>(
+        data: &[u8],
+    ) -> Result<(Header, &[u8]), String> {
+        let (header, body) = postcard::take_from_bytes::<H>(data)
+            .map_err(|e| format!("Failed to parse table header: {e:?}"))?;
+        let header = header.into();
+        let checksum = crc32fast::hash(body);
+        if checksum != header.checksum {
+            return Err(format!(
+                "Table checksum mismatch: expected {:08x}, got {checksum:08x}",
+                header.checksum
+            ));
+        }
+        Ok((header, body))
+    }
 
-                for (year, table) in &db.db.0 {
-                    if !table.0.0.is_sorted_by_key(|(canton, _)| canton) {
-                        return Err(format!("Table isn't sorted by canton for year {year}"));
-                    }
+    fn validate(mut self, header: Header) -> Result<Self, String> {
+        if !self.db.0.is_sorted_by_key(|(year, _)| year) {
+            return Err("Table isn't sorted by year".into());
+        }
+
+        for (year, table) in &self.db.0 {
+            if !table.cantons.0.is_sorted_by_key(|(canton, _)| canton) {
+                return Err(format!("Table isn't sorted by canton for year {year}"));
+            }
+            arena_get(&self.arena_scale, table.federal_index, "scale")?;
+            for (_, base) in &table.cantons.0 {
+                arena_get(&self.arena_scale, base.scale_index, "scale")?;
+                if let Some(index) = base.fortune_scale_index {
+                    arena_get(&self.arena_scale, index, "scale")?;
+                }
+                for &index in &base.municipalities {
+                    arena_get(&self.arena_municipality, index, "municipality")?;
                 }
+            }
+        }
+        for scale in &self.arena_scale {
+            self.table(scale.single_index)?;
+            self.table(scale.married_index)?;
+        }
+
+        self.header = header;
+        Ok(self)
+    }
+
+    /// Returns the income tax multiplier and scale of the given canton, or of
+    /// the Confederation for "CH".
+    pub fn income_scale(&self, year: u32, zone: &str) -> Result<(f64, &CantonalScale), String> {
+        let entry = self
+            .db
+            .get(&year)
+            .ok_or_else(|| format!("Didn't find year: {year}"))?;
+        let (rate, index) = if zone == "CH" {
+            (100.0, entry.federal_index)
+        } else {
+            let base = entry
+                .cantons
+                .get(zone)
+                .ok_or_else(|| format!("Didn't find canton: {zone}"))?;
+            (base.rate, base.scale_index)
+        };
+        Ok((rate, arena_get(&self.arena_scale, index, "scale")?))
+    }
+
+    /// Returns the table at the given index of the arena.
+    pub fn table(&self, index: u32) -> Result<&Table, String> {
+        arena_get(&self.arena_table, index, "table")
+    }
+
+    /// Returns the municipalities of a canton.
+    pub fn municipalities(
+        &self,
+        year: u32,
+        canton: &str,
+    ) -> Result<impl Iterator<Item = &Municipality>, String> {
+        let base = self
+            .db
+            .get(&year)
+            .ok_or_else(|| format!("Didn't find year: {year}"))?
+            .cantons
+            .get(canton)
+            .ok_or_else(|| format!("Didn't find canton: {canton}"))?;
+        let municipalities = base
+            .municipalities
+            .iter()
+            .map(|&index| arena_get(&self.arena_municipality, index, "municipality"))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(municipalities.into_iter())
+    }
+
+    /// Returns the municipality of a canton with the given name or BFS number.
+    pub fn municipality(
+        &self,
+        year: u32,
+        canton: &str,
+        name: &str,
+    ) -> Result<&Municipality, String> {
+        let bfs_id = name.parse::<u32>().ok();
+        self.municipalities(year, canton)?
+            .find(|municipality| match bfs_id {
+                Some(bfs_id) => municipality.bfs_id == bfs_id,
+                None => municipality.name.eq_ignore_ascii_case(name),
+            })
+            .ok_or_else(|| format!("Didn't find municipality {name} in {canton}"))
+    }
+
+    /// Returns the median communal income tax multiplier of a canton, as used
+    /// by the layered plots of the analysis tool.
+    pub fn median_communal_rate(&self, year: u32, canton: &str) -> Result<f64, String> {
+        let mut rates: Vec<f64> = self
+            .municipalities(year, canton)?
+            .map(|municipality| municipality.income_rate)
+            .collect();
+        if rates.is_empty() {
+            return Err(format!("Didn't find municipalities in {canton}"));
+        }
+        rates.sort_by(f64::total_cmp);
+        Ok(rates[rates.len() / 2])
+    }
+
+    /// Evaluates the simple income tax of a scale.
+    pub fn simple_tax(
+        &self,
+        scale: &CantonalScale,
+        status: CivilStatus,
+        income: f64,
+    ) -> Result<f64, String> {
+        Ok(match status {
+            CivilStatus::Single => self.table(scale.single_index)?.eval(income),
+            CivilStatus::Married => self
+                .table(scale.married_index)?
+                .eval_split(income, scale.splitting),
+        })
+    }
+}
+
+/// Returns the entry at the given index of an arena, which may be out of
+/// bounds in a corrupted database.
+fn arena_get<'a, T>(arena: &'a [T], index: u32, kind: &str) -> Result<&'a T, String> {
+    arena
+        .get(index as usize)
+        .ok_or_else(|| format!("Invalid {kind} index {index} in table"))
+}
+
+/// Header of the versions 1 and 2 of the format, for migration.
+mod v2 {
+    use super::Encoding;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub struct Header {
+        pub source_date: u64,
+        pub years: Vec<u32>,
+        pub cantons: Vec<String>,
+        pub checksum: u32,
+    }
 
-                Ok(db)
+    impl From<Header> for super::Header {
+        fn from(header: Header) -> Self {
+            super::Header {
+                source_date: header.source_date,
+                years: header.years,
+                cantons: header.cantons,
+                encoding: Encoding::default(),
+                checksum: header.checksum,
             }
-            Err(e) => Err(format!("Failed to parse table: {e:?}")),
         }
     }
 }
 
+/// Layout of the version 1 of the format, for migration.
+mod v1 {
+    use super::{CantonalScale, SortedMap, Table};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub struct Database {
+        pub arena_scale: Vec<CantonalScale>,
+        pub arena_table: Vec<Table>,
+        pub db: SortedMap<u32, Year>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Year(pub SortedMap<String, CantonalBase>);
+
+    #[derive(Deserialize)]
+    pub struct CantonalBase {
+        pub rate: f64,
+        pub scale_index: u32,
+    }
+}
+
+/// Compact encoding of the database, where rates are [`Number`]s and tables
+/// are [`CompactTable`]s.
 #[derive(Deserialize)]
-pub struct Year(pub SortedMap<String, CantonalBase>);
+struct CompactDatabase {
+    arena_scale: Vec<CompactScale>,
+    arena_table: Vec<CompactTable>,
+    arena_municipality: Vec<CompactMunicipality>,
+    db: SortedMap<u32, CompactYear>,
+}
+
+#[derive(Deserialize)]
+struct CompactScale {
+    splitting: Number,
+    single_index: u32,
+    married_index: u32,
+}
+
+#[derive(Deserialize)]
+struct CompactYear {
+    federal_index: u32,
+    cantons: SortedMap<String, CompactCantonalBase>,
+}
+
+#[derive(Deserialize)]
+struct CompactCantonalBase {
+    rate: Number,
+    scale_index: u32,
+    fortune_rate: Number,
+    fortune_scale_index: Option<u32>,
+    municipalities: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+struct CompactMunicipality {
+    bfs_id: u32,
+    tax_location_id: u32,
+    name: String,
+    zip_code: String,
+    /// Income and fortune rates, followed by the church rates in the order of
+    /// the [`ChurchRates`] fields.
+    rates: [Number; 8],
+}
+
+impl TryFrom<CompactDatabase> for Database {
+    type Error = String;
+
+    fn try_from(compact: CompactDatabase) -> Result<Self, String> {
+        let arena_scale = compact
+            .arena_scale
+            .into_iter()
+            .map(|scale| CantonalScale {
+                splitting: scale.splitting.into(),
+                single_index: scale.single_index,
+                married_index: scale.married_index,
+            })
+            .collect();
+        let arena_table = compact
+            .arena_table
+            .into_iter()
+            .map(Table::try_from)
+            .collect::<Result<_, _>>()?;
+        let arena_municipality = compact
+            .arena_municipality
+            .into_iter()
+            .map(|municipality| {
+                let [
+                    income_rate,
+                    fortune_rate,
+                    income_protestant,
+                    income_roman_catholic,
+                    income_christ_catholic,
+                    fortune_protestant,
+                    fortune_roman_catholic,
+                    fortune_christ_catholic,
+                ] = municipality.rates.map(f64::from);
+                Municipality {
+                    bfs_id: municipality.bfs_id,
+                    tax_location_id: municipality.tax_location_id,
+                    name: municipality.name,
+                    zip_code: municipality.zip_code,
+                    income_rate,
+                    fortune_rate,
+                    church: ChurchRates {
+                        income_protestant,
+                        income_roman_catholic,
+                        income_christ_catholic,
+                        fortune_protestant,
+                        fortune_roman_catholic,
+                        fortune_christ_catholic,
+                    },
+                }
+            })
+            .collect();
+        let years = compact
+            .db
+            .0
+            .into_iter()
+            .map(|(year, entries)| {
+                let cantons = entries
+                    .cantons
+                    .0
+                    .into_iter()
+                    .map(|(canton, base)| {
+                        let base = CantonalBase {
+                            rate: base.rate.into(),
+                            scale_index: base.scale_index,
+                            fortune_rate: base.fortune_rate.into(),
+                            fortune_scale_index: base.fortune_scale_index,
+                            municipalities: base.municipalities,
+                        };
+                        (canton, base)
+                    })
+                    .collect();
+                let entries = Year {
+                    federal_index: entries.federal_index,
+                    cantons: SortedMap(cantons),
+                };
+                (year, entries)
+            })
+            .collect();
+
+        Ok(Database {
+            header: Header::default(),
+            arena_scale,
+            arena_table,
+            arena_municipality,
+            db: SortedMap(years),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Year {
+    pub federal_index: u32,
+    pub cantons: SortedMap<String, CantonalBase>,
+}
 
 #[derive(Deserialize)]
 pub struct CantonalBase {
     pub rate: f64,
     pub scale_index: u32,
+    pub fortune_rate: f64,
+    pub fortune_scale_index: Option<u32>,
+    pub municipalities: Vec<u32>,
+}
+
+/// Communal multipliers of a municipality, in percent of the simple tax.
+#[derive(Deserialize)]
+pub struct Municipality {
+    pub bfs_id: u32,
+    pub tax_location_id: u32,
+    pub name: String,
+    pub zip_code: String,
+    pub income_rate: f64,
+    pub fortune_rate: f64,
+    pub church: ChurchRates,
+}
+
+/// Church tax multipliers of each confession, in percent of the simple tax.
+#[derive(Deserialize)]
+pub struct ChurchRates {
+    pub income_protestant: f64,
+    pub income_roman_catholic: f64,
+    pub income_christ_catholic: f64,
+    pub fortune_protestant: f64,
+    pub fortune_roman_catholic: f64,
+    pub fortune_christ_catholic: f64,
 }
 
 #[derive(Deserialize)]
@@ -97,6 +590,121 @@ impl Table {
     }
 }
 
+#[cfg(test)]
+impl Table {
+    /// Table of consecutive brackets, given by their length and marginal rate
+    /// in percent, evaluated without rounding.
+    pub fn zuerich(brackets: &[(f64, f64)]) -> Self {
+        Table {
+            table: RawTable::Zuerich(TableZuerich(
+                brackets
+                    .iter()
+                    .map(|&(bracket_len, marginal_rate)| TableZuerichEntry {
+                        bracket_len,
+                        marginal_rate,
+                    })
+                    .collect(),
+            )),
+            policy: EvalPolicy::Raw,
+        }
+    }
+}
+
+/// Compact encoding of a [`Table`], with columns of [`Number`]s and
+/// delta-encoded bracket starts.
+#[derive(Deserialize)]
+struct CompactTable {
+    table: CompactRawTable,
+    policy: EvalPolicy,
+}
+
+#[derive(Deserialize)]
+enum CompactRawTable {
+    Bund {
+        bracket_starts: Deltas,
+        base_taxes: Vec<Number>,
+        marginal_rates: Vec<Number>,
+    },
+    Flattax(Number),
+    Formel {
+        bracket_starts: Deltas,
+        formulas: Vec<Formula>,
+    },
+    Freiburg {
+        bracket_starts: Deltas,
+        tax_rates: Vec<Number>,
+    },
+    Zuerich {
+        bracket_lens: Vec<Number>,
+        marginal_rates: Vec<Number>,
+    },
+}
+
+impl TryFrom<CompactTable> for Table {
+    type Error = String;
+
+    fn try_from(compact: CompactTable) -> Result<Self, String> {
+        let table = match compact.table {
+            CompactRawTable::Bund {
+                bracket_starts,
+                base_taxes,
+                marginal_rates,
+            } => {
+                let rates = zip_columns(base_taxes, marginal_rates, |base_tax, marginal_rate| {
+                    (f64::from(base_tax), f64::from(marginal_rate))
+                })?;
+                RawTable::Bund(TableBund(zip_columns(
+                    bracket_starts.decode()?,
+                    rates,
+                    |bracket_start, (base_tax, marginal_rate)| TableBundEntry {
+                        bracket_start,
+                        base_tax,
+                        marginal_rate,
+                    },
+                )?))
+            }
+            CompactRawTable::Flattax(rate) => RawTable::Flattax(TableFlattax(rate.into())),
+            CompactRawTable::Formel {
+                bracket_starts,
+                formulas,
+            } => RawTable::Formel(TableFormel(zip_columns(
+                bracket_starts.decode()?,
+                formulas,
+                |bracket_start, formula| TableFormelEntry {
+                    bracket_start,
+                    formula,
+                },
+            )?)),
+            CompactRawTable::Freiburg {
+                bracket_starts,
+                tax_rates,
+            } => RawTable::Freiburg(TableFreiburg(zip_columns(
+                bracket_starts.decode()?,
+                tax_rates,
+                |bracket_start, tax_rate| TableFreiburgEntry {
+                    bracket_start,
+                    tax_rate: tax_rate.into(),
+                },
+            )?)),
+            CompactRawTable::Zuerich {
+                bracket_lens,
+                marginal_rates,
+            } => RawTable::Zuerich(TableZuerich(zip_columns(
+                bracket_lens,
+                marginal_rates,
+                |bracket_len, marginal_rate| TableZuerichEntry {
+                    bracket_len: bracket_len.into(),
+                    marginal_rate: marginal_rate.into(),
+                },
+            )?)),
+        };
+        Ok(Table {
+            table,
+            policy: compact.policy,
+        })
+    }
+}
+
 #[derive(Deserialize)]
 pub enum EvalPolicy {
     Raw,
@@ -303,3 +911,28 @@ struct TableZuerichEntry {
     bracket_len: f64,
     marginal_rate: f64,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testdata::{YEAR, make_database};
+
+    #[test]
+    fn invalid_index() {
+        assert!(make_database().validate(Header::default()).is_ok());
+
+        let mut db = make_database();
+        db.arena_scale[0].married_index = 1;
+        assert!(db.validate(Header::default()).is_err());
+
+        let mut db = make_database();
+        db.db.0[0].1.cantons.0[0].1.municipalities.push(1);
+        assert!(db.validate(Header::default()).is_err());
+
+        // Lookups fail rather than panic on a database that skipped validation.
+        let mut db = make_database();
+        db.db.0[0].1.federal_index = 1;
+        assert!(db.income_scale(YEAR, "CH").is_err());
+        assert!(db.income_scale(YEAR, "ZH").is_ok());
+    }
+}
@@ -0,0 +1,57 @@
+use crate::table::{
+    CantonalBase, CantonalScale, ChurchRates, Database, Header, Municipality, Table, Year,
+};
+
+pub const YEAR: u32 = 2025;
+
+/// Creates a small database for [`YEAR`], where the federal tax and the canton
+/// ZH share one scale: no tax up to 10'000 CHF and 10% above, with a splitting
+/// of 2 for married couples. The only municipality of ZH, Zürich, has a
+/// multiplier of 50%.
+pub fn make_database() -> Database {
+    Database {
+        header: Header::default(),
+        arena_scale: vec![CantonalScale {
+            splitting: 2.0,
+            single_index: 0,
+            married_index: 0,
+        }],
+        arena_table: vec![Table::zuerich(&[(10_000.0, 0.0), (1e12, 10.0)])],
+        arena_municipality: vec![Municipality {
+            bfs_id: 261,
+            tax_location_id: 100,
+            name: "Zürich".into(),
+            zip_code: "8000".into(),
+            income_rate: 50.0,
+            fortune_rate: 50.0,
+            church: ChurchRates {
+                income_protestant: 0.0,
+                income_roman_catholic: 0.0,
+                income_christ_catholic: 0.0,
+                fortune_protestant: 0.0,
+                fortune_roman_catholic: 0.0,
+                fortune_christ_catholic: 0.0,
+            },
+        }],
+        db: [(
+            YEAR,
+            Year {
+                federal_index: 0,
+                cantons: [(
+                    "ZH".to_owned(),
+                    CantonalBase {
+                        rate: 100.0,
+                        scale_index: 0,
+                        fortune_rate: 100.0,
+                        fortune_scale_index: None,
+                        municipalities: vec![0],
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            },
+        )]
+        .into_iter()
+        .collect(),
+    }
+}